@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::camera::Projection;
+use ::gltf::image::Format;
+use crate::math::vec3::{Vec3, Point3, Color3};
+use crate::shapes::hittable::HittableList;
+use crate::shapes::material::Material;
+use crate::shapes::mesh::{TriangleMesh, MeshError};
+use crate::shapes::surface::AlphaMode;
+use crate::shapes::texture::{Texture, ImageTexture, srgb_to_linear};
+use crate::lights::punctual::{Light, Falloff};
+use crate::render::camera::CameraBuilder;
+use crate::render::projection::Orthographic;
+
+//Metallic-roughness materials are at least this metallic to become metals, the rest are Lambertian.
+const METALLIC_THRESHOLD : f64 = 0.5;
+
+//Column major 4x4 transform, as glTF stores them.
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY : Matrix = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+//Everything a glTF file's scene holds that can be rendered, in world space.
+pub struct GltfScene {
+    pub world : HittableList, //One triangle mesh per primitive, and the punctual lights
+    pub cameras : Vec<CameraBuilder> //One per camera node with its view, field of view and aspect ratio set, in the order the nodes are visited
+}
+
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(::gltf::Error), //The file couldn't be read, parsed or its buffers and images loaded
+    NoScene,
+    Mesh {mesh : usize, primitive : usize, error : MeshError} //A primitive's data doesn't make a valid mesh
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Gltf(error) => write!(f, "could not load glTF file: {}", error),
+            GltfError::NoScene => write!(f, "glTF file has no scene"),
+            GltfError::Mesh { mesh, primitive, error } => write!(f, "primitive {} of mesh {}: {}", primitive, mesh, error)
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Gltf(error) => Some(error),
+            GltfError::Mesh { error, .. } => Some(error),
+            GltfError::NoScene => None
+        }
+    }
+}
+
+impl From<::gltf::Error> for GltfError {
+    fn from(error : ::gltf::Error) -> Self {
+        return GltfError::Gltf(error);
+    }
+}
+
+//Loads the default scene of a .gltf or .glb file, or its first scene if none is marked as the default.
+//
+//Metallic-roughness materials become metals with the roughness as fuzz when they are mostly metallic, using the
+//average of a metallic texture, and Lambertian otherwise. Base color, roughness, normal and alpha textures are kept,
+//read with the first set of texture coordinates. Emission, extensions other than KHR_lights_punctual and primitives
+//other than triangle lists are skipped. Light intensities are used as they are, candela for point and spot lights
+//and lux for directional lights.
+pub fn load<P : AsRef<Path>>(path : P) -> Result<GltfScene, GltfError> {
+    let (document, buffers, images) = ::gltf::import(path)?;
+    let scene = document.default_scene().or_else(|| document.scenes().next()).ok_or(GltfError::NoScene)?;
+
+    let mut importer : Importer = Importer {buffers : &buffers, images : &images, materials : HashMap::new(), textures : HashMap::new()};
+    let mut result : GltfScene = GltfScene {world : HittableList::new(), cameras : Vec::new()};
+    for node in scene.nodes() {
+        importer.visit(&node, &IDENTITY, &mut result)?;
+    }
+    return Ok(result);
+}
+
+//Which part of an image a texture is made from.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Channels {
+    Color, //RGB stored in sRGB
+    Data, //RGB stored as linear values
+    Single(usize) //One channel spread over all three, for textures read as scalars
+}
+
+struct Importer<'a> {
+    buffers : &'a [::gltf::buffer::Data],
+    images : &'a [::gltf::image::Data],
+    materials : HashMap<Option<usize>, Material>, //By material index, None for the default material
+    textures : HashMap<(usize, Channels), Texture> //By image index, shared by every material using the same image
+}
+
+impl Importer<'_> {
+    fn visit(&mut self, node : &::gltf::Node, parent : &Matrix, scene : &mut GltfScene) -> Result<(), GltfError> {
+        let local : [[f32; 4]; 4] = node.transform().matrix();
+        let transform : Matrix = multiply(parent, &local.map(|column| column.map(f64::from)));
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let triangles : TriangleMesh = self.primitive(&primitive, &transform).map_err(|error| GltfError::Mesh {mesh : mesh.index(), primitive : primitive.index(), error : error})?;
+                scene.world.add(Box::new(triangles));
+            }
+        }
+        if let Some(camera) = node.camera() {
+            scene.cameras.push(camera_builder(&camera, &transform));
+        }
+        if let Some(light) = node.light() {
+            scene.world.add_light(punctual_light(&light, &transform));
+        }
+
+        for child in node.children() {
+            self.visit(&child, &transform, scene)?;
+        }
+        return Ok(());
+    }
+
+    //Triangle mesh of a primitive, moved into world space.
+    fn primitive(&mut self, primitive : &::gltf::Primitive, transform : &Matrix) -> Result<TriangleMesh, MeshError> {
+        let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|data| &data.0[..]));
+        let positions : Vec<Point3> = reader.read_positions().map_or(Vec::new(), |positions| positions.map(|p| transform_point(transform, &to_vec(p))).collect());
+
+        //Mirroring transforms turn triangles inside out, so their winding and tangent handedness are flipped back.
+        let mirrored : bool = determinant(transform) < 0.0;
+        let indices : Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect()
+        };
+        let mut builder = TriangleMesh::builder(positions, triangle_list(&indices, mirrored)).material(self.material(&primitive.material()));
+        if let Some(normals) = reader.read_normals() {
+            builder = builder.normals(normals.map(|n| transform_normal(transform, &to_vec(n)).unit_vector()).collect());
+        }
+        //glTF puts v = 0 at the top of images, textures here have it at the bottom.
+        if let Some(uvs) = reader.read_tex_coords(0) {
+            builder = builder.uvs(uvs.into_f32().map(|[u, v]| (u as f64, 1.0 - v as f64)).collect());
+        }
+        if let Some(tangents) = reader.read_tangents() {
+            let handedness : f64 = if mirrored {-1.0} else {1.0};
+            builder = builder.tangents(tangents.map(|[x, y, z, w]| (transform_vector(transform, &to_vec([x, y, z])), handedness * w as f64)).collect());
+        }
+        return builder.build();
+    }
+
+    fn material(&mut self, material : &::gltf::Material) -> Material {
+        if let Some(converted) = self.materials.get(&material.index()) {
+            return converted.clone();
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, alpha] = pbr.base_color_factor();
+        let albedo : Color3 = Color3::new(r as f64, g as f64, b as f64);
+        let roughness : f64 = pbr.roughness_factor() as f64;
+
+        //Metalness is taken over the whole material, from the average of the metallic texture if it has one.
+        let mut metallic : f64 = pbr.metallic_factor() as f64;
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            metallic *= self.channel_average(info.texture().source().index(), 2);
+        }
+        let mut converted : Material = if metallic >= METALLIC_THRESHOLD {Material::metal(albedo, roughness)} else {Material::Lambertian {albedo : albedo}};
+
+        if let Some(info) = pbr.base_color_texture() {
+            converted = converted.with_albedo_map(self.texture(info.texture().source().index(), Channels::Color));
+        }
+        if let (Some(info), Material::Metal { .. }) = (pbr.metallic_roughness_texture(), &converted) {
+            converted = converted.with_roughness_map(self.texture(info.texture().source().index(), Channels::Single(1)));
+        }
+        if let Some(normal) = material.normal_texture() {
+            converted = converted.with_normal_map(self.texture(normal.texture().source().index(), Channels::Data), normal.scale() as f64);
+        }
+
+        //Opacity is the base color's alpha times the alpha of its texture.
+        let mode : Option<AlphaMode> = match material.alpha_mode() {
+            ::gltf::material::AlphaMode::Opaque => None,
+            ::gltf::material::AlphaMode::Mask => Some(AlphaMode::Mask {cutoff : material.alpha_cutoff().unwrap_or(0.5) as f64}),
+            ::gltf::material::AlphaMode::Blend => Some(AlphaMode::Stochastic)
+        };
+        if let Some(mode) = mode {
+            let opacity : Texture = match pbr.base_color_texture() {
+                Some(info) => self.texture(info.texture().source().index(), Channels::Single(3)),
+                None => Texture::constant(1.0)
+            };
+            let opacity : Texture = match opacity {
+                Texture::Image(image) if alpha < 1.0 => ImageTexture::new(image.width(), image.height(), scaled_texels(&image, alpha as f64)).map_or(Texture::constant(alpha as f64), Texture::image),
+                Texture::Constant(_) => Texture::constant(alpha as f64),
+                opacity => opacity
+            };
+            converted = converted.with_opacity(opacity, mode);
+        }
+
+        self.materials.insert(material.index(), converted.clone());
+        return converted;
+    }
+
+    fn texture(&mut self, image : usize, channels : Channels) -> Texture {
+        if let Some(texture) = self.textures.get(&(image, channels)) {
+            return texture.clone();
+        }
+        //Images that are missing or whose pixels don't fill them are left out, as if the texture were white.
+        let texture : Texture = match self.images.get(image) {
+            Some(data) => {
+                let texels : Vec<Color3> = decode(data).into_iter().map(|[r, g, b, a]| match channels {
+                    Channels::Color => Color3::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)),
+                    Channels::Data => Color3::new(r, g, b),
+                    Channels::Single(channel) => {
+                        let value : f64 = [r, g, b, a][channel];
+                        Color3::new(value, value, value)
+                    }
+                }).collect();
+                ImageTexture::new(data.width as usize, data.height as usize, texels).map_or(Texture::constant(1.0), Texture::image)
+            }
+            None => Texture::constant(1.0)
+        };
+        self.textures.insert((image, channels), texture.clone());
+        return texture;
+    }
+
+    fn channel_average(&self, image : usize, channel : usize) -> f64 {
+        let pixels : Vec<[f64; 4]> = self.images.get(image).map_or(Vec::new(), decode);
+        if pixels.is_empty() {
+            return 1.0;
+        }
+        return pixels.iter().map(|pixel| pixel[channel]).sum::<f64>() / pixels.len() as f64;
+    }
+}
+
+//Texels of an image multiplied by factor, row by row from the top left.
+fn scaled_texels(image : &ImageTexture, factor : f64) -> Vec<Color3> {
+    let mut texels : Vec<Color3> = Vec::with_capacity(image.width() * image.height());
+    for row in 0..image.height() {
+        for column in 0..image.width() {
+            let u : f64 = (column as f64 + 0.5) / image.width() as f64;
+            let v : f64 = 1.0 - (row as f64 + 0.5) / image.height() as f64;
+            texels.push(factor * image.sample(u, v));
+        }
+    }
+    return texels;
+}
+
+//Pixels of a decoded image as RGBA in [0, 1], missing channels as 0 and a missing alpha as 1.
+fn decode(data : &::gltf::image::Data) -> Vec<[f64; 4]> {
+    let (channels, bytes) : (usize, usize) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4)
+    };
+    let component = |bytes_of : &[u8]| -> f64 {
+        match bytes_of.len() {
+            1 => bytes_of[0] as f64 / 255.0,
+            2 => u16::from_le_bytes([bytes_of[0], bytes_of[1]]) as f64 / 65535.0,
+            _ => f32::from_le_bytes([bytes_of[0], bytes_of[1], bytes_of[2], bytes_of[3]]) as f64
+        }
+    };
+
+    return data.pixels.chunks_exact(channels * bytes).map(|pixel| {
+        let mut rgba : [f64; 4] = [0.0, 0.0, 0.0, 1.0];
+        for (channel, value) in pixel.chunks_exact(bytes).enumerate() {
+            rgba[channel] = component(value);
+        }
+        rgba
+    }).collect();
+}
+
+//Camera builder looking down the node's -z axis with its y axis up, as glTF cameras do.
+fn camera_builder(camera : &::gltf::Camera, transform : &Matrix) -> CameraBuilder {
+    let eye : Point3 = transform_point(transform, &Vec3::default());
+    let forward : Vec3 = transform_vector(transform, &Vec3::new(0.0, 0.0, -1.0)).unit_vector();
+    let up : Vec3 = transform_vector(transform, &Vec3::new(0.0, 1.0, 0.0)).unit_vector();
+    let builder : CameraBuilder = CameraBuilder::new().look_at(eye, eye + forward, up);
+
+    match camera.projection() {
+        Projection::Perspective(perspective) => {
+            let builder : CameraBuilder = builder.fov((perspective.yfov() as f64).to_degrees());
+            match perspective.aspect_ratio() {
+                Some(aspect_ratio) => builder.aspect_ratio(aspect_ratio as f64),
+                None => builder
+            }
+        }
+        Projection::Orthographic(orthographic) => {
+            let (half_width, half_height) = (orthographic.xmag() as f64, orthographic.ymag() as f64);
+            let builder : CameraBuilder = builder.projection(Orthographic::new(2.0 * half_height));
+            if half_width > 0.0 && half_height > 0.0 {builder.aspect_ratio(half_width / half_height)} else {builder}
+        }
+    }
+}
+
+//Punctual light at the node's origin, shining down its -z axis.
+fn punctual_light(light : &::gltf::khr_lights_punctual::Light, transform : &Matrix) -> Light {
+    let [r, g, b] = light.color();
+    let intensity : Color3 = light.intensity() as f64 * Color3::new(r as f64, g as f64, b as f64);
+    let position : Point3 = transform_point(transform, &Vec3::default());
+    let direction : Vec3 = transform_vector(transform, &Vec3::new(0.0, 0.0, -1.0)).unit_vector();
+    let falloff : Falloff = light.range().map_or(Falloff::InverseSquare, |range| Falloff::Range(range as f64));
+
+    match light.kind() {
+        Kind::Directional => Light::directional(direction, intensity),
+        Kind::Point => Light::point(position, intensity).with_falloff(falloff),
+        Kind::Spot { inner_cone_angle, outer_cone_angle } => {
+            Light::spot(position, position + direction, intensity, (inner_cone_angle as f64).to_degrees(), (outer_cone_angle as f64).to_degrees()).with_falloff(falloff)
+        }
+    }
+}
+
+//Triangles from a list of indices, three per triangle, wound the other way round when mirrored.
+fn triangle_list(indices : &[u32], mirrored : bool) -> Vec<[usize; 3]> {
+    return indices.chunks_exact(3).map(|t| {
+        let (a, b, c) = (t[0] as usize, t[1] as usize, t[2] as usize);
+        if mirrored {[a, c, b]} else {[a, b, c]}
+    }).collect();
+}
+
+fn to_vec(v : [f32; 3]) -> Vec3 {
+    return Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64);
+}
+
+fn multiply(a : &Matrix, b : &Matrix) -> Matrix {
+    let mut product : Matrix = [[0.0; 4]; 4];
+    for column in 0..4 {
+        for row in 0..4 {
+            product[column][row] = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+        }
+    }
+    return product;
+}
+
+fn transform_point(m : &Matrix, p : &Point3) -> Point3 {
+    return transform_vector(m, p) + Vec3::new(m[3][0], m[3][1], m[3][2]);
+}
+
+fn transform_vector(m : &Matrix, v : &Vec3) -> Vec3 {
+    return v.x() * Vec3::new(m[0][0], m[0][1], m[0][2]) + v.y() * Vec3::new(m[1][0], m[1][1], m[1][2]) + v.z() * Vec3::new(m[2][0], m[2][1], m[2][2]);
+}
+
+fn determinant(m : &Matrix) -> f64 {
+    let (a, b, c) = (Vec3::new(m[0][0], m[0][1], m[0][2]), Vec3::new(m[1][0], m[1][1], m[1][2]), Vec3::new(m[2][0], m[2][1], m[2][2]));
+    return Vec3::dot(&a, &Vec3::cross(&b, &c));
+}
+
+//Normals go through the inverse transpose, which for columns a, b and c is (b x c, c x a, a x b) over the
+//determinant. Only its direction matters here, so the determinant's sign stands in for it.
+fn transform_normal(m : &Matrix, n : &Vec3) -> Vec3 {
+    let (a, b, c) = (Vec3::new(m[0][0], m[0][1], m[0][2]), Vec3::new(m[1][0], m[1][1], m[1][2]), Vec3::new(m[2][0], m[2][1], m[2][2]));
+    let normal : Vec3 = n.x() * Vec3::cross(&b, &c) + n.y() * Vec3::cross(&c, &a) + n.z() * Vec3::cross(&a, &b);
+    return if determinant(m) < 0.0 {normal.negate()} else {normal};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Column-major, like glTF.
+    fn scale(x : f64, y : f64, z : f64) -> Matrix {
+        return [[x, 0.0, 0.0, 0.0], [0.0, y, 0.0, 0.0], [0.0, 0.0, z, 0.0], [0.0, 0.0, 0.0, 1.0]];
+    }
+
+    fn translation(x : f64, y : f64, z : f64) -> Matrix {
+        return [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [x, y, z, 1.0]];
+    }
+
+    //Rotation about z by 90 degrees.
+    fn quarter_turn() -> Matrix {
+        return [[0.0, 1.0, 0.0, 0.0], [-1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+    }
+
+    fn assert_close(a : &Vec3, b : &Vec3) {
+        assert!((*a - *b).length() < 1e-12, "{:?} is not close to {:?}", a, b);
+    }
+
+    #[test]
+    fn multiply_applies_the_right_matrix_first() {
+        let p : Point3 = Point3::new(1.0, 2.0, 3.0);
+        let parent : Matrix = multiply(&translation(1.0, 0.0, -1.0), &quarter_turn());
+        let child : Matrix = multiply(&parent, &scale(2.0, 3.0, 4.0));
+
+        //Scaled to (2, 6, 12), turned to (-6, 2, 12), then moved.
+        assert_close(&transform_point(&child, &p), &Point3::new(-5.0, 2.0, 11.0));
+        assert_close(&transform_point(&child, &p), &transform_point(&translation(1.0, 0.0, -1.0), &transform_point(&quarter_turn(), &transform_point(&scale(2.0, 3.0, 4.0), &p))));
+        assert_eq!(multiply(&IDENTITY, &child), child);
+        assert_eq!(multiply(&child, &IDENTITY), child);
+    }
+
+    #[test]
+    fn transformed_normals_stay_perpendicular_to_the_surface() {
+        let m : Matrix = multiply(&quarter_turn(), &scale(1.0, 5.0, 0.5));
+        let (tangent, bitangent) : (Vec3, Vec3) = (Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 1.0));
+        let normal : Vec3 = Vec3::cross(&tangent, &bitangent);
+
+        let transformed : Vec3 = transform_normal(&m, &normal);
+        assert!(Vec3::dot(&transformed, &transform_vector(&m, &tangent)).abs() < 1e-12);
+        assert!(Vec3::dot(&transformed, &transform_vector(&m, &bitangent)).abs() < 1e-12);
+        assert!(Vec3::dot(&transformed, &Vec3::cross(&transform_vector(&m, &tangent), &transform_vector(&m, &bitangent))) > 0.0);
+    }
+
+    #[test]
+    fn mirrored_normals_follow_the_mirrored_surface() {
+        let transformed : Vec3 = transform_normal(&scale(1.0, 1.0, -2.0), &Vec3::new(0.0, 0.0, 1.0));
+        assert_close(&transformed.unit_vector(), &Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn mirrored_triangles_keep_facing_their_normals() {
+        let positions : [Point3; 3] = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        let normal : Vec3 = Vec3::new(0.0, 0.0, 1.0);
+
+        for m in [IDENTITY, scale(-1.0, 1.0, 1.0), multiply(&quarter_turn(), &scale(2.0, -1.0, 3.0)), scale(-1.0, -1.0, -1.0)] {
+            let mirrored : bool = determinant(&m) < 0.0;
+            let [a, b, c] = triangle_list(&[0, 1, 2], mirrored)[0];
+            let moved : Vec<Point3> = positions.iter().map(|p| transform_point(&m, p)).collect();
+            let face : Vec3 = Vec3::cross(&(moved[b] - moved[a]), &(moved[c] - moved[a]));
+            assert!(Vec3::dot(&face, &transform_normal(&m, &normal)) > 0.0, "winding disagrees with the normal for {:?}", m);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+pub mod math;
+pub mod shapes;
+pub mod render;
+pub mod lights;
+pub mod scenes;
+pub mod import;
+
+pub use math::vec3::{Vec3, Color3, Point3};
+pub use math::ray::Ray;
+pub use shapes::hittable::{HitRecord, Hittable, HittableList};
+pub use shapes::material::Material;
+pub use shapes::sphere::Sphere;
+pub use shapes::mesh::TriangleMesh;
+pub use render::camera::{Camera, CameraBuilder, CameraError};
+pub use render::framebuffer::Framebuffer;
+
+//Renders the world through the camera on every available core and returns the image in memory.
+//Use Camera::render directly for progress callbacks, cancellation or statistics.
+pub fn render(camera : Camera, world : HittableList) -> Framebuffer {
+    let (framebuffer, _) = camera.render(world, None, None, None);
+    return framebuffer;
+}
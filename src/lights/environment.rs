@@ -0,0 +1,183 @@
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+use rand::Rng;
+use crate::math::vec3::{Vec3, Color3};
+use crate::math::distribution::Distribution2D;
+use super::sky::PhysicalSky;
+
+//Equirectangular (latitude/longitude) radiance map surrounding the scene. The middle of the image is the -z
+//direction and the top row is straight up, matching the Equirectangular camera projection.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    width : usize,
+    height : usize,
+    texels : Vec<Color3>,
+    rotation : f64, //Radians about the y axis
+    intensity : f64,
+    distribution : Distribution2D
+}
+
+impl EnvironmentMap {
+    //Texels are linear radiance, given row by row from the top left. Returns None if the map is empty or there isn't
+    //one texel per pixel.
+    pub fn new(width : usize, height : usize, texels : Vec<Color3>) -> Option<Self> {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            return None;
+        }
+
+        //Rows near the poles cover less solid angle, weighting by sin(theta) keeps them from being oversampled.
+        let mut luminance : Vec<f64> = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let sin_theta : f64 = (PI * (row as f64 + 0.5) / height as f64).sin();
+            for column in 0..width {
+                luminance.push(texels[row * width + column].luminance() * sin_theta);
+            }
+        }
+
+        return Some(Self {
+            width : width,
+            height : height,
+            texels : texels,
+            rotation : 0.0,
+            intensity : 1.0,
+            distribution : Distribution2D::new(&luminance, width, height)
+        });
+    }
+
+    //Loads any format the image crate can decode as linear radiance, normally a Radiance .hdr file.
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.to_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let texels : Vec<Color3> = image.pixels().map(|p| Color3::new(p.0[0] as f64, p.0[1] as f64, p.0[2] as f64)).collect();
+        return EnvironmentMap::new(width, height, texels).ok_or_else(|| {
+            image::ImageError::Parameter(image::error::ParameterError::from_kind(image::error::ParameterErrorKind::DimensionMismatch))
+        });
+    }
+
+    //Rotation about the vertical axis in degrees.
+    pub fn with_rotation(mut self, degrees : f64) -> Self {
+        self.rotation = degrees.to_radians();
+        return self;
+    }
+
+    pub fn with_intensity(mut self, intensity : f64) -> Self {
+        self.intensity = intensity;
+        return self;
+    }
+
+    //Direction to image coordinates in [0, 1)^2.
+    fn direction_to_uv(&self, direction : &Vec3) -> (f64, f64) {
+        let d : Vec3 = direction.unit_vector();
+        let phi : f64 = d.x().atan2(-d.z()) - self.rotation;
+        let theta : f64 = d.y().clamp(-1.0, 1.0).acos();
+        return ((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI);
+    }
+
+    fn uv_to_direction(&self, u : f64, v : f64) -> Vec3 {
+        let phi : f64 = (u - 0.5) * 2.0 * PI + self.rotation;
+        let theta : f64 = v * PI;
+        return Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+    }
+
+    pub fn radiance(&self, direction : &Vec3) -> Color3 {
+        let (u, v) = self.direction_to_uv(direction);
+        let column : usize = ((u * self.width as f64) as usize).min(self.width - 1);
+        let row : usize = ((v * self.height as f64) as usize).min(self.height - 1);
+        return self.intensity * self.texels[row * self.width + column];
+    }
+
+    //Solid angle density of sample() choosing this direction.
+    pub fn pdf(&self, direction : &Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta : f64 = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        return self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta);
+    }
+
+    //Direction picked in proportion to the map's brightness, with its radiance and solid angle density.
+    pub fn sample(&self) -> (Vec3, Color3, f64) {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let ((u, v), pdf_uv) = self.distribution.sample(rng.gen(), rng.gen());
+        let sin_theta : f64 = (v * PI).sin();
+        let direction : Vec3 = self.uv_to_direction(u, v);
+
+        let pdf : f64 = if sin_theta <= 0.0 {0.0} else {pdf_uv / (2.0 * PI * PI * sin_theta)};
+        return (direction, self.radiance(&direction), pdf);
+    }
+}
+
+//Light arriving from infinitely far away, seen by rays that leave the scene.
+#[derive(Clone, Debug, Default)]
+pub enum Environment {
+    #[default]
+    GradientSky, //White at the horizon to light blue overhead, not importance sampled
+    Map(Arc<EnvironmentMap>),
+    PhysicalSky(Arc<PhysicalSky>), //Only the sun is importance sampled, the rest of the sky is found by scattered rays
+    Black
+}
+
+impl Environment {
+    pub fn map(map : EnvironmentMap) -> Self {
+        return Environment::Map(Arc::new(map));
+    }
+
+    pub fn physical_sky(sky : PhysicalSky) -> Self {
+        return Environment::PhysicalSky(Arc::new(sky));
+    }
+
+    pub fn radiance(&self, direction : &Vec3) -> Color3 {
+        match self {
+            Environment::GradientSky => {
+                let unit_direction : Vec3 = direction.unit_vector();
+                let a : f64 = 0.5 * (unit_direction.y() + 1.0);
+                return (1.0-a) * Color3::new(1.0,1.0,1.0) + a * Color3::new(0.5, 0.7, 1.0);
+            }
+            Environment::Map(map) => map.radiance(direction),
+            Environment::PhysicalSky(sky) => sky.radiance(direction),
+            Environment::Black => Color3::default()
+        }
+    }
+
+    //Whether direct lighting should sample this environment with shadow rays.
+    pub fn is_sampled(&self) -> bool {
+        return match self {
+            Environment::Map(_) => true,
+            Environment::PhysicalSky(sky) => sky.has_sun(),
+            _ => false
+        };
+    }
+
+    pub fn sample(&self) -> Option<(Vec3, Color3, f64)> {
+        match self {
+            Environment::Map(map) => Some(map.sample()),
+            Environment::PhysicalSky(sky) if sky.has_sun() => Some(sky.sample_sun()),
+            _ => None
+        }
+    }
+
+    pub fn pdf(&self, direction : &Vec3) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            Environment::PhysicalSky(sky) => sky.sun_pdf(direction),
+            _ => 0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_rejects_maps_without_one_texel_per_pixel() {
+        assert!(EnvironmentMap::new(0, 2, Vec::new()).is_none());
+        assert!(EnvironmentMap::new(2, 0, Vec::new()).is_none());
+        assert!(EnvironmentMap::new(2, 2, vec![Color3::new(1.0, 1.0, 1.0); 3]).is_none());
+
+        let map : EnvironmentMap = EnvironmentMap::new(2, 1, vec![Color3::new(1.0, 0.0, 0.0), Color3::new(0.0, 1.0, 0.0)]).unwrap();
+        assert_eq!(map.radiance(&Vec3::new(0.0, 0.0, -1.0)).length_squared(), 1.0);
+    }
+}
@@ -0,0 +1,13 @@
+pub mod environment;
+pub mod sky;
+pub mod punctual;
+
+//Power heuristic (beta = 2) weight for a sample drawn with density f_pdf that could also have come from a strategy with density g_pdf.
+pub fn power_heuristic(f_pdf : f64, g_pdf : f64) -> f64 {
+    let f2 : f64 = f_pdf * f_pdf;
+    let g2 : f64 = g_pdf * g_pdf;
+    if f2 + g2 <= 0.0 {
+        return 0.0;
+    }
+    return f2 / (f2 + g2);
+}
\ No newline at end of file
@@ -0,0 +1,199 @@
+use std::f64::consts::PI;
+use rand::Rng;
+use crate::math::vec3::{Vec3, Point3, Color3};
+use crate::math::spectrum::{Blackbody, EmissionSpectrum};
+
+//How a point or spot light's intensity drops off with distance.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Falloff {
+    #[default]
+    InverseSquare, //Physically based
+    Linear, //Divided by distance, softer than real lights
+    Constant, //No falloff at all
+    Range(f64) //Inverse square, smoothly windowed to reach zero at this distance
+}
+
+impl Falloff {
+    pub fn attenuation(&self, distance : f64) -> f64 {
+        let distance_squared : f64 = (distance * distance).max(1e-8);
+        match self {
+            Falloff::InverseSquare => 1.0 / distance_squared,
+            Falloff::Linear => 1.0 / distance.max(1e-4),
+            Falloff::Constant => 1.0,
+            Falloff::Range(range) => {
+                let ratio : f64 = distance / range;
+                let window : f64 = (1.0 - ratio * ratio * ratio * ratio).clamp(0.0, 1.0);
+                window * window / distance_squared
+            }
+        }
+    }
+}
+
+//Light from a single point or direction, with no geometry for rays to hit. The path tracer only reaches them through
+//shadow rays from non-specular surfaces, so caustics from them need the bidirectional integrator.
+#[derive(Copy, Clone, Debug)]
+pub enum Light {
+    Point {position : Point3, intensity : Color3, falloff : Falloff, spectrum : EmissionSpectrum}, //Intensity is power per steradian
+    Spot {position : Point3, direction : Vec3, intensity : Color3, inner_angle : f64, outer_angle : f64, falloff : Falloff, spectrum : EmissionSpectrum}, //Half angles in degrees, full intensity inside the inner cone and none outside the outer
+    Directional {direction : Vec3, irradiance : Color3, spectrum : EmissionSpectrum} //Direction the light travels in, like sunlight
+}
+
+//Incident light from a Light at a shading point.
+#[derive(Copy, Clone, Debug)]
+pub struct LightSample {
+    pub direction : Vec3, //Unit vector from the shading point towards the light
+    pub distance : f64, //Distance the shadow ray has to clear, infinite for directional lights
+    pub radiance : Color3 //Incident radiance already integrated over the light's delta distribution
+}
+
+impl Light {
+    pub fn point(position : Point3, intensity : Color3) -> Self {
+        return Light::Point {position : position, intensity : intensity, falloff : Falloff::InverseSquare, spectrum : EmissionSpectrum::Rgb};
+    }
+
+    pub fn spot(position : Point3, target : Point3, intensity : Color3, inner_angle : f64, outer_angle : f64) -> Self {
+        return Light::Spot {position : position, direction : (target - position).unit_vector(), intensity : intensity, inner_angle : inner_angle, outer_angle : outer_angle, falloff : Falloff::InverseSquare, spectrum : EmissionSpectrum::Rgb};
+    }
+
+    pub fn directional(direction : Vec3, irradiance : Color3) -> Self {
+        return Light::Directional {direction : direction.unit_vector(), irradiance : irradiance, spectrum : EmissionSpectrum::Rgb};
+    }
+
+    //Makes the light a black body at temperature Kelvin, keeping its luminance. Its color becomes the black body's,
+    //and the spectral integrator uses the exact spectrum.
+    pub fn with_blackbody(self, temperature : f64) -> Self {
+        let blackbody : Blackbody = Blackbody::new(temperature);
+        let spectrum : EmissionSpectrum = EmissionSpectrum::Blackbody(blackbody);
+        match self {
+            Light::Point { position, intensity, falloff, .. } => Light::Point {position : position, intensity : intensity.luminance() * blackbody.color(), falloff : falloff, spectrum : spectrum},
+            Light::Spot { position, direction, intensity, inner_angle, outer_angle, falloff, .. } => Light::Spot {position : position, direction : direction, intensity : intensity.luminance() * blackbody.color(), inner_angle : inner_angle, outer_angle : outer_angle, falloff : falloff, spectrum : spectrum},
+            Light::Directional { direction, irradiance, .. } => Light::Directional {direction : direction, irradiance : irradiance.luminance() * blackbody.color(), spectrum : spectrum}
+        }
+    }
+
+    pub fn spectrum(&self) -> EmissionSpectrum {
+        match self {
+            Light::Point { spectrum, .. } | Light::Spot { spectrum, .. } | Light::Directional { spectrum, .. } => *spectrum
+        }
+    }
+
+    pub fn with_falloff(self, falloff : Falloff) -> Self {
+        match self {
+            Light::Point { position, intensity, spectrum, .. } => Light::Point {position : position, intensity : intensity, falloff : falloff, spectrum : spectrum},
+            Light::Spot { position, direction, intensity, inner_angle, outer_angle, spectrum, .. } => Light::Spot {position : position, direction : direction, intensity : intensity, inner_angle : inner_angle, outer_angle : outer_angle, falloff : falloff, spectrum : spectrum},
+            Light::Directional { .. } => self
+        }
+    }
+
+    //None when the point gets no light, e.g. outside a spot light's cone.
+    pub fn sample(&self, p : &Point3) -> Option<LightSample> {
+        match self {
+            Light::Point { position, intensity, falloff, .. } => {
+                let to_light : Vec3 = *position - *p;
+                let distance : f64 = to_light.length();
+                return Some(LightSample {direction : to_light / distance, distance : distance, radiance : falloff.attenuation(distance) * *intensity});
+            }
+
+            Light::Spot { position, direction, intensity, inner_angle, outer_angle, falloff, .. } => {
+                let to_light : Vec3 = *position - *p;
+                let distance : f64 = to_light.length();
+                let wi : Vec3 = to_light / distance;
+
+                let cone : f64 = spot_cone(Vec3::dot(&wi.negate(), &direction.unit_vector()), *inner_angle, *outer_angle);
+                if cone <= 0.0 {
+                    return None;
+                }
+                return Some(LightSample {direction : wi, distance : distance, radiance : (cone * falloff.attenuation(distance)) * *intensity});
+            }
+
+            Light::Directional { direction, irradiance, .. } => {
+                return Some(LightSample {direction : direction.unit_vector().negate(), distance : f64::INFINITY, radiance : *irradiance});
+            }
+        }
+    }
+
+    //Point and spot lights sit somewhere in the scene, so paths can be traced out of them. Directional lights can't.
+    pub fn is_positional(&self) -> bool {
+        return !matches!(self, Light::Directional { .. });
+    }
+
+    //Ray leaving a point or spot light, for tracing paths from the light: its origin and direction, the intensity
+    //emitted along it and the solid angle density of the direction. None for directional lights.
+    pub fn sample_emission(&self) -> Option<(Point3, Vec3, Color3, f64)> {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        match self {
+            Light::Point { position, intensity, .. } => {
+                return Some((*position, Vec3::random_unit_vector(), *intensity, 1.0 / (4.0 * PI)));
+            }
+
+            Light::Spot { position, direction, intensity, inner_angle, outer_angle, .. } => {
+                //Uniform over the outer cone, the smooth edge is applied to the intensity.
+                let axis : Vec3 = direction.unit_vector();
+                let cos_outer : f64 = outer_angle.max(*inner_angle).to_radians().cos();
+                let cos_theta : f64 = 1.0 - rng.gen::<f64>() * (1.0 - cos_outer);
+                let sin_theta : f64 = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi : f64 = 2.0 * PI * rng.gen::<f64>();
+
+                let helper : Vec3 = if axis.x().abs() > 0.9 {Vec3::new(0.0, 1.0, 0.0)} else {Vec3::new(1.0, 0.0, 0.0)};
+                let u : Vec3 = Vec3::cross(&helper, &axis).unit_vector();
+                let v : Vec3 = Vec3::cross(&axis, &u);
+                let emitted : Vec3 = (sin_theta * phi.cos()) * u + (sin_theta * phi.sin()) * v + cos_theta * axis;
+
+                let cone : f64 = spot_cone(cos_theta, *inner_angle, *outer_angle);
+                return Some((*position, emitted, cone * *intensity, self.emission_pdf(&emitted)));
+            }
+
+            Light::Directional { .. } => None
+        }
+    }
+
+    //Solid angle density of sample_emission picking this direction.
+    pub fn emission_pdf(&self, direction : &Vec3) -> f64 {
+        match self {
+            Light::Point { .. } => 1.0 / (4.0 * PI),
+            Light::Spot { direction : axis, inner_angle, outer_angle, .. } => {
+                let cos_outer : f64 = outer_angle.max(*inner_angle).to_radians().cos();
+                if Vec3::dot(&direction.unit_vector(), &axis.unit_vector()) < cos_outer {
+                    return 0.0;
+                }
+                1.0 / (2.0 * PI * (1.0 - cos_outer))
+            }
+            Light::Directional { .. } => 0.0
+        }
+    }
+
+    //Ratio of the light's falloff to the inverse square law at a distance. Paths traced out of the light pick up the
+    //inverse square through their geometry, this scales them to match the light's own falloff.
+    pub fn falloff_scale(&self, distance : f64) -> f64 {
+        match self {
+            Light::Point { falloff, .. } | Light::Spot { falloff, .. } => falloff.attenuation(distance) * distance * distance,
+            Light::Directional { .. } => 1.0
+        }
+    }
+
+    //Total emitted power, used to pick between lights in proportion to how much they contribute.
+    pub fn power(&self) -> f64 {
+        match self {
+            Light::Point { intensity, .. } => 4.0 * PI * intensity.luminance(),
+            Light::Spot { intensity, inner_angle, outer_angle, .. } => {
+                let cos_mid : f64 = (0.5 * (inner_angle + outer_angle)).to_radians().cos();
+                2.0 * PI * (1.0 - cos_mid) * intensity.luminance()
+            }
+            Light::Directional { irradiance, .. } => irradiance.luminance()
+        }
+    }
+}
+
+//Smoothstep between the outer and inner cones.
+fn spot_cone(cos_angle : f64, inner_angle : f64, outer_angle : f64) -> f64 {
+    let cos_inner : f64 = inner_angle.to_radians().cos();
+    let cos_outer : f64 = outer_angle.max(inner_angle).to_radians().cos();
+    if cos_angle >= cos_inner {
+        return 1.0;
+    }
+    if cos_angle <= cos_outer {
+        return 0.0;
+    }
+    let t : f64 = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+    return t * t * (3.0 - 2.0 * t);
+}
@@ -0,0 +1,245 @@
+use std::f64::consts::PI;
+use rand::Rng;
+use crate::math::vec3::{Vec3, Color3};
+
+//Scene radiance per cd/m^2. Puts the zenith of a clear midday sky at around the brightness of the gradient sky.
+const RADIANCE_PER_NIT : f64 = 5.0e-5;
+
+//Luminance of the sun's disk above the atmosphere in cd/m^2, and its angular radius.
+const SUN_LUMINANCE : f64 = 1.6e9;
+const SUN_ANGULAR_RADIUS : f64 = 0.004654; //0.2666 degrees
+
+//Wavelengths in micrometres used to attenuate the sun's color for the red, green and blue channels.
+const CHANNEL_WAVELENGTHS : [f64; 3] = [0.68, 0.55, 0.44];
+
+//Local date and time at a place on Earth, used to work out where the sun is.
+#[derive(Copy, Clone, Debug)]
+pub struct SolarTime {
+    pub year : i32,
+    pub month : u32, //1 to 12
+    pub day : u32,
+    pub hour : f64, //Local clock time in hours, 13.5 is half past one in the afternoon
+    pub utc_offset : f64, //Hours ahead of UTC of the local clock
+    pub latitude : f64, //Degrees, north is positive
+    pub longitude : f64 //Degrees, east is positive
+}
+
+impl SolarTime {
+    fn day_of_year(&self) -> u32 {
+        const DAYS_BEFORE_MONTH : [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let leap : bool = (self.year % 4 == 0 && self.year % 100 != 0) || self.year % 400 == 0;
+        let month : usize = (self.month.clamp(1, 12) - 1) as usize;
+        return DAYS_BEFORE_MONTH[month] + self.day + if leap && month >= 2 {1} else {0};
+    }
+
+    //Sun elevation above the horizon and azimuth clockwise from north, both in degrees. Uses the NOAA
+    //approximation of the equation of time and declination, good to a fraction of a degree.
+    pub fn sun_position(&self) -> (f64, f64) {
+        let utc_hour : f64 = self.hour - self.utc_offset;
+        let gamma : f64 = 2.0 * PI / 365.0 * (self.day_of_year() as f64 - 1.0 + (utc_hour - 12.0) / 24.0);
+
+        let equation_of_time : f64 = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin() - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+        let declination : f64 = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos()
+            + 0.000907 * (2.0 * gamma).sin() - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+        //True solar time in minutes and the hour angle, which is zero at solar noon.
+        let solar_minutes : f64 = utc_hour * 60.0 + equation_of_time + 4.0 * self.longitude;
+        let hour_angle : f64 = (solar_minutes / 4.0 - 180.0).to_radians();
+        let latitude : f64 = self.latitude.to_radians();
+
+        let cos_zenith : f64 = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation : f64 = 90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+        let azimuth : f64 = hour_angle.sin().atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos()).to_degrees() + 180.0;
+
+        return (elevation, azimuth.rem_euclid(360.0));
+    }
+}
+
+//Coefficients A to E of the Perez sky luminance distribution.
+#[derive(Copy, Clone, Debug)]
+struct Perez {
+    a : f64,
+    b : f64,
+    c : f64,
+    d : f64,
+    e : f64
+}
+
+impl Perez {
+    //theta is the angle from the zenith, gamma the angle from the sun.
+    fn evaluate(&self, theta : f64, gamma : f64) -> f64 {
+        let cos_theta : f64 = theta.cos().max(0.01);
+        return (1.0 + self.a * (self.b / cos_theta).exp()) * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos() * gamma.cos());
+    }
+}
+
+//Preetham et al. "A Practical Analytic Model for Daylight" clear sky, with a sun disk that direct lighting can
+//sample. The world's up direction is +y, north is -z and east is +x. Below the horizon is a flat ground that
+//reflects the sun and sky according to ground_albedo.
+#[derive(Clone, Debug)]
+pub struct PhysicalSky {
+    pub turbidity : f64,
+    pub ground_albedo : Color3,
+    sun_direction : Vec3,
+    intensity : f64,
+    perez : [Perez; 3], //Y, x and y
+    zenith : [f64; 3], //Zenith luminance (cd/m^2) and chromaticity
+    normalization : [f64; 3], //Perez function at the zenith, which the zenith values are divided by
+    sun_radiance : Color3,
+    ground_radiance : Color3
+}
+
+impl PhysicalSky {
+    //Turbidity ranges from 2 (very clear) to about 10 (hazy). Elevation and azimuth (clockwise from north) in degrees.
+    pub fn new(turbidity : f64, ground_albedo : Color3, sun_elevation : f64, sun_azimuth : f64) -> Self {
+        let elevation : f64 = sun_elevation.to_radians();
+        let azimuth : f64 = sun_azimuth.to_radians();
+        let sun_direction : Vec3 = Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos());
+
+        let mut sky : PhysicalSky = PhysicalSky {
+            turbidity : turbidity,
+            ground_albedo : ground_albedo,
+            sun_direction : sun_direction,
+            intensity : 1.0,
+            perez : [Perez {a : 0.0, b : 0.0, c : 0.0, d : 0.0, e : 0.0}; 3],
+            zenith : [0.0; 3],
+            normalization : [1.0; 3],
+            sun_radiance : Color3::default(),
+            ground_radiance : Color3::default()
+        };
+        sky.precompute();
+        return sky;
+    }
+
+    pub fn from_solar_time(turbidity : f64, ground_albedo : Color3, time : &SolarTime) -> Self {
+        let (elevation, azimuth) = time.sun_position();
+        return PhysicalSky::new(turbidity, ground_albedo, elevation, azimuth);
+    }
+
+    pub fn with_intensity(mut self, intensity : f64) -> Self {
+        self.intensity = intensity;
+        self.precompute();
+        return self;
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        return self.sun_direction;
+    }
+
+    fn precompute(&mut self) {
+        let t : f64 = self.turbidity;
+        self.perez = [
+            Perez {a : 0.1787 * t - 1.4630, b : -0.3554 * t + 0.4275, c : -0.0227 * t + 5.3251, d : 0.1206 * t - 2.5771, e : -0.0670 * t + 0.3703},
+            Perez {a : -0.0193 * t - 0.2592, b : -0.0665 * t + 0.0008, c : -0.0004 * t + 0.2125, d : -0.0641 * t - 0.8989, e : -0.0033 * t + 0.0452},
+            Perez {a : -0.0167 * t - 0.2608, b : -0.0950 * t + 0.0092, c : -0.0079 * t + 0.2102, d : -0.0441 * t - 1.6537, e : -0.0109 * t + 0.0529}
+        ];
+
+        //The model is only fitted for the sun above the horizon, so a set sun is clamped to it.
+        let theta_s : f64 = self.sun_direction.y().clamp(0.0, 1.0).acos().min(PI / 2.0 - 0.001);
+        let (t2, s2, s3) = (t * t, theta_s * theta_s, theta_s * theta_s * theta_s);
+
+        let chi : f64 = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance : f64 = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0) * 1000.0;
+        let zenith_x : f64 = t2 * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * theta_s)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y : f64 = t2 * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * theta_s)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * theta_s + 0.26688);
+
+        self.zenith = [zenith_luminance, zenith_x, zenith_y];
+        for i in 0..3 {
+            self.normalization[i] = self.perez[i].evaluate(0.0, theta_s);
+        }
+
+        //Sun disk, attenuated by Rayleigh and aerosol scattering along the path through the atmosphere.
+        let elevation_degrees : f64 = 90.0 - theta_s.to_degrees();
+        let air_mass : f64 = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta : f64 = 0.04608 * t - 0.04586;
+        let mut sun : Color3 = Color3::default();
+        for (channel, wavelength) in CHANNEL_WAVELENGTHS.iter().enumerate() {
+            let rayleigh : f64 = (-0.008735 * air_mass * wavelength.powf(-4.08)).exp();
+            let aerosol : f64 = (-beta * air_mass * wavelength.powf(-1.3)).exp();
+            sun[channel] = rayleigh * aerosol;
+        }
+        let visible : bool = self.sun_direction.y() > 0.0 && elevation_degrees > 0.0;
+        self.sun_radiance = if visible {(SUN_LUMINANCE * RADIANCE_PER_NIT * self.intensity) * sun} else {Color3::default()};
+
+        //Flat diffuse ground lit by the sun and, roughly, by a uniform sky as bright as the zenith.
+        let solid_angle : f64 = 2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos());
+        let sky_irradiance : Color3 = PI * self.sky_radiance(&Vec3::new(0.0, 1.0, 0.0));
+        let sun_irradiance : Color3 = (solid_angle * self.sun_direction.y().max(0.0)) * self.sun_radiance;
+        self.ground_radiance = (1.0 / PI) * (self.ground_albedo * (sky_irradiance + sun_irradiance));
+    }
+
+    //Sky without the sun disk, for a direction above the horizon.
+    fn sky_radiance(&self, direction : &Vec3) -> Color3 {
+        let d : Vec3 = direction.unit_vector();
+        let theta : f64 = d.y().clamp(0.0, 1.0).acos();
+        let gamma : f64 = Vec3::dot(&d, &self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let luminance : f64 = self.zenith[0] * self.perez[0].evaluate(theta, gamma) / self.normalization[0];
+        let x : f64 = self.zenith[1] * self.perez[1].evaluate(theta, gamma) / self.normalization[1];
+        let y : f64 = self.zenith[2] * self.perez[2].evaluate(theta, gamma) / self.normalization[2];
+
+        //Fade the sky out as the sun drops below the horizon rather than cutting to black.
+        let twilight : f64 = ((self.sun_direction.y() + 0.1) / 0.1).clamp(0.0, 1.0);
+        return (RADIANCE_PER_NIT * self.intensity * twilight) * xyy_to_rgb(x, y, luminance);
+    }
+
+    fn in_sun(&self, direction : &Vec3) -> bool {
+        return Vec3::dot(&direction.unit_vector(), &self.sun_direction) >= SUN_ANGULAR_RADIUS.cos();
+    }
+
+    pub fn radiance(&self, direction : &Vec3) -> Color3 {
+        if direction.y() < 0.0 {
+            return self.ground_radiance;
+        }
+
+        let sky : Color3 = self.sky_radiance(direction);
+        return if self.in_sun(direction) {sky + self.sun_radiance} else {sky};
+    }
+
+    pub fn has_sun(&self) -> bool {
+        return !self.sun_radiance.near_zero();
+    }
+
+    //Uniform direction within the sun's disk with the radiance arriving from it and its solid angle density.
+    pub fn sample_sun(&self) -> (Vec3, Color3, f64) {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let cos_max : f64 = SUN_ANGULAR_RADIUS.cos();
+        let cos_theta : f64 = 1.0 - rng.gen::<f64>() * (1.0 - cos_max);
+        let sin_theta : f64 = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi : f64 = 2.0 * PI * rng.gen::<f64>();
+
+        //Frame around the sun direction.
+        let w : Vec3 = self.sun_direction;
+        let helper : Vec3 = if w.x().abs() > 0.9 {Vec3::new(0.0, 1.0, 0.0)} else {Vec3::new(1.0, 0.0, 0.0)};
+        let u : Vec3 = Vec3::cross(&helper, &w).unit_vector();
+        let v : Vec3 = Vec3::cross(&w, &u);
+
+        let direction : Vec3 = (sin_theta * phi.cos()) * u + (sin_theta * phi.sin()) * v + cos_theta * w;
+        return (direction, self.radiance(&direction), self.sun_pdf(&direction));
+    }
+
+    pub fn sun_pdf(&self, direction : &Vec3) -> f64 {
+        if !self.has_sun() || !self.in_sun(direction) {
+            return 0.0;
+        }
+        return 1.0 / (2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos()));
+    }
+}
+
+//CIE xyY to linear sRGB.
+fn xyy_to_rgb(x : f64, y : f64, luminance : f64) -> Color3 {
+    if y <= 0.0 {
+        return Color3::default();
+    }
+    let big_x : f64 = x * luminance / y;
+    let big_z : f64 = (1.0 - x - y) * luminance / y;
+    return Color3::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0)
+    );
+}
@@ -0,0 +1,169 @@
+//Piecewise constant distributions for importance sampling tabulated functions such as environment maps.
+
+//Distribution over [0, 1) proportional to a piecewise constant function with func.len() equal sized steps.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func : Vec<f64>,
+    cdf : Vec<f64>, //func.len() + 1 entries, cdf[0] = 0 and cdf[n] = 1
+    integral : f64
+}
+
+impl Distribution1D {
+    //Negative values are treated as zero. A function that is zero everywhere falls back to a uniform distribution.
+    pub fn new(func : &[f64]) -> Self {
+        let n : usize = func.len().max(1);
+        let func : Vec<f64> = if func.is_empty() {vec![0.0]} else {func.iter().map(|f| f.max(0.0)).collect()};
+
+        let mut cdf : Vec<f64> = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+
+        let integral : f64 = cdf[n];
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0.0 {*value / integral} else {i as f64 / n as f64};
+        }
+
+        return Self {func : func, cdf : cdf, integral : integral};
+    }
+
+    pub fn count(&self) -> usize {
+        return self.func.len();
+    }
+
+    pub fn integral(&self) -> f64 {
+        return self.integral;
+    }
+
+    //Maps a uniform sample to a point x in [0, 1), returning x, its density and the index of the step it fell in.
+    pub fn sample_continuous(&self, u : f64) -> (f64, f64, usize) {
+        let index : usize = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(self.count() - 1);
+        let width : f64 = self.cdf[index + 1] - self.cdf[index];
+        let offset : f64 = if width > 0.0 {(u - self.cdf[index]) / width} else {0.0};
+
+        let x : f64 = (index as f64 + offset) / self.count() as f64;
+        return (x, self.pdf_at_index(index), index);
+    }
+
+    fn pdf_at_index(&self, index : usize) -> f64 {
+        if self.integral <= 0.0 {
+            return 1.0;
+        }
+        return self.func[index] / self.integral;
+    }
+
+    pub fn pdf(&self, x : f64) -> f64 {
+        let index : usize = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        return self.pdf_at_index(index);
+    }
+
+    //Picks one of the steps in proportion to its value, returning its index and probability.
+    pub fn sample_discrete(&self, u : f64) -> (usize, f64) {
+        let (_, _, index) = self.sample_continuous(u);
+        return (index, self.discrete_pdf(index));
+    }
+
+    pub fn discrete_pdf(&self, index : usize) -> f64 {
+        return self.pdf_at_index(index) / self.count() as f64;
+    }
+}
+
+//Distribution over [0, 1)^2 for a function tabulated on a width x height grid, stored row by row.
+//A row is picked from the marginal distribution, then a column from that row's conditional distribution.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional : Vec<Distribution1D>,
+    marginal : Distribution1D
+}
+
+impl Distribution2D {
+    pub fn new(func : &[f64], width : usize, height : usize) -> Self {
+        let conditional : Vec<Distribution1D> = (0..height).map(|row| Distribution1D::new(&func[row * width..(row + 1) * width])).collect();
+        let row_integrals : Vec<f64> = conditional.iter().map(|row| row.integral()).collect();
+        return Self {conditional : conditional, marginal : Distribution1D::new(&row_integrals)};
+    }
+
+    //Returns (x, y) in [0, 1)^2 and its density.
+    pub fn sample(&self, u0 : f64, u1 : f64) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u1);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u0);
+        return ((x, y), pdf_x * pdf_y);
+    }
+
+    pub fn pdf(&self, x : f64, y : f64) -> f64 {
+        let row : usize = ((y * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        return self.marginal.pdf(y) * self.conditional[row].pdf(x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FUNC : [f64; 5] = [1.0, 0.0, 3.0, 0.5, 2.0];
+
+    #[test]
+    fn sampled_density_matches_pdf() {
+        let distribution : Distribution1D = Distribution1D::new(&FUNC);
+        for i in 0..100 {
+            let (x, pdf, index) = distribution.sample_continuous((i as f64 + 0.5) / 100.0);
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(index, (x * FUNC.len() as f64) as usize);
+            assert!((pdf - distribution.pdf(x)).abs() < 1e-12);
+            assert!(pdf > 0.0, "sampled a step with no weight");
+        }
+    }
+
+    #[test]
+    fn samples_fall_in_proportion_to_the_function() {
+        let distribution : Distribution1D = Distribution1D::new(&FUNC);
+        let total : f64 = FUNC.iter().sum();
+        let samples : usize = 10000;
+        let mut counts : [usize; 5] = [0; 5];
+        for i in 0..samples {
+            let (index, probability) = distribution.sample_discrete((i as f64 + 0.5) / samples as f64);
+            assert!((probability - FUNC[index] / total).abs() < 1e-12);
+            counts[index] += 1;
+        }
+        for (count, f) in counts.iter().zip(FUNC.iter()) {
+            assert!((*count as f64 / samples as f64 - f / total).abs() < 1e-3);
+        }
+        let pdf_integral : f64 = (0..FUNC.len()).map(|i| distribution.pdf((i as f64 + 0.5) / FUNC.len() as f64) / FUNC.len() as f64).sum();
+        assert!((pdf_integral - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zero_function_is_uniform() {
+        let distribution : Distribution1D = Distribution1D::new(&[0.0, 0.0, -1.0]);
+        let (x, pdf, _) = distribution.sample_continuous(0.5);
+        assert!((x - 0.5).abs() < 1e-12);
+        assert_eq!(pdf, 1.0);
+        assert!((distribution.discrete_pdf(2) - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sampled_2d_density_matches_pdf_and_integrates_to_one() {
+        let (width, height) : (usize, usize) = (4, 3);
+        let func : Vec<f64> = (0..width * height).map(|i| ((i * 7) % 5) as f64).collect();
+        let distribution : Distribution2D = Distribution2D::new(&func, width, height);
+
+        for i in 0..20 {
+            for j in 0..20 {
+                let ((x, y), pdf) = distribution.sample((i as f64 + 0.5) / 20.0, (j as f64 + 0.5) / 20.0);
+                assert!((pdf - distribution.pdf(x, y)).abs() < 1e-9);
+                assert!(pdf > 0.0);
+            }
+        }
+
+        let total : f64 = func.iter().sum();
+        let mut integral : f64 = 0.0;
+        for row in 0..height {
+            for column in 0..width {
+                let pdf : f64 = distribution.pdf((column as f64 + 0.5) / width as f64, (row as f64 + 0.5) / height as f64);
+                assert!((pdf - func[row * width + column] * (width * height) as f64 / total).abs() < 1e-9);
+                integral += pdf / (width * height) as f64;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-12);
+    }
+}
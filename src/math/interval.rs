@@ -3,12 +3,12 @@ pub struct Interval {
     pub max : f64
 }
 
-pub const empty : Interval = Interval {
+pub const EMPTY : Interval = Interval {
     min : f64::INFINITY,
     max : f64::NEG_INFINITY
 };
 
-pub const universe : Interval = Interval {
+pub const UNIVERSE : Interval = Interval {
     min : f64::NEG_INFINITY,
     max : f64::INFINITY
 };
@@ -32,10 +32,10 @@ impl Interval {
     }
 
     pub fn clamp(&self, x : f64) -> f64 {
-        if(x < self.min){
+        if x < self.min {
             return self.min;
         }
-        if(x > self.max){
+        if x > self.max {
             return self.max;
         }
         
@@ -1,3 +1,5 @@
 pub mod vec3;
 pub mod ray;
-pub mod interval;
\ No newline at end of file
+pub mod interval;
+pub mod distribution;
+pub mod spectrum;
@@ -1,15 +1,19 @@
 use super::vec3::Vec3;
+use crate::shapes::medium::MediumStack;
 
+#[derive(Default)]
 pub struct Ray {
     pub origin : Vec3,
-    pub dir : Vec3
+    pub dir : Vec3,
+    pub media : MediumStack //Dielectrics the ray is inside, empty for rays starting in the open air
 }
 
 impl Ray {
     pub fn new(orig : Vec3, direction : Vec3) -> Self {
         Self {
             origin : orig,
-            dir : direction
+            dir : direction,
+            media : MediumStack::default()
         }
     }
 
@@ -17,12 +21,3 @@ impl Ray {
        return self.origin + t * self.dir;
     }
 }
-
-impl Default for Ray {
-    fn default() -> Self {
-        Self {
-            origin : Vec3::default(),
-            dir : Vec3::default()
-        }
-    }
-}
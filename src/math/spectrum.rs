@@ -0,0 +1,386 @@
+use std::ops::{Add, AddAssign, Mul, MulAssign, Index, IndexMut};
+use crate::math::vec3::Color3;
+
+//Visible range that wavelengths are sampled from, in nanometres.
+pub const LAMBDA_MIN : f64 = 380.0;
+pub const LAMBDA_MAX : f64 = 780.0;
+
+//Wavelengths carried by every spectral path, the hero wavelength and the ones rotated from it.
+pub const SPECTRUM_SAMPLES : usize = 4;
+
+//Integral of the y colour matching function below over the visible range, so a constant spectrum of 1 has Y = 1.
+const CIE_Y_INTEGRAL : f64 = 106.919735;
+
+//Integral of D65 times the y colour matching function divided by CIE_Y_INTEGRAL, scaling D65 to Y = 1.
+const D65_Y : f64 = 98.853110;
+
+//CIE standard illuminant D65 every 10nm from 380 to 780nm.
+const D65 : [f64; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92,
+    108.81, 109.35, 107.80, 104.79, 107.69, 104.41, 104.05, 100.00, 96.33, 95.79,
+    88.69, 90.01, 89.60, 87.70, 83.29, 83.70, 80.03, 80.21, 82.28, 78.28,
+    69.72, 71.61, 74.35, 61.60, 69.89, 75.09, 63.59, 46.42, 66.81, 63.38
+];
+
+//Smits' basis spectra for turning RGB into a smooth reflectance, 10 bins evenly spread from 380 to 720nm.
+const SMITS_WHITE : [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN : [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA : [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW : [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED : [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN : [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE : [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+//Wavelengths a single path carries. The hero wavelength is sampled uniformly and the others are spread evenly across
+//the range from it, so one path covers the whole spectrum. Once something wavelength dependent happens to the path,
+//like refraction through dispersive glass, only the hero can carry on and the others are terminated.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledWavelengths {
+    lambda : [f64; SPECTRUM_SAMPLES],
+    pdf : [f64; SPECTRUM_SAMPLES]
+}
+
+impl SampledWavelengths {
+    //u is a uniform random number in [0, 1).
+    pub fn sample_uniform(u : f64) -> Self {
+        let range : f64 = LAMBDA_MAX - LAMBDA_MIN;
+        let hero : f64 = LAMBDA_MIN + u * range;
+        let mut lambda : [f64; SPECTRUM_SAMPLES] = [hero; SPECTRUM_SAMPLES];
+        for (i, wavelength) in lambda.iter_mut().enumerate() {
+            *wavelength = LAMBDA_MIN + (hero - LAMBDA_MIN + i as f64 * range / SPECTRUM_SAMPLES as f64) % range;
+        }
+        return Self {lambda : lambda, pdf : [1.0 / range; SPECTRUM_SAMPLES]};
+    }
+
+    pub fn hero(&self) -> f64 {
+        return self.lambda[0];
+    }
+
+    pub fn lambda(&self, i : usize) -> f64 {
+        return self.lambda[i];
+    }
+
+    //Drops every wavelength but the hero, which then stands in for all of them.
+    pub fn terminate_secondary(&mut self) {
+        if self.is_terminated() {
+            return;
+        }
+        self.pdf[0] /= SPECTRUM_SAMPLES as f64;
+        for pdf in self.pdf.iter_mut().skip(1) {
+            *pdf = 0.0;
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        return self.pdf[1] == 0.0;
+    }
+}
+
+//Values of a spectrum at the wavelengths of a SampledWavelengths.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SampledSpectrum {
+    values : [f64; SPECTRUM_SAMPLES]
+}
+
+impl SampledSpectrum {
+    pub fn new(values : [f64; SPECTRUM_SAMPLES]) -> Self {
+        return Self {values : values};
+    }
+
+    pub fn constant(value : f64) -> Self {
+        return Self {values : [value; SPECTRUM_SAMPLES]};
+    }
+
+    //Smooth reflectance with the given RGB albedo, using Smits' method. Scaling the color scales the spectrum.
+    pub fn from_reflectance(rgb : &Color3, wavelengths : &SampledWavelengths) -> Self {
+        let mut values : [f64; SPECTRUM_SAMPLES] = [0.0; SPECTRUM_SAMPLES];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = rgb_to_reflectance(rgb, wavelengths.lambda(i));
+        }
+        return Self {values : values};
+    }
+
+    //Emission spectrum of an RGB light: the reflectance of the color lighting a D65 white, so (1, 1, 1) comes back as
+    //white on the film.
+    pub fn from_illuminant(rgb : &Color3, wavelengths : &SampledWavelengths) -> Self {
+        let mut values : [f64; SPECTRUM_SAMPLES] = [0.0; SPECTRUM_SAMPLES];
+        for (i, value) in values.iter_mut().enumerate() {
+            let lambda : f64 = wavelengths.lambda(i);
+            *value = rgb_to_reflectance(rgb, lambda) * d65(lambda) / D65_Y;
+        }
+        return Self {values : values};
+    }
+
+    pub fn max_value(&self) -> f64 {
+        return self.values.iter().cloned().fold(f64::MIN, f64::max);
+    }
+
+    pub fn is_black(&self) -> bool {
+        return self.values.iter().all(|value| *value == 0.0);
+    }
+
+    //Monte Carlo estimate of the spectrum's CIE XYZ tristimulus value, from the wavelengths it was sampled at.
+    pub fn to_xyz(&self, wavelengths : &SampledWavelengths) -> (f64, f64, f64) {
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for i in 0..SPECTRUM_SAMPLES {
+            if wavelengths.pdf[i] <= 0.0 {
+                continue;
+            }
+            let lambda : f64 = wavelengths.lambda(i);
+            let weight : f64 = self.values[i] / wavelengths.pdf[i];
+            x += weight * cie_x(lambda);
+            y += weight * cie_y(lambda);
+            z += weight * cie_z(lambda);
+        }
+        let scale : f64 = 1.0 / (SPECTRUM_SAMPLES as f64 * CIE_Y_INTEGRAL);
+        return (scale * x, scale * y, scale * z);
+    }
+
+    //Linear sRGB color of the spectrum, what the film records for it.
+    pub fn to_rgb(&self, wavelengths : &SampledWavelengths) -> Color3 {
+        let (x, y, z) = self.to_xyz(wavelengths);
+        return Color3::from_xyz(x, y, z);
+    }
+}
+
+impl Index<usize> for SampledSpectrum {
+    type Output = f64;
+    fn index(&self, i : usize) -> &f64 {
+        return &self.values[i];
+    }
+}
+
+impl IndexMut<usize> for SampledSpectrum {
+    fn index_mut(&mut self, i : usize) -> &mut f64 {
+        return &mut self.values[i];
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn add(self, other : SampledSpectrum) -> SampledSpectrum {
+        let mut values : [f64; SPECTRUM_SAMPLES] = self.values;
+        for (value, other) in values.iter_mut().zip(other.values.iter()) {
+            *value += other;
+        }
+        return SampledSpectrum {values : values};
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, other : SampledSpectrum) {
+        *self = *self + other;
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, other : SampledSpectrum) -> SampledSpectrum {
+        let mut values : [f64; SPECTRUM_SAMPLES] = self.values;
+        for (value, other) in values.iter_mut().zip(other.values.iter()) {
+            *value *= other;
+        }
+        return SampledSpectrum {values : values};
+    }
+}
+
+impl MulAssign for SampledSpectrum {
+    fn mul_assign(&mut self, other : SampledSpectrum) {
+        *self = *self * other;
+    }
+}
+
+impl Mul<SampledSpectrum> for f64 {
+    type Output = SampledSpectrum;
+    fn mul(self, spectrum : SampledSpectrum) -> SampledSpectrum {
+        return SampledSpectrum {values : spectrum.values.map(|value| self * value)};
+    }
+}
+
+//Spectrum of an ideal black body at a temperature in Kelvin, scaled to a luminance of 1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Blackbody {
+    temperature : f64,
+    normalization : f64
+}
+
+impl Blackbody {
+    pub fn new(temperature : f64) -> Self {
+        let temperature : f64 = temperature.max(1.0);
+        let unnormalized : Blackbody = Blackbody {temperature : temperature, normalization : 1.0};
+        let (_, y, _) = unnormalized.integrate_xyz();
+        return Blackbody {temperature : temperature, normalization : 1.0 / y.max(f64::MIN_POSITIVE)};
+    }
+
+    pub fn temperature(&self) -> f64 {
+        return self.temperature;
+    }
+
+    pub fn radiance(&self, lambda : f64) -> f64 {
+        return self.normalization * planck(lambda, self.temperature);
+    }
+
+    //Linear sRGB color of the spectrum, for integrators that render in RGB. Very low temperatures fall outside sRGB
+    //and are clipped to it.
+    pub fn color(&self) -> Color3 {
+        let (x, y, z) = self.integrate_xyz();
+        let rgb : Color3 = Color3::from_xyz(x, y, z);
+        return Color3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0));
+    }
+
+    //XYZ of the spectrum integrated in 1nm steps.
+    fn integrate_xyz(&self) -> (f64, f64, f64) {
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        let steps : usize = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        for step in 0..steps {
+            let lambda : f64 = LAMBDA_MIN + step as f64 + 0.5;
+            let radiance : f64 = self.radiance(lambda);
+            x += radiance * cie_x(lambda);
+            y += radiance * cie_y(lambda);
+            z += radiance * cie_z(lambda);
+        }
+        return (x / CIE_Y_INTEGRAL, y / CIE_Y_INTEGRAL, z / CIE_Y_INTEGRAL);
+    }
+}
+
+//Shape of the light a light source emits across the spectrum. Its brightness comes from the source's RGB color,
+//which for a black body is the color of its spectrum.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum EmissionSpectrum {
+    #[default]
+    Rgb, //Upsampled from the RGB color
+    Blackbody(Blackbody)
+}
+
+impl EmissionSpectrum {
+    //Spectrum of emitted light with the RGB value rgb.
+    pub fn sample(&self, rgb : &Color3, wavelengths : &SampledWavelengths) -> SampledSpectrum {
+        match self {
+            EmissionSpectrum::Rgb => SampledSpectrum::from_illuminant(rgb, wavelengths),
+            EmissionSpectrum::Blackbody(blackbody) => {
+                let mut values : [f64; SPECTRUM_SAMPLES] = [0.0; SPECTRUM_SAMPLES];
+                for (i, value) in values.iter_mut().enumerate() {
+                    *value = rgb.luminance() * blackbody.radiance(wavelengths.lambda(i));
+                }
+                SampledSpectrum::new(values)
+            }
+        }
+    }
+}
+
+//Linear sRGB color of a surface with the given reflectance spectrum, lit by D65 so a reflectance of 1 is white. For
+//wavelength dependent effects rendered in RGB, integrated in 10nm steps.
+pub fn reflectance_to_rgb<F : Fn(f64) -> f64>(reflectance : F) -> Color3 {
+    let (mut x, mut y, mut z, mut white) = (0.0, 0.0, 0.0, 0.0);
+    let mut lambda : f64 = LAMBDA_MIN + 5.0;
+    while lambda < LAMBDA_MAX {
+        let illuminant : f64 = d65(lambda);
+        let reflected : f64 = illuminant * reflectance(lambda);
+        x += reflected * cie_x(lambda);
+        y += reflected * cie_y(lambda);
+        z += reflected * cie_z(lambda);
+        white += illuminant * cie_y(lambda);
+        lambda += 10.0;
+    }
+    return Color3::from_xyz(x / white, y / white, z / white);
+}
+
+//Analytic fits of the CIE 1931 2 degree colour matching functions (Wyman, Sloan and Shirley 2013).
+pub fn cie_x(lambda : f64) -> f64 {
+    return 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+}
+
+pub fn cie_y(lambda : f64) -> f64 {
+    return 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+}
+
+pub fn cie_z(lambda : f64) -> f64 {
+    return 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+}
+
+//Gaussian with a different width either side of its mean.
+fn lobe(x : f64, mean : f64, sigma_below : f64, sigma_above : f64) -> f64 {
+    let t : f64 = (x - mean) / if x < mean {sigma_below} else {sigma_above};
+    return (-0.5 * t * t).exp();
+}
+
+//Spectral radiance of a black body by Planck's law, for a wavelength in nanometres.
+pub fn planck(lambda : f64, temperature : f64) -> f64 {
+    const C : f64 = 299792458.0;
+    const H : f64 = 6.62606957e-34;
+    const KB : f64 = 1.3806488e-23;
+    let l : f64 = lambda * 1e-9;
+    return (2.0 * H * C * C) / (l.powi(5) * ((H * C / (l * KB * temperature)).exp() - 1.0));
+}
+
+fn d65(lambda : f64) -> f64 {
+    let t : f64 = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+    let i : usize = (t as usize).min(D65.len() - 2);
+    let f : f64 = t - i as f64;
+    return (1.0 - f) * D65[i] + f * D65[i + 1];
+}
+
+//Smits' RGB to spectrum conversion: white, plus the secondary and primary colors that make up the rest.
+pub fn rgb_to_reflectance(rgb : &Color3, lambda : f64) -> f64 {
+    let bin : usize = (((lambda - LAMBDA_MIN) / 34.0).max(0.0) as usize).min(9);
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    if r <= g && r <= b {
+        let base : f64 = r * SMITS_WHITE[bin];
+        return if g <= b {base + (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]} else {base + (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]};
+    }
+    if g <= r && g <= b {
+        let base : f64 = g * SMITS_WHITE[bin];
+        return if r <= b {base + (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]} else {base + (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]};
+    }
+    let base : f64 = b * SMITS_WHITE[bin];
+    return if r <= g {base + (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]} else {base + (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Average of estimate over wavelengths stratified across the visible range.
+    fn average<F : Fn(SampledWavelengths) -> Color3>(estimate : F) -> Color3 {
+        let samples : usize = 4000;
+        let mut sum : Color3 = Color3::default();
+        for i in 0..samples {
+            sum += estimate(SampledWavelengths::sample_uniform((i as f64 + 0.5) / samples as f64));
+        }
+        return (1.0 / samples as f64) * sum;
+    }
+
+    fn assert_close(a : &Color3, b : &Color3, tolerance : f64) {
+        for channel in 0..3 {
+            assert!((a[channel] - b[channel]).abs() < tolerance, "{:?} is not close to {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn white_illuminant_comes_back_white() {
+        let white : Color3 = Color3::new(1.0, 1.0, 1.0);
+        let rgb : Color3 = average(|wavelengths| SampledSpectrum::from_illuminant(&white, &wavelengths).to_rgb(&wavelengths));
+        assert_close(&rgb, &white, 0.005);
+    }
+
+    #[test]
+    fn terminating_secondary_wavelengths_keeps_the_expected_color() {
+        let color : Color3 = Color3::new(0.2, 0.5, 0.9);
+        let full : Color3 = average(|wavelengths| SampledSpectrum::from_illuminant(&color, &wavelengths).to_rgb(&wavelengths));
+        let hero_only : Color3 = average(|mut wavelengths| {
+            wavelengths.terminate_secondary();
+            SampledSpectrum::from_illuminant(&color, &wavelengths).to_rgb(&wavelengths)
+        });
+        assert_close(&hero_only, &full, 1e-3);
+    }
+
+    #[test]
+    fn terminating_twice_changes_nothing() {
+        let mut once : SampledWavelengths = SampledWavelengths::sample_uniform(0.3);
+        once.terminate_secondary();
+        let mut twice : SampledWavelengths = once;
+        twice.terminate_secondary();
+        assert!(twice.is_terminated());
+        assert_eq!(once.pdf, twice.pdf);
+    }
+}
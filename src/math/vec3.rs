@@ -1,7 +1,6 @@
 use std::ops;
 use std::fmt;
-use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
 use rand::Rng;
 use super::interval::Interval;
 
@@ -95,6 +94,7 @@ impl Vec3 {
         return self[0] * other[0] + self[1] * other[1] + self[2] * other[2];
     }
 
+    #[allow(clippy::neg_multiply)]
     pub fn negate(&self) -> Vec3 {
         Vec3 {
             vec : [
@@ -135,6 +135,7 @@ impl Vec3 {
         return *self - 2.0*Vec3::dot(self, normal)*(*normal);
     }
 
+    #[allow(clippy::neg_multiply)]
     pub fn refract(uv : &Vec3, normal : &Vec3, refractive_ratio : f64) -> Vec3 {
         let cos_theta : f64 = Vec3::dot(&uv.negate(), normal).min(1.0);
         let r_out_perp : Vec3 = refractive_ratio * (cos_theta*(*normal) + *uv);
@@ -149,26 +150,37 @@ impl Color3 {
         return linear_component.sqrt();
     }
 
-    //Should only be used by color3 variables, can still be used by vec3's though.
-    pub fn write_color(&self, f : &mut fs::File, samples_per_pixel : u32) {
+    //Linear sRGB (D65) color of a CIE XYZ tristimulus value.
+    pub fn from_xyz(x : f64, y : f64, z : f64) -> Color3 {
+        return Color3::new(
+            3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+            -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+            0.0556434 * x - 0.2040259 * y + 1.0572252 * z
+        );
+    }
+
+    //Relative luminance of a linear sRGB color.
+    pub fn luminance(&self) -> f64 {
+        return 0.2126 * self[0] + 0.7152 * self[1] + 0.0722 * self[2];
+    }
+
+    //Gamma corrected 8 bit components of a color that has already been averaged over its samples.
+    pub fn to_rgb8(&self) -> [u8; 3] {
         static INTENSITY : Interval = Interval::new(0.0, 0.999);
-        
-        let mut r : f64 = self[0];
-        let mut g : f64 = self[1];
-        let mut b : f64 = self[2];
+        return [
+            (256.0 * INTENSITY.clamp(Color3::linear_to_gamma(self[0]))) as u8,
+            (256.0 * INTENSITY.clamp(Color3::linear_to_gamma(self[1]))) as u8,
+            (256.0 * INTENSITY.clamp(Color3::linear_to_gamma(self[2]))) as u8
+        ];
+    }
 
+    //Should only be used by color3 variables, can still be used by vec3's though.
+    pub fn write_color<W : Write>(&self, f : &mut W, samples_per_pixel : u32) -> io::Result<()> {
         //Divide the color by the number of samples
         let scale : f64 = 1.0 / (samples_per_pixel as f64);
-        r *= scale;
-        g *= scale;
-        b *= scale;
-
-        //Gamma correction
-        r = Color3::linear_to_gamma(r);
-        g = Color3::linear_to_gamma(g);
-        b = Color3::linear_to_gamma(b);
+        let [r, g, b] = (*self * scale).to_rgb8();
         
-        writeln!(f, "{} {} {}", (256.0 * INTENSITY.clamp(r)) as u32, (256.0 * INTENSITY.clamp(g)) as u32, (256.0* INTENSITY.clamp(b)) as u32).expect("Failed to write to file");
+        return writeln!(f, "{} {} {}", r, g, b);
     }
 }
 
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::math::vec3::Vec3;
+use crate::shapes::hittable::HittableList;
+use super::camera::{Camera, CameraBuilder, CameraError};
+use super::progress::{CancellationToken, ProgressReporter};
+use super::stats::RenderStatistics;
+
+//Camera parameters at one point in time. Frames don't have to be integers, a key can sit between two frames.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraKeyframe {
+    pub frame : f64,
+    pub eye : Vec3,
+    pub target : Vec3,
+    pub up : Vec3,
+    pub fov : f64,
+    pub focus_distance : f64,
+    pub defocus_angle : f64 //Thin lens aperture, as in CameraBuilder::defocus_angle
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom //Passes through every key with a continuous first derivative, the end keys are repeated as tangents
+}
+
+#[derive(Debug)]
+pub enum AnimationError {
+    NoKeyframes,
+    Camera {frame : f64, error : CameraError},
+    Image {frame : u32, error : image::ImageError}
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnimationError::NoKeyframes => write!(f, "camera animation has no keyframes"),
+            AnimationError::Camera { frame, error } => write!(f, "frame {}: {}", frame, error),
+            AnimationError::Image { frame, error } => write!(f, "frame {}: could not write image: {}", frame, error)
+        }
+    }
+}
+
+impl std::error::Error for AnimationError {}
+
+//Keyframed camera. Everything that isn't keyframed (resolution, samples, projection, aperture shape) comes from the base builder.
+#[derive(Clone)]
+pub struct CameraAnimation {
+    base : CameraBuilder,
+    keyframes : Vec<CameraKeyframe>,
+    pub interpolation : Interpolation
+}
+
+impl CameraAnimation {
+    pub fn new(base : CameraBuilder, interpolation : Interpolation) -> Self {
+        return Self {base : base, keyframes : Vec::new(), interpolation : interpolation};
+    }
+
+    //Keys are kept sorted by frame, a key on the same frame as an existing one replaces it.
+    pub fn add_keyframe(&mut self, keyframe : CameraKeyframe) {
+        match self.keyframes.binary_search_by(|key| key.frame.total_cmp(&keyframe.frame)) {
+            Ok(index) => self.keyframes[index] = keyframe,
+            Err(index) => self.keyframes.insert(index, keyframe)
+        }
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        return &self.keyframes;
+    }
+
+    //Interpolated parameters at a frame, held constant before the first key and after the last.
+    pub fn sample(&self, frame : f64) -> Option<CameraKeyframe> {
+        let keys : &[CameraKeyframe] = &self.keyframes;
+        let first : &CameraKeyframe = keys.first()?;
+        let last : &CameraKeyframe = keys.last()?;
+        if frame <= first.frame {
+            return Some(*first);
+        }
+        if frame >= last.frame {
+            return Some(*last);
+        }
+
+        //Segment k1 -> k2 containing the frame, with its neighbours for the spline tangents.
+        let index : usize = keys.partition_point(|key| key.frame <= frame);
+        let (k1, k2) = (&keys[index - 1], &keys[index]);
+        let t : f64 = (frame - k1.frame) / (k2.frame - k1.frame);
+
+        let mut keyframe : CameraKeyframe = match self.interpolation {
+            Interpolation::Linear => CameraKeyframe {
+                frame : frame,
+                eye : k1.eye + t * (k2.eye - k1.eye),
+                target : k1.target + t * (k2.target - k1.target),
+                up : k1.up + t * (k2.up - k1.up),
+                fov : lerp(k1.fov, k2.fov, t),
+                focus_distance : lerp(k1.focus_distance, k2.focus_distance, t),
+                defocus_angle : lerp(k1.defocus_angle, k2.defocus_angle, t)
+            },
+            Interpolation::CatmullRom => {
+                let k0 : &CameraKeyframe = if index >= 2 {&keys[index - 2]} else {k1};
+                let k3 : &CameraKeyframe = if index + 1 < keys.len() {&keys[index + 1]} else {k2};
+                let frames : [f64; 4] = [k0.frame, k1.frame, k2.frame, k3.frame];
+                CameraKeyframe {
+                    frame : frame,
+                    eye : catmull_rom_vec([k0.eye, k1.eye, k2.eye, k3.eye], frames, t),
+                    target : catmull_rom_vec([k0.target, k1.target, k2.target, k3.target], frames, t),
+                    up : catmull_rom_vec([k0.up, k1.up, k2.up, k3.up], frames, t),
+                    fov : catmull_rom([k0.fov, k1.fov, k2.fov, k3.fov], frames, t),
+                    focus_distance : catmull_rom([k0.focus_distance, k1.focus_distance, k2.focus_distance, k3.focus_distance], frames, t),
+                    defocus_angle : catmull_rom([k0.defocus_angle, k1.defocus_angle, k2.defocus_angle, k3.defocus_angle], frames, t)
+                }
+            }
+        };
+
+        //Overshoot from the spline must not produce a negative aperture.
+        keyframe.defocus_angle = keyframe.defocus_angle.max(0.0);
+        return Some(keyframe);
+    }
+
+    pub fn camera_at(&self, frame : f64) -> Result<Camera, AnimationError> {
+        let keyframe : CameraKeyframe = self.sample(frame).ok_or(AnimationError::NoKeyframes)?;
+        return self.base.clone()
+            .look_at(keyframe.eye, keyframe.target, keyframe.up)
+            .fov(keyframe.fov)
+            .focus_distance(keyframe.focus_distance)
+            .defocus_angle(keyframe.defocus_angle)
+            .build()
+            .map_err(|error| AnimationError::Camera {frame : frame, error : error});
+    }
+}
+
+fn lerp(a : f64, b : f64, t : f64) -> f64 {
+    return a + t * (b - a);
+}
+
+//Cubic Hermite curve from values[1] to values[2] at t in [0, 1]. The Catmull-Rom tangents are slopes over the frames
+//between the neighbouring keys, scaled to this segment's length, so the camera keeps its speed through a key even
+//when the keys around it are unevenly spaced.
+fn catmull_rom(values : [f64; 4], frames : [f64; 4], t : f64) -> f64 {
+    let span : f64 = frames[2] - frames[1];
+    let m1 : f64 = span * (values[2] - values[0]) / (frames[2] - frames[0]);
+    let m2 : f64 = span * (values[3] - values[1]) / (frames[3] - frames[1]);
+    let t2 : f64 = t * t;
+    let t3 : f64 = t2 * t;
+    return (2.0*t3 - 3.0*t2 + 1.0) * values[1] + (t3 - 2.0*t2 + t) * m1 + (3.0*t2 - 2.0*t3) * values[2] + (t3 - t2) * m2;
+}
+
+fn catmull_rom_vec(values : [Vec3; 4], frames : [f64; 4], t : f64) -> Vec3 {
+    let channel = |i : usize| catmull_rom([values[0][i], values[1][i], values[2][i], values[3][i]], frames, t);
+    return Vec3::new(channel(0), channel(1), channel(2));
+}
+
+#[derive(Clone, Debug)]
+pub struct SequenceOptions {
+    pub first_frame : u32,
+    pub last_frame : u32, //Inclusive
+    pub output_pattern : String, //The run of # characters is replaced by the zero padded frame number, e.g. image.####.png
+    pub skip_existing : bool
+}
+
+impl SequenceOptions {
+    pub fn new(first_frame : u32, last_frame : u32) -> Self {
+        return Self {first_frame : first_frame, last_frame : last_frame, output_pattern : String::from("image.####.png"), skip_existing : false};
+    }
+
+    pub fn frame_path(&self, frame : u32) -> PathBuf {
+        return PathBuf::from(frame_file_name(&self.output_pattern, frame));
+    }
+}
+
+//Replaces the first run of # with the frame number padded to the run's length. Without a run the number goes before the extension.
+pub fn frame_file_name(pattern : &str, frame : u32) -> String {
+    if let Some(start) = pattern.find('#') {
+        let width : usize = pattern[start..].chars().take_while(|c| *c == '#').count();
+        return format!("{}{:0width$}{}", &pattern[..start], frame, &pattern[start + width..], width = width);
+    }
+
+    return match Path::new(pattern).extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!("{}.{:04}.{}", &pattern[..pattern.len() - extension.len() - 1], frame, extension),
+        None => format!("{}.{:04}", pattern, frame)
+    };
+}
+
+#[derive(Default, Debug)]
+pub struct SequenceSummary {
+    pub rendered : Vec<u32>,
+    pub skipped : Vec<u32>,
+    pub statistics : RenderStatistics
+}
+
+//Renders and writes every frame in the range. Each frame reports its progress per tile as it renders, and a cancelled
+//token stops the sequence after the frame in flight (which is not written, since it is incomplete).
+pub fn render_sequence(animation : &CameraAnimation, world : HittableList, options : &SequenceOptions, progress : Option<Arc<dyn ProgressReporter>>, cancel : Option<CancellationToken>) -> Result<SequenceSummary, AnimationError> {
+    let world : Arc<HittableList> = Arc::new(world);
+    let cancel : CancellationToken = cancel.unwrap_or_default();
+    let mut summary : SequenceSummary = SequenceSummary::default();
+
+    for frame in options.first_frame..=options.last_frame {
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        let path : PathBuf = options.frame_path(frame);
+        if options.skip_existing && path.exists() {
+            summary.skipped.push(frame);
+            continue;
+        }
+
+        let camera : Camera = animation.camera_at(frame as f64)?;
+        let (framebuffer, statistics) = camera.render_shared(Arc::clone(&world), None, progress.clone(), Some(cancel.clone()));
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        framebuffer.save(&path).map_err(|error| AnimationError::Image {frame : frame, error : error})?;
+        summary.statistics.merge(statistics);
+        summary.rendered.push(frame);
+    }
+
+    return Ok(summary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catmull_rom_passes_through_keys() {
+        let values : [f64; 4] = [0.0, 1.0, 4.0, 2.0];
+        let frames : [f64; 4] = [0.0, 1.0, 5.0, 6.0];
+        assert!((catmull_rom(values, frames, 0.0) - 1.0).abs() < 1e-12);
+        assert!((catmull_rom(values, frames, 1.0) - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn catmull_rom_speed_is_continuous_across_unevenly_spaced_keys() {
+        //Keys at frames 0, 1, 5 and 6. The segment ending at frame 5 and the one starting there must agree on the rate
+        //of change per frame.
+        let (values, frames) : ([f64; 5], [f64; 5]) = ([0.0, 1.0, 4.0, 2.0, 3.0], [0.0, 1.0, 5.0, 6.0, 7.0]);
+        let h : f64 = 1e-6;
+        let before : f64 = (catmull_rom([values[0], values[1], values[2], values[3]], [frames[0], frames[1], frames[2], frames[3]], 1.0)
+            - catmull_rom([values[0], values[1], values[2], values[3]], [frames[0], frames[1], frames[2], frames[3]], 1.0 - h)) / (h * 4.0);
+        let after : f64 = (catmull_rom([values[1], values[2], values[3], values[4]], [frames[1], frames[2], frames[3], frames[4]], h)
+            - catmull_rom([values[1], values[2], values[3], values[4]], [frames[1], frames[2], frames[3], frames[4]], 0.0)) / (h * 1.0);
+        assert!((before - after).abs() < 1e-4, "{} per frame before the key, {} after", before, after);
+    }
+
+    #[test]
+    fn catmull_rom_matches_the_uniform_spline_for_even_keys() {
+        let values : [f64; 4] = [0.5, -1.0, 2.0, 3.0];
+        let t : f64 = 0.3;
+        let [p0, p1, p2, p3] = values;
+        let uniform : f64 = 0.5 * ((2.0 * p1) + (p2 - p0) * t + (2.0*p0 - 5.0*p1 + 4.0*p2 - p3) * t * t + (3.0*p1 - p0 - 3.0*p2 + p3) * t * t * t);
+        assert!((catmull_rom(values, [0.0, 1.0, 2.0, 3.0], t) - uniform).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,219 @@
+use std::path::Path;
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, Image, SmallVec, WritableImage};
+use crate::math::vec3::{Vec3, Point3, Color3};
+use crate::math::ray::Ray;
+use crate::shapes::hittable::HitRecord;
+use super::framebuffer::{Framebuffer, Splats};
+
+//Light passes the beauty image is split into. Every bit of light a sample finds goes into exactly one of them, so
+//together they add back up to the beauty.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LightPass {
+    DirectDiffuse, //Light reaching a diffuse first hit straight from a light or the background
+    IndirectDiffuse, //Light reaching a diffuse first hit after bouncing off other surfaces
+    Specular, //Everything seen through a specular first hit, reflections and refractions of glass and metal
+    Emission //Light sources seen directly, for now only the background
+}
+
+//What a single camera sample contributes to the arbitrary output variables (AOVs): its light split by pass and the
+//first surface it hit.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AovSample {
+    pub direct_diffuse : Color3,
+    pub indirect_diffuse : Color3,
+    pub specular : Color3,
+    pub emission : Color3,
+    pub surface : Option<SurfaceSample>
+}
+
+//Surface data of a camera ray's first hit.
+#[derive(Copy, Clone, Debug)]
+pub struct SurfaceSample {
+    pub albedo : Color3,
+    pub normal : Vec3, //Shading normal, facing the camera
+    pub position : Point3,
+    pub depth : f64, //Distance from the ray origin
+    pub object_id : usize
+}
+
+impl AovSample {
+    pub fn add(&mut self, pass : LightPass, light : Color3) {
+        match pass {
+            LightPass::DirectDiffuse => self.direct_diffuse += light,
+            LightPass::IndirectDiffuse => self.indirect_diffuse += light,
+            LightPass::Specular => self.specular += light,
+            LightPass::Emission => self.emission += light
+        }
+    }
+
+    //Keeps the first surface recorded, later bounces of the same path are ignored.
+    pub fn record_surface(&mut self, ray : &Ray, rec : &HitRecord) {
+        if self.surface.is_none() {
+            self.surface = Some(SurfaceSample {
+                albedo : rec.material.albedo(),
+                normal : rec.normal,
+                position : rec.p,
+                depth : rec.t * ray.dir.length(),
+                object_id : rec.object_id
+            });
+        }
+    }
+}
+
+//Running sums of the AOV samples taken in one pixel.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct AovPixel {
+    light : [Color3; 4],
+    albedo : Color3,
+    normal : Vec3,
+    position : Vec3,
+    depth : f64,
+    hits : u32,
+    object_id : Option<usize> //Of the first sample that hit something, ids can't be averaged
+}
+
+impl AovPixel {
+    pub(crate) fn add(&mut self, sample : &AovSample) {
+        self.light[0] += sample.direct_diffuse;
+        self.light[1] += sample.indirect_diffuse;
+        self.light[2] += sample.specular;
+        self.light[3] += sample.emission;
+        if let Some(surface) = &sample.surface {
+            self.albedo += surface.albedo;
+            self.normal += surface.normal;
+            self.position += surface.position;
+            self.depth += surface.depth;
+            self.hits += 1;
+            self.object_id = self.object_id.or(Some(surface.object_id));
+        }
+    }
+
+    //Light passes are scaled by light_scale like the beauty, surface data is averaged over the samples that hit.
+    pub(crate) fn resolve(&self, layers : &mut AovLayers, x : u32, y : u32, light_scale : f64) {
+        layers.direct_diffuse.set(x, y, light_scale * self.light[0]);
+        layers.indirect_diffuse.set(x, y, light_scale * self.light[1]);
+        layers.specular.set(x, y, light_scale * self.light[2]);
+        layers.emission.set(x, y, light_scale * self.light[3]);
+        if self.hits == 0 {
+            return;
+        }
+
+        let surface_scale : f64 = 1.0 / (self.hits as f64);
+        let depth : f64 = surface_scale * self.depth;
+        let id : f64 = self.object_id.map_or(0.0, |id| (id + 1) as f64);
+        layers.albedo.set(x, y, surface_scale * self.albedo);
+        layers.normal.set(x, y, if self.normal.near_zero() {Vec3::default()} else {self.normal.unit_vector()});
+        layers.position.set(x, y, surface_scale * self.position);
+        layers.depth.set(x, y, Color3::new(depth, depth, depth));
+        layers.object_id.set(x, y, Color3::new(id, id, id));
+    }
+}
+
+//AOV images of a render. The light passes add up to the beauty image, before any denoising; the surface layers hold
+//the data of the first hit in each pixel and are black where nothing was hit. Depth and object id (index in the
+//world's object list plus one, 0 for misses) are stored in all three channels.
+#[derive(Clone, Debug)]
+pub struct AovLayers {
+    pub direct_diffuse : Framebuffer,
+    pub indirect_diffuse : Framebuffer,
+    pub specular : Framebuffer,
+    pub emission : Framebuffer,
+    pub albedo : Framebuffer,
+    pub normal : Framebuffer,
+    pub depth : Framebuffer,
+    pub position : Framebuffer,
+    pub object_id : Framebuffer,
+    pub noisy : Option<Framebuffer> //Beauty as rendered, kept when a denoiser asked for it
+}
+
+impl AovLayers {
+    pub fn new(width : u32, height : u32) -> Self {
+        let empty : Framebuffer = Framebuffer::new(width, height);
+        return Self {
+            direct_diffuse : empty.clone(),
+            indirect_diffuse : empty.clone(),
+            specular : empty.clone(),
+            emission : empty.clone(),
+            albedo : empty.clone(),
+            normal : empty.clone(),
+            depth : empty.clone(),
+            position : empty.clone(),
+            object_id : empty,
+            noisy : None
+        };
+    }
+
+    //Every layer with the name it is saved under.
+    pub fn layers(&self) -> [(&'static str, &Framebuffer); 9] {
+        return [
+            ("direct_diffuse", &self.direct_diffuse),
+            ("indirect_diffuse", &self.indirect_diffuse),
+            ("specular", &self.specular),
+            ("emission", &self.emission),
+            ("albedo", &self.albedo),
+            ("normal", &self.normal),
+            ("depth", &self.depth),
+            ("position", &self.position),
+            ("object_id", &self.object_id)
+        ];
+    }
+
+    //Sum of the light passes, the beauty image they were split from.
+    pub fn light_sum(&self) -> Framebuffer {
+        let mut sum : Framebuffer = self.direct_diffuse.clone();
+        for pass in [&self.indirect_diffuse, &self.specular, &self.emission] {
+            for (pixel, light) in sum.pixels_mut().iter_mut().zip(pass.pixels().iter()) {
+                *pixel += *light;
+            }
+        }
+        return sum;
+    }
+
+    //Adds light splatted during the render to the indirect diffuse pass, to keep it in step with the beauty.
+    pub(crate) fn add_splats(&mut self, splats : &Splats, scale : f64) {
+        self.indirect_diffuse.add_splats(splats, scale);
+    }
+
+    //Saves every layer as its own image named after it, e.g. directory/albedo.png. The format comes from the extension
+    //as in Framebuffer::save; 8 bit formats clip depth, position and object id, which only write_exr keeps intact.
+    pub fn save_images<P : AsRef<Path>>(&self, directory : P, extension : &str) -> image::ImageResult<()> {
+        for (name, layer) in self.layers() {
+            layer.save(directory.as_ref().join(format!("{}.{}", name, extension)))?;
+        }
+        if let Some(noisy) = &self.noisy {
+            noisy.save(directory.as_ref().join(format!("noisy.{}", extension)))?;
+        }
+        return Ok(());
+    }
+
+    //Writes the beauty and every layer into one multi-layer OpenEXR file in linear 32 bit float. The beauty is the
+    //default R, G, B channels and layers are prefixed with their name (albedo.R, ...), apart from depth.Z and
+    //object_id.id which have a single channel each.
+    pub fn write_exr<P : AsRef<Path>>(&self, beauty : &Framebuffer, path : P) -> exr::error::UnitResult {
+        let mut channels : Vec<AnyChannel<FlatSamples>> = Vec::new();
+        push_rgb(&mut channels, "", beauty);
+        for (name, layer) in self.layers() {
+            match name {
+                "depth" => channels.push(AnyChannel::new("depth.Z", channel_samples(layer, 0))),
+                "object_id" => channels.push(AnyChannel::new("object_id.id", channel_samples(layer, 0))),
+                _ => push_rgb(&mut channels, &format!("{}.", name), layer)
+            }
+        }
+        if let Some(noisy) = &self.noisy {
+            push_rgb(&mut channels, "noisy.", noisy);
+        }
+
+        let size : (usize, usize) = (beauty.width as usize, beauty.height as usize);
+        return Image::from_channels(size, AnyChannels::sort(SmallVec::from_vec(channels))).write().to_file(path);
+    }
+}
+
+fn push_rgb(channels : &mut Vec<AnyChannel<FlatSamples>>, prefix : &str, layer : &Framebuffer) {
+    for (axis, name) in ["R", "G", "B"].iter().enumerate() {
+        channels.push(AnyChannel::new(format!("{}{}", prefix, name).as_str(), channel_samples(layer, axis)));
+    }
+}
+
+fn channel_samples(layer : &Framebuffer, axis : usize) -> FlatSamples {
+    return FlatSamples::F32(layer.pixels().iter().map(|pixel| pixel[axis] as f32).collect());
+}
@@ -0,0 +1,125 @@
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+use rand::Rng;
+
+//Grayscale transmission mask for custom bokeh shapes. The image is stretched over the unit square [-1, 1]^2, white
+//lets light through and black blocks it. Samples are drawn in proportion to the transmission of each texel.
+#[derive(Debug)]
+pub struct ApertureMask {
+    width : usize,
+    height : usize,
+    transmission : Vec<f64>,
+    cdf : Vec<f64> //Running sum of the transmission, normalized so the last entry is 1
+}
+
+impl ApertureMask {
+    //Texels are given row by row from the top left, values are clamped to [0, 1]. Returns None if nothing is transmitted.
+    pub fn new(width : usize, height : usize, transmission : Vec<f64>) -> Option<Self> {
+        let transmission : Vec<f64> = transmission.into_iter().map(|t| t.clamp(0.0, 1.0)).collect();
+        let mut cdf : Vec<f64> = Vec::with_capacity(transmission.len());
+        let mut total : f64 = 0.0;
+        for t in transmission.iter() {
+            total += t;
+            cdf.push(total);
+        }
+
+        if total <= 0.0 || transmission.len() != width * height {
+            return None;
+        }
+
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+
+        return Some(Self {width : width, height : height, transmission : transmission, cdf : cdf});
+    }
+
+    //Loads the luminance of an image file as the mask.
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.to_luma32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let transmission : Vec<f64> = image.pixels().map(|p| p.0[0] as f64).collect();
+
+        return ApertureMask::new(width, height, transmission).ok_or_else(|| {
+            image::ImageError::Parameter(image::error::ParameterError::from_kind(image::error::ParameterErrorKind::Generic(String::from("aperture mask is completely black"))))
+        });
+    }
+
+    pub fn transmission_at(&self, x : f64, y : f64) -> f64 {
+        if !(-1.0..=1.0).contains(&x) || !(-1.0..=1.0).contains(&y) {
+            return 0.0;
+        }
+        let column : usize = (((x + 1.0) / 2.0 * self.width as f64) as usize).min(self.width - 1);
+        let row : usize = (((1.0 - y) / 2.0 * self.height as f64) as usize).min(self.height - 1);
+        return self.transmission[row * self.width + column];
+    }
+
+    fn sample(&self) -> (f64, f64) {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let texel : usize = self.cdf.partition_point(|&c| c < rng.gen::<f64>()).min(self.cdf.len() - 1);
+        let column : f64 = (texel % self.width) as f64 + rng.gen::<f64>();
+        let row : f64 = (texel / self.width) as f64 + rng.gen::<f64>();
+        return (2.0 * column / self.width as f64 - 1.0, 1.0 - 2.0 * row / self.height as f64);
+    }
+}
+
+//Shape of the lens opening, defined on the unit disk and scaled by the lens radius.
+#[derive(Clone, Debug, Default)]
+pub enum Aperture {
+    #[default]
+    Circular,
+    Polygonal {blades : u32, rotation : f64}, //Regular polygon inscribed in the unit circle, rotation in degrees. Fewer than 3 blades are taken as 3
+    Mask(Arc<ApertureMask>)
+}
+
+impl Aperture {
+    pub fn polygonal(blades : u32, rotation : f64) -> Self {
+        return Aperture::Polygonal {blades : blades.max(3), rotation : rotation};
+    }
+
+    //Uniformly distributed point on the opening.
+    pub fn sample(&self) -> (f64, f64) {
+        match self {
+            Aperture::Circular => {
+                let p = crate::math::vec3::Vec3::random_in_unit_disk();
+                return (p[0], p[1]);
+            }
+
+            Aperture::Polygonal { blades, rotation } => {
+                //All blade triangles have the same area, so pick one and sample it uniformly.
+                let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+                let blades : u32 = (*blades).max(3);
+                let blade : f64 = rng.gen_range(0..blades) as f64;
+                let step : f64 = 2.0 * PI / (blades as f64);
+                let angle : f64 = rotation.to_radians() + blade * step;
+
+                let (mut a, mut b) : (f64, f64) = (rng.gen(), rng.gen());
+                if a + b > 1.0 {
+                    a = 1.0 - a;
+                    b = 1.0 - b;
+                }
+                return (a * angle.cos() + b * (angle + step).cos(), a * angle.sin() + b * (angle + step).sin());
+            }
+
+            Aperture::Mask(mask) => mask.sample()
+        }
+    }
+
+    //Fraction of light let through at a point on the unit disk, used where the opening is a stop inside a lens.
+    pub fn transmission(&self, x : f64, y : f64) -> f64 {
+        match self {
+            Aperture::Circular => if x*x + y*y <= 1.0 {1.0} else {0.0},
+
+            Aperture::Polygonal { blades, rotation } => {
+                //Inside if the point is within the apothem of the blade edge facing it.
+                let step : f64 = 2.0 * PI / ((*blades).max(3) as f64);
+                let angle : f64 = (y.atan2(x) - rotation.to_radians()).rem_euclid(step) - step / 2.0;
+                let apothem : f64 = (step / 2.0).cos();
+                return if (x*x + y*y).sqrt() * angle.cos() <= apothem {1.0} else {0.0};
+            }
+
+            Aperture::Mask(mask) => mask.transmission_at(x, y)
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use rand::Rng;
+use crate::math::vec3::{Vec3, Point3, Color3};
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::math::distribution::Distribution1D;
+use crate::shapes::hittable::{HitRecord, Hittable, HittableList};
+use crate::lights::punctual::LightSample;
+use super::camera::Camera;
+use super::framebuffer::Splats;
+use super::integrator::{Integrator, background_radiance, sample_background, sample_lights};
+use super::projection::ImportanceSample;
+use super::aov::{AovSample, LightPass};
+use super::stats;
+
+//Bidirectional path tracing, after Veach's thesis and the structure of PBRT's implementation. Every camera sample
+//traces one subpath from the camera and one from a light, joins every pair of their vertices and weights each joined
+//path with the balance heuristic over all the ways it could have been sampled. Paths joined straight to the camera
+//land on some other pixel and are splatted onto the film.
+//
+//Point and spot lights get the full bidirectional treatment. Nothing can be traced out of the environment or a
+//directional light, so the camera subpath finds those the same way the path tracer does. Every material other than
+//Lambertian counts as specular: subpaths pass through it but can't be joined at it.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Bidirectional;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light(usize), //Index into the world's lights
+    Surface
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind : VertexKind,
+    rec : HitRecord, //Only p is used for camera and light vertices
+    beta : Color3, //Throughput of the subpath up to and including this vertex
+    delta : bool, //Specular surface, or a camera that light subpaths can't reach
+    pdf_fwd : f64, //Area density of the subpath's own sampling arriving here
+    pdf_rev : f64 //Area density of arriving here from the other end of the path
+}
+
+impl Vertex {
+    fn new(kind : VertexKind, p : Point3, beta : Color3) -> Self {
+        let rec : HitRecord = HitRecord {p : p, ..HitRecord::default()};
+        return Self {kind : kind, rec : rec, beta : beta, delta : false, pdf_fwd : 0.0, pdf_rev : 0.0};
+    }
+
+    fn p(&self) -> Point3 {
+        return self.rec.p;
+    }
+
+    fn is_connectible(&self) -> bool {
+        return match self.kind {
+            VertexKind::Light(_) => true,
+            _ => !self.delta
+        };
+    }
+
+    //BSDF times cosine for light travelling between this vertex and other. Lambertian surfaces are symmetric, so
+    //the same value serves both subpaths.
+    fn eval(&self, other : &Vertex) -> Color3 {
+        return self.rec.material.eval(&self.rec, &(other.p() - self.p()));
+    }
+
+    //Converts a solid angle density of leaving this vertex towards next into an area density at next.
+    fn convert_density(&self, pdf : f64, next : &Vertex) -> f64 {
+        let to_next : Vec3 = next.p() - self.p();
+        let distance_squared : f64 = to_next.length_squared();
+        if distance_squared <= 0.0 {
+            return 0.0;
+        }
+
+        let mut density : f64 = pdf / distance_squared;
+        if next.kind == VertexKind::Surface {
+            density *= Vec3::dot(&next.rec.normal, &to_next).abs() / distance_squared.sqrt();
+        }
+        return density;
+    }
+
+    //Area density at next of this vertex sending the path on towards it. Light subpaths pick their light at random
+    //while direct lighting tries every light, so the light's probability of being picked is part of its density.
+    fn pdf(&self, context : &Context, next : &Vertex) -> f64 {
+        let to_next : Vec3 = next.p() - self.p();
+        let pdf : f64 = match self.kind {
+            VertexKind::Camera => context.camera.projection().direction_pdf(context.camera.basis(), &Ray::new(self.p(), to_next)),
+            VertexKind::Light(index) => context.light_pdf(index) * context.world.lights[index].emission_pdf(&to_next),
+            VertexKind::Surface => if self.delta {0.0} else {self.rec.material.pdf(&self.rec, &to_next)}
+        };
+        return self.convert_density(pdf, next);
+    }
+}
+
+//What the vertices of one sample's subpaths need to know about the scene. Light subpaths start from a point or spot
+//light picked in proportion to its power.
+struct Context<'a> {
+    camera : &'a Camera,
+    world : &'a HittableList,
+    light_choice : Option<Distribution1D>
+}
+
+impl<'a> Context<'a> {
+    fn new(camera : &'a Camera, world : &'a HittableList) -> Self {
+        let power : Vec<f64> = world.lights.iter().map(|light| if light.is_positional() {light.power()} else {0.0}).collect();
+        let light_choice : Option<Distribution1D> = if power.iter().sum::<f64>() > 0.0 {Some(Distribution1D::new(&power))} else {None};
+        return Self {camera : camera, world : world, light_choice : light_choice};
+    }
+
+    //Index of the light that starts the light subpath and the probability of picking it.
+    fn choose_light(&self, u : f64) -> Option<(usize, f64)> {
+        return self.light_choice.as_ref().map(|distribution| distribution.sample_discrete(u));
+    }
+
+    fn light_pdf(&self, index : usize) -> f64 {
+        return self.light_choice.as_ref().map_or(0.0, |distribution| distribution.discrete_pdf(index));
+    }
+}
+
+impl Bidirectional {
+    //Extends a subpath from its first vertex until it leaves the scene, is absorbed, is ended by Russian roulette or
+    //has max_vertices vertices. A camera subpath passes radiance, which collects the environment and directional lights.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(&self, context : &Context, ray : Ray, beta : Color3, pdf : f64, max_vertices : usize, vertices : &mut Vec<Vertex>, mut radiance : Option<&mut Color3>) {
+        let (camera, world) = (context.camera, context.world);
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let mut ray : Ray = ray;
+        let mut beta : Color3 = beta;
+        let mut pdf_fwd : f64 = pdf;
+        let mut scatter_pdf : Option<f64> = None; //As in the path tracer, for weighting the environment
+
+        while vertices.len() < max_vertices {
+            let mut rec : HitRecord = HitRecord::default();
+            if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                if let Some(radiance) = radiance.as_deref_mut() {
+                    *radiance += beta * background_radiance(camera, &ray, scatter_pdf);
+                }
+                break;
+            }
+
+            let previous : usize = vertices.len() - 1;
+            if let VertexKind::Light(index) = vertices[previous].kind {
+                beta = world.lights[index].falloff_scale((rec.p - vertices[previous].p()).length()) * beta;
+            }
+
+            let mut vertex : Vertex = Vertex::new(VertexKind::Surface, rec.p, beta);
+            vertex.rec = rec.clone();
+            vertex.pdf_fwd = vertices[previous].convert_density(pdf_fwd, &vertex);
+            vertices.push(vertex);
+            let current : usize = previous + 1;
+
+            let specular : bool = rec.material.is_specular();
+            if let Some(radiance) = radiance.as_deref_mut() {
+                if !specular {
+                    *radiance += beta * (sample_background(camera, &rec, world) + sample_lights(&rec, world, |light| !light.is_positional()));
+                }
+            }
+            if vertices.len() >= max_vertices {
+                break;
+            }
+
+            let mut scattered : Ray = Ray::default();
+            let mut attenuation : Color3 = Color3::default();
+            if !rec.material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                break;
+            }
+
+            let pdf_rev : f64;
+            if specular {
+                vertices[current].delta = true;
+                pdf_fwd = 0.0;
+                pdf_rev = 0.0;
+                scatter_pdf = None;
+            }
+            else {
+                pdf_fwd = rec.material.pdf(&rec, &scattered.dir);
+                pdf_rev = rec.material.pdf(&rec, &ray.dir.negate());
+                scatter_pdf = Some(pdf_fwd);
+            }
+            beta = beta * attenuation;
+            vertices[previous].pdf_rev = vertices[current].convert_density(pdf_rev, &vertices[previous]);
+
+            if current >= camera.russian_roulette() as usize {
+                let survival : f64 = beta.x().max(beta.y()).max(beta.z()).min(0.95);
+                if rng.gen::<f64>() >= survival {
+                    break;
+                }
+                beta /= survival;
+            }
+
+            stats::count_secondary_ray();
+            ray = scattered;
+        }
+    }
+
+    //Weighted contribution of joining the first s light vertices to the first t camera vertices. Contributions for
+    //t = 1 land on another pixel and are splatted instead of returned.
+    fn connect(&self, context : &Context, light_vertices : &[Vertex], camera_vertices : &[Vertex], s : usize, t : usize, splats : &mut Splats) -> Color3 {
+        let (camera, world) = (context.camera, context.world);
+
+        if t == 1 {
+            let qs : &Vertex = &light_vertices[s - 1];
+            if camera_vertices[0].delta || !qs.is_connectible() {
+                return Color3::default();
+            }
+
+            let sample : ImportanceSample = match camera.projection().sample_importance(camera.basis(), &qs.p()) {
+                Some(sample) => sample,
+                None => return Color3::default()
+            };
+            let contribution : Color3 = sample.weight * (qs.beta * qs.rec.material.eval(&qs.rec, &sample.direction));
+            if contribution.near_zero() || !visible(world, qs.p(), sample.direction, sample.distance) {
+                return Color3::default();
+            }
+
+            let sampled : Vertex = Vertex::new(VertexKind::Camera, qs.p() + sample.distance * sample.direction, Color3::new(1.0, 1.0, 1.0));
+            let weight : f64 = self.mis_weight(context, light_vertices, camera_vertices, Some(&sampled), s, t);
+            splats.add(sample.s, sample.t, weight * contribution);
+            return Color3::default();
+        }
+
+        let pt : &Vertex = &camera_vertices[t - 1];
+        if !pt.is_connectible() {
+            return Color3::default();
+        }
+
+        if s == 1 {
+            //Direct lighting from every point and spot light, each one standing in for the light subpath's first vertex.
+            let mut direct : Color3 = Color3::default();
+            for (index, light) in world.lights.iter().enumerate().filter(|(_, light)| light.is_positional()) {
+                let sample : LightSample = match light.sample(&pt.p()) {
+                    Some(sample) => sample,
+                    None => continue
+                };
+
+                let contribution : Color3 = pt.beta * pt.rec.material.eval(&pt.rec, &sample.direction) * sample.radiance;
+                if contribution.near_zero() || !visible(world, pt.p(), sample.direction, sample.distance) {
+                    continue;
+                }
+
+                let sampled : Vertex = Vertex::new(VertexKind::Light(index), pt.p() + sample.distance * sample.direction, sample.radiance);
+                direct += self.mis_weight(context, light_vertices, camera_vertices, Some(&sampled), s, t) * contribution;
+            }
+            return direct;
+        }
+
+        let qs : &Vertex = &light_vertices[s - 1];
+        if !qs.is_connectible() {
+            return Color3::default();
+        }
+
+        let to_light : Vec3 = qs.p() - pt.p();
+        let distance : f64 = to_light.length();
+        let contribution : Color3 = (1.0 / (distance * distance)) * (qs.beta * qs.eval(pt) * pt.eval(qs) * pt.beta);
+        if contribution.near_zero() || !visible(world, pt.p(), to_light / distance, distance) {
+            return Color3::default();
+        }
+        return self.mis_weight(context, light_vertices, camera_vertices, None, s, t) * contribution;
+    }
+
+    //Balance heuristic weight of the (s, t) strategy. Walks outwards from the connection along both subpaths,
+    //accumulating the ratio of each neighbouring strategy's density to this one's.
+    fn mis_weight(&self, context : &Context, light_vertices : &[Vertex], camera_vertices : &[Vertex], sampled : Option<&Vertex>, s : usize, t : usize) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        let qs : &Vertex = if s == 1 {sampled.expect("s = 1 strategies sample a light vertex")} else {&light_vertices[s - 1]};
+        let pt : &Vertex = if t == 1 {sampled.expect("t = 1 strategies sample a camera vertex")} else {&camera_vertices[t - 1]};
+
+        //(pdf_fwd, pdf_rev, delta) of each vertex, with the changes this strategy makes at the ends of the subpaths.
+        let mut light : Vec<(f64, f64, bool)> = light_vertices.iter().take(s).map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        light.resize(s, (0.0, 0.0, false));
+        let mut camera_path : Vec<(f64, f64, bool)> = camera_vertices[..t].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        light[s - 1] = (qs.pdf_fwd, pt.pdf(context, qs), false);
+        camera_path[t - 1] = (pt.pdf_fwd, qs.pdf(context, pt), false);
+        if t > 1 {
+            camera_path[t - 2].1 = pt.pdf(context, &camera_vertices[t - 2]);
+        }
+        if s > 1 {
+            light[s - 2].1 = qs.pdf(context, &light_vertices[s - 2]);
+        }
+
+        let remap = |pdf : f64| if pdf != 0.0 {pdf} else {1.0};
+        let mut sum : f64 = 0.0;
+
+        let mut ratio : f64 = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera_path[i].1) / remap(camera_path[i].0);
+            if !camera_path[i].2 && !camera_path[i - 1].2 {
+                sum += ratio;
+            }
+        }
+
+        //Every light that starts a subpath is a point or spot light, which no path can hit, so there is no i = 0 term.
+        ratio = 1.0;
+        for i in (1..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            if !light[i].2 && !light[i - 1].2 {
+                sum += ratio;
+            }
+        }
+
+        return 1.0 / (1.0 + sum);
+    }
+}
+
+//Whether nothing blocks the segment of the given length leaving from along direction.
+fn visible(world : &HittableList, from : Point3, direction : Vec3, distance : f64) -> bool {
+    stats::count_shadow_ray();
+    let mut rec : HitRecord = HitRecord::default();
+    return !world.hit(&Ray::new(from, direction), Interval::new(0.001, distance - 0.001), &mut rec);
+}
+
+impl Integrator for Bidirectional {
+    fn radiance(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats) -> Color3 {
+        return self.radiance_aovs(camera, ray, world, splats, &mut AovSample::default());
+    }
+
+    //Joined paths can't be told apart into direct and indirect light, so everything goes into the pass of the first
+    //hit: emission for misses, specular for specular surfaces and indirect diffuse otherwise.
+    fn radiance_aovs(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats, aovs : &mut AovSample) -> Color3 {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let context : Context = Context::new(camera, world);
+        let max_depth : usize = camera.max_depth() as usize;
+        let max_vertices : usize = max_depth + 1;
+
+        //Camera subpath. Projections that can't be reached from the scene are marked delta so nothing is joined to them.
+        let mut radiance : Color3 = Color3::default();
+        let mut camera_vertices : Vec<Vertex> = Vec::with_capacity(max_vertices);
+        let direction_pdf : f64 = camera.projection().direction_pdf(camera.basis(), &ray);
+        let mut start : Vertex = Vertex::new(VertexKind::Camera, ray.origin, Color3::new(1.0, 1.0, 1.0));
+        start.delta = direction_pdf <= 0.0;
+        camera_vertices.push(start);
+        let primary : Ray = Ray::new(ray.origin, ray.dir);
+        self.random_walk(&context, ray, Color3::new(1.0, 1.0, 1.0), direction_pdf, max_vertices, &mut camera_vertices, Some(&mut radiance));
+        stats::record_path_length(camera_vertices.len() as u32 - 1);
+        let pass : LightPass = match camera_vertices.get(1) {
+            Some(first) => {
+                aovs.record_surface(&primary, &first.rec);
+                if first.rec.material.is_specular() {LightPass::Specular} else {LightPass::IndirectDiffuse}
+            }
+            None => LightPass::Emission
+        };
+
+        //Light subpath. The first bounce's density includes the chance of picking the light, see Vertex::pdf.
+        let mut light_vertices : Vec<Vertex> = Vec::with_capacity(max_vertices);
+        if let Some((index, light_pdf)) = context.choose_light(rng.gen()) {
+            if let Some((origin, direction, intensity, direction_pdf)) = world.lights[index].sample_emission() {
+                light_vertices.push(Vertex::new(VertexKind::Light(index), origin, (1.0 / light_pdf) * intensity));
+
+                if direction_pdf > 0.0 && !intensity.near_zero() {
+                    let beta : Color3 = (1.0 / (light_pdf * direction_pdf)) * intensity;
+                    self.random_walk(&context, Ray::new(origin, direction), beta, light_pdf * direction_pdf, max_vertices, &mut light_vertices, None);
+                }
+            }
+        }
+
+        //Every way of joining the two, up to max_depth bounces. Direct lighting (s = 1) doesn't need the light subpath.
+        for t in 1..=camera_vertices.len() {
+            for s in 1..=light_vertices.len().max(1) {
+                if (s == 1 && t == 1) || s + t - 2 > max_depth {
+                    continue;
+                }
+                radiance += self.connect(&context, &light_vertices, &camera_vertices, s, t, splats);
+            }
+        }
+
+        aovs.add(pass, radiance);
+        return radiance;
+    }
+}
@@ -1,259 +1,534 @@
-use crate::{Vec3, Color3, global};
-use crate::{Ray};
-use crate::{Interval};
-use crate::{global::Parameters};
-use crate::{HitRecord, Hittable, HittableList};
-use std::{fs, iter};
-use std::io::Write;
+use crate::math::vec3::{Vec3, Color3};
+use crate::math::ray::Ray;
+use crate::shapes::hittable::HittableList;
+use super::stats::{self, RenderStatistics, RayCounters};
+use super::progress::{Progress, ProgressReporter, CancellationToken};
+use super::framebuffer::{Framebuffer, Splats};
+use super::projection::{CameraBasis, Projection, Perspective};
+use super::aperture::Aperture;
+use super::integrator::{Integrator, PathTracer};
+use super::aov::{AovSample, AovPixel, AovLayers};
+use super::denoise::Denoiser;
+use super::post::PostProcess;
+use crate::lights::environment::Environment;
 use rand::Rng;
-use std::f64::consts::PI;
+use std::fmt;
 use std::thread;
 use std::thread::available_parallelism;
-use std::sync::{Arc, Mutex, mpsc};
-use std::collections::HashMap;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicU32, Ordering};
 
-#[derive(Default, Copy, Clone)]
-pub struct Camera {
-    center : Vec3,
-    pixel00_loc : Vec3,
-    pixel_delta_u : Vec3,
-    pixel_delta_v : Vec3,
-    aspect_ratio : f64,
-    defocus_disk_u : Vec3, //Defocus disk horizontal radius
-    defocus_disk_v : Vec3, //Defocus disk vertical radius
+//Width and height in pixels of the square tiles handed out to worker threads.
+const TILE_SIZE : u32 = 32;
 
+//Built and validated by CameraBuilder, all of the viewport data is derived once at build time so the
+//options can't fall out of sync with it.
+#[derive(Clone)]
+pub struct Camera {
     //Camera frame basis vectors
-    u : Vec3,
-    v : Vec3, 
-    w : Vec3,
+    basis : CameraBasis,
+    projection : Arc<dyn Projection>,
+    custom_projection : Option<Arc<dyn Projection>>, //Set when the builder was given a projection other than the thin lens
 
     //Rendering options
-    pub image_width : u32,
-    pub image_height : u32,
-    pub samples_per_pixel : u32,
-    pub max_depth : u32,
-    pub fov : f64,
+    aspect_ratio : f64,
+    image_width : u32,
+    image_height : u32,
+    samples_per_pixel : u32,
+    max_depth : u32,
+    roulette_min_bounces : u32, //Paths are only randomly terminated after this many bounces
+    fov : f64,
 
     //Look at transform vectors
-    pub eye : Vec3,
-    pub target : Vec3, 
-    pub up : Vec3,
+    eye : Vec3,
+    target : Vec3, 
+    up : Vec3,
 
     //Depth of field parameters
-    pub defocus_angle : f64, //Variation angle of rays through each pixel
-    pub focus_distance : f64, //Focus distance of camera
+    defocus_angle : f64, //Variation angle of rays through each pixel
+    focus_distance : f64, //Focus distance of camera
+    aperture : Aperture,
+
+    //Light arriving from outside the scene
+    background : Environment,
+
+    integrator : Arc<dyn Integrator>,
+    denoiser : Option<Denoiser>, //Applied to the finished image, using AOVs rendered alongside it
+    post_process : Option<PostProcess> //Applied last, after denoising
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraError {
+    ZeroWidth,
+    ZeroSamples,
+    InvalidAspectRatio(f64),
+    DegenerateLookAt, //Eye and target coincide, or up is parallel to the view direction
+    NonPositiveFocusDistance(f64)
+}
 
-impl Camera {
-    pub fn new(aspect_ratio : f64, width : u32, samples : u32, depth : u32, field_of_view : f64, e : Vec3, t : Vec3, u : Vec3, defocus_a : f64, focus_d : f64) -> Self {
-        Self {
-            center : e,
-            aspect_ratio : aspect_ratio,
-            image_width : width,
-            image_height : ((width as f64)/aspect_ratio).max(1.0) as u32,
-            samples_per_pixel : samples,
-            max_depth : depth,
-            fov : field_of_view, 
-            eye : e,
-            target : t, 
-            up : u,
-            defocus_angle : defocus_a,
-            focus_distance : focus_d,
-            ..Default::default()
+impl fmt::Display for CameraError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraError::ZeroWidth => write!(f, "image width must be at least one pixel"),
+            CameraError::ZeroSamples => write!(f, "samples per pixel must be at least one"),
+            CameraError::InvalidAspectRatio(ratio) => write!(f, "aspect ratio must be positive and finite, got {}", ratio),
+            CameraError::DegenerateLookAt => write!(f, "eye, target and up do not define a camera frame"),
+            CameraError::NonPositiveFocusDistance(distance) => write!(f, "focus distance must be positive, got {}", distance)
         }
     }
+}
 
-    fn ray_color(ray : &Ray, depth : u32, world : &HittableList) -> Color3 {
-        let mut rec : HitRecord = HitRecord::default();
-        
-        if depth <= 0 {
-            return Color3::new(0.0,0.0,0.0);
+impl std::error::Error for CameraError {}
+
+#[derive(Clone)]
+pub struct CameraBuilder {
+    aspect_ratio : f64,
+    image_width : u32,
+    samples_per_pixel : u32,
+    max_depth : u32,
+    roulette_min_bounces : u32,
+    fov : f64,
+    eye : Vec3,
+    target : Vec3,
+    up : Vec3,
+    defocus_angle : f64,
+    focus_distance : f64,
+    aperture : Aperture,
+    background : Environment,
+    projection : Option<Arc<dyn Projection>>,
+    integrator : Arc<dyn Integrator>,
+    denoiser : Option<Denoiser>,
+    post_process : Option<PostProcess>
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self {
+            aspect_ratio : 16.0 / 9.0,
+            image_width : 400,
+            samples_per_pixel : 10,
+            max_depth : 10,
+            roulette_min_bounces : 3,
+            fov : 90.0,
+            eye : Vec3::new(0.0, 0.0, 0.0),
+            target : Vec3::new(0.0, 0.0, -1.0),
+            up : Vec3::new(0.0, 1.0, 0.0),
+            defocus_angle : 0.0,
+            focus_distance : 10.0,
+            aperture : Aperture::Circular,
+            background : Environment::GradientSky,
+            projection : None,
+            integrator : Arc::new(PathTracer),
+            denoiser : None,
+            post_process : None
         }
+    }
+}
+
+impl CameraBuilder {
+    pub fn new() -> Self {
+        return Self::default();
+    }
 
-        if world.hit(ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
-            let mut scattered : Ray = Ray::default();
-            let mut attenuation : Color3 = Color3::default();
+    pub fn aspect_ratio(mut self, aspect_ratio : f64) -> Self {
+        self.aspect_ratio = aspect_ratio;
+        return self;
+    }
 
-            if(rec.material.scatter(ray, &rec, &mut attenuation, &mut scattered)){
-                return attenuation * Self::ray_color(&scattered, depth-1, world)
-            }
+    pub fn image_width(mut self, width : u32) -> Self {
+        self.image_width = width;
+        return self;
+    }
 
-            return Color3::default();
+    pub fn samples_per_pixel(mut self, samples : u32) -> Self {
+        self.samples_per_pixel = samples;
+        return self;
+    }
+
+    pub fn max_depth(mut self, depth : u32) -> Self {
+        self.max_depth = depth;
+        return self;
+    }
+
+    //Number of bounces before Russian roulette may end a path, 3 by default. Paths that carry little light are
+    //ended early and the survivors weighted up to compensate, which keeps the image unbiased. u32::MAX disables it.
+    pub fn russian_roulette(mut self, min_bounces : u32) -> Self {
+        self.roulette_min_bounces = min_bounces;
+        return self;
+    }
+
+    //Vertical field of view in degrees.
+    pub fn fov(mut self, field_of_view : f64) -> Self {
+        self.fov = field_of_view;
+        return self;
+    }
+
+    pub fn look_at(mut self, eye : Vec3, target : Vec3, up : Vec3) -> Self {
+        self.eye = eye;
+        self.target = target;
+        self.up = up;
+        return self;
+    }
+
+    //Angle in degrees of the cone of rays through each pixel, 0 disables depth of field.
+    pub fn defocus_angle(mut self, angle : f64) -> Self {
+        self.defocus_angle = angle;
+        return self;
+    }
+
+    pub fn focus_distance(mut self, distance : f64) -> Self {
+        self.focus_distance = distance;
+        return self;
+    }
+
+    //Shape of the thin lens opening, circular by default.
+    pub fn aperture(mut self, aperture : Aperture) -> Self {
+        self.aperture = aperture;
+        return self;
+    }
+
+    //What rays that leave the scene see, the gradient sky by default.
+    pub fn background(mut self, background : Environment) -> Self {
+        self.background = background;
+        return self;
+    }
+
+    //Replaces the default thin lens perspective, fov, defocus angle and focus distance only apply to the default.
+    pub fn projection<P : Projection + 'static>(mut self, projection : P) -> Self {
+        self.projection = Some(Arc::new(projection));
+        return self;
+    }
+
+    //Light transport algorithm, the path tracer by default.
+    pub fn integrator<I : Integrator + 'static>(mut self, integrator : I) -> Self {
+        self.integrator = Arc::new(integrator);
+        return self;
+    }
+
+    //Denoises every render, which then also renders the albedo, normal and depth AOVs the denoiser needs.
+    pub fn denoiser(mut self, denoiser : Denoiser) -> Self {
+        self.denoiser = Some(denoiser);
+        return self;
+    }
+
+    //Lens and film effects run on every rendered image. AOV layers are left as they are.
+    pub fn post_process(mut self, post_process : PostProcess) -> Self {
+        self.post_process = Some(post_process);
+        return self;
+    }
+
+    pub fn build(self) -> Result<Camera, CameraError> {
+        if self.image_width == 0 {
+            return Err(CameraError::ZeroWidth);
+        }
+        if self.samples_per_pixel == 0 {
+            return Err(CameraError::ZeroSamples);
+        }
+        if !(self.aspect_ratio > 0.0 && self.aspect_ratio.is_finite()) {
+            return Err(CameraError::InvalidAspectRatio(self.aspect_ratio));
+        }
+        if self.focus_distance.is_nan() || self.focus_distance <= 0.0 {
+            return Err(CameraError::NonPositiveFocusDistance(self.focus_distance));
         }
 
+        let view : Vec3 = self.eye - self.target;
+        if view.near_zero() || Vec3::cross(&self.up, &view).near_zero() {
+            return Err(CameraError::DegenerateLookAt);
+        }
 
-        let unit_direction : Vec3 = ray.dir.unit_vector();
-        let a : f64 = 0.5 * (unit_direction.y() + 1.0);
-        return (1.0-a) * Color3::new(1.0,1.0,1.0) + a * Color3::new(0.5, 0.7, 1.0);
+        let image_height : u32 = ((self.image_width as f64)/self.aspect_ratio).max(1.0) as u32;
+        let projection : Arc<dyn Projection> = match &self.projection {
+            Some(projection) => Arc::clone(projection),
+            None => Arc::new(Perspective::new(self.fov, self.focus_distance, self.defocus_angle).with_aperture(self.aperture.clone()))
+        };
+
+        let mut camera : Camera = Camera {
+            basis : CameraBasis::default(),
+            projection : projection,
+            custom_projection : self.projection,
+            aspect_ratio : self.aspect_ratio,
+            image_width : self.image_width,
+            image_height : image_height,
+            samples_per_pixel : self.samples_per_pixel,
+            max_depth : self.max_depth,
+            roulette_min_bounces : self.roulette_min_bounces,
+            fov : self.fov,
+            eye : self.eye,
+            target : self.target,
+            up : self.up,
+            defocus_angle : self.defocus_angle,
+            focus_distance : self.focus_distance,
+            aperture : self.aperture,
+            background : self.background,
+            integrator : self.integrator,
+            denoiser : self.denoiser,
+            post_process : self.post_process
+        };
+        camera.initialize();
+        return Ok(camera);
     }
-    
-    pub fn render(mut self, params : &mut global::Parameters, world : HittableList, single_threaded : Option<bool>){
+}
+
+
+impl Camera {
+    pub fn builder() -> CameraBuilder {
+        return CameraBuilder::new();
+    }
+
+    //Builder preloaded with this camera's options, for making variations of it.
+    pub fn to_builder(&self) -> CameraBuilder {
+        return CameraBuilder {
+            aspect_ratio : self.aspect_ratio,
+            image_width : self.image_width,
+            samples_per_pixel : self.samples_per_pixel,
+            max_depth : self.max_depth,
+            roulette_min_bounces : self.roulette_min_bounces,
+            fov : self.fov,
+            eye : self.eye,
+            target : self.target,
+            up : self.up,
+            defocus_angle : self.defocus_angle,
+            focus_distance : self.focus_distance,
+            aperture : self.aperture.clone(),
+            background : self.background.clone(),
+            projection : self.custom_projection.clone(),
+            integrator : Arc::clone(&self.integrator),
+            denoiser : self.denoiser,
+            post_process : self.post_process.clone()
+        };
+    }
+
+    pub fn projection(&self) -> Arc<dyn Projection> {
+        return Arc::clone(&self.projection);
+    }
+
+    pub fn basis(&self) -> &CameraBasis {
+        return &self.basis;
+    }
+
+    pub fn image_width(&self) -> u32 {
+        return self.image_width;
+    }
+
+    pub fn image_height(&self) -> u32 {
+        return self.image_height;
+    }
+
+    pub fn samples_per_pixel(&self) -> u32 {
+        return self.samples_per_pixel;
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        return self.max_depth;
+    }
+
+    pub fn focus_distance(&self) -> f64 {
+        return self.focus_distance;
+    }
+
+    pub fn russian_roulette(&self) -> u32 {
+        return self.roulette_min_bounces;
+    }
+
+    pub fn background(&self) -> &Environment {
+        return &self.background;
+    }
+
+    //Renders the world into a framebuffer and returns it with the statistics gathered along the way.
+    //The image is split into tiles which worker threads pull from a shared queue; the cancellation token is checked
+    //between tiles and the reporter is told about every finished tile.
+    pub fn render(self, world : HittableList, single_threaded : Option<bool>, progress : Option<Arc<dyn ProgressReporter>>, cancel : Option<CancellationToken>) -> (Framebuffer, RenderStatistics) {
+        return self.render_shared(Arc::new(world), single_threaded, progress, cancel);
+    }
+
+    //Same as render, for worlds that are rendered more than once (stereo pairs, animations).
+    pub fn render_shared(self, world : Arc<HittableList>, single_threaded : Option<bool>, progress : Option<Arc<dyn ProgressReporter>>, cancel : Option<CancellationToken>) -> (Framebuffer, RenderStatistics) {
+        let (framebuffer, _, statistics) = self.render_film(world, false, single_threaded, progress, cancel);
+        return (framebuffer, statistics);
+    }
+
+    //Same as render, also splitting the image into AOV layers for compositing. The light passes add up to the
+    //returned beauty image.
+    pub fn render_aovs(self, world : HittableList, single_threaded : Option<bool>, progress : Option<Arc<dyn ProgressReporter>>, cancel : Option<CancellationToken>) -> (Framebuffer, AovLayers, RenderStatistics) {
+        let (width, height) = (self.image_width, self.image_height);
+        let (framebuffer, layers, statistics) = self.render_film(Arc::new(world), true, single_threaded, progress, cancel);
+        return (framebuffer, layers.unwrap_or_else(|| AovLayers::new(width, height)), statistics);
+    }
+
+    fn render_film(self, world : Arc<HittableList>, aovs : bool, single_threaded : Option<bool>, progress : Option<Arc<dyn ProgressReporter>>, cancel : Option<CancellationToken>) -> (Framebuffer, Option<AovLayers>, RenderStatistics) {
         let single_threaded = single_threaded.unwrap_or(false);
-        if single_threaded {
-            self.single_threaded_render(params, world);
-            return;
+        let cancel : CancellationToken = cancel.unwrap_or_default();
+
+        let denoiser : Option<Denoiser> = self.denoiser;
+        let post_process : Option<PostProcess> = self.post_process.clone();
+        let integrator : Arc<dyn Integrator> = Arc::clone(&self.integrator);
+        let render_aovs : bool = aovs || denoiser.is_some();
+        let (mut framebuffer, mut layers, mut statistics) = match integrator.render(&self, &world, render_aovs, single_threaded, progress.as_ref(), &cancel) {
+            Some(result) => result,
+            None => self.render_tiles(integrator, world, render_aovs, single_threaded, progress.as_ref(), &cancel)
+        };
+
+        if let (Some(denoiser), Some(layers)) = (denoiser, &mut layers) {
+            let denoised : Framebuffer = statistics.time_phase(stats::DENOISE_PHASE, || denoiser.denoise(&framebuffer, layers));
+            let noisy : Framebuffer = std::mem::replace(&mut framebuffer, denoised);
+            if denoiser.keeps_noisy() {
+                layers.noisy = Some(noisy);
+            }
         }
-        
-        self.initialize(params);
+        if let Some(post_process) = &post_process {
+            framebuffer = statistics.time_phase(stats::POST_PHASE, || post_process.apply(&framebuffer));
+        }
+        return (framebuffer, if aovs {layers} else {None}, statistics);
+    }
+
+    //The tile loop behind render, estimating every sample with the given integrator and filling AOV layers if asked.
+    pub(crate) fn render_tiles(self, integrator : Arc<dyn Integrator>, world : Arc<HittableList>, aovs : bool, single_threaded : bool, progress : Option<&Arc<dyn ProgressReporter>>, cancel : &CancellationToken) -> (Framebuffer, Option<AovLayers>, RenderStatistics) {
+        let mut statistics : RenderStatistics = RenderStatistics::new();
+        let render_start = std::time::Instant::now();
 
         //image setup
-        let mut image : fs::File = fs::File::create("image.ppm").expect("Unable to create file");
+        let mut framebuffer : Framebuffer = Framebuffer::new(self.image_width, self.image_height);
+        let mut layers : Option<AovLayers> = if aovs {Some(AovLayers::new(self.image_width, self.image_height))} else {None};
+        let sample_scale : f64 = 1.0 / (self.samples_per_pixel as f64);
+
+        //Tile setup
+        let tiles_x : u32 = self.image_width.div_ceil(TILE_SIZE);
+        let tiles_y : u32 = self.image_height.div_ceil(TILE_SIZE);
+        let tiles_total : u32 = tiles_x * tiles_y;
         
         //Thread setup
-        let thread_count : usize = available_parallelism().unwrap().get();
+        let thread_count : usize = if single_threaded {1} else {available_parallelism().map(|n| n.get()).unwrap_or(1)};
         let mut threads = vec![];
-        let rows_per_thread = self.image_height/(thread_count as u32); //Number of rows assigned to each thread.
         
-        //Shared pointers for the camera, world, and image array.
+        //Shared pointers for the camera, world, and the index of the next tile to render.
         let camera_arc = Arc::new(self);
-        let world_arc = Arc::new(world);
-        let rows_remaining = Arc::new(Mutex::new(self.image_height));
+        let world_arc = world;
+        let next_tile = Arc::new(AtomicU32::new(0));
         
         let (tx, rx) = mpsc::channel();
 
-        for thread in 0..thread_count {
-            let handle: thread::JoinHandle<()> = thread::spawn({
+        for _ in 0..thread_count {
+            let handle: thread::JoinHandle<(RayCounters, Splats)> = thread::spawn({
                 let camera = Arc::clone(&camera_arc);
                 let world = Arc::clone(&world_arc);
-                let row_count = Arc::clone(&rows_remaining);
+                let next_tile = Arc::clone(&next_tile);
+                let integrator = Arc::clone(&integrator);
+                let cancel = cancel.clone();
                 let tx_thread = tx.clone();
                 move || {
-                    //Loop through a protion of the image assigned 
-                    let mut ray : Ray = Ray::default();
-                    let mut evaluated_rows = vec![vec![Vec3::default(); self.image_width as usize]; rows_per_thread as usize];
-                    for j in 0..rows_per_thread {
-                        let row : u32 = (thread as u32) * rows_per_thread + j;
-                        for i in 0..camera.image_width {
-                            let mut pixel_color : Color3 = Color3::default();
-                            for _ in 0..camera.samples_per_pixel {
-                                camera.get_ray(i, row, &mut ray);
-                                pixel_color += Camera::ray_color(&ray, camera.max_depth, &world);
-                            }
+                    let mut splats : Splats = Splats::new(camera.image_width, camera.image_height);
+                    loop {
+                        let tile : u32 = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if tile >= tiles_total || cancel.is_cancelled() {
+                            break;
+                        }
 
-                            evaluated_rows[j as usize][i as usize] = pixel_color;
+                        //Evaluate every pixel of the tile, clipped to the image bounds.
+                        let x0 : u32 = (tile % tiles_x) * TILE_SIZE;
+                        let y0 : u32 = (tile / tiles_x) * TILE_SIZE;
+                        let x1 : u32 = (x0 + TILE_SIZE).min(camera.image_width);
+                        let y1 : u32 = (y0 + TILE_SIZE).min(camera.image_height);
+                        let mut tile_pixels : Vec<Color3> = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+                        let mut tile_aovs : Vec<AovPixel> = Vec::new();
+
+                        for j in y0..y1 {
+                            for i in x0..x1 {
+                                let mut pixel_color : Color3 = Color3::default();
+                                let mut pixel_aovs : AovPixel = AovPixel::default();
+                                for _ in 0..camera.samples_per_pixel {
+                                    if let Some(ray) = camera.get_ray(i, j) {
+                                        stats::count_camera_ray();
+                                        if aovs {
+                                            let mut sample : AovSample = AovSample::default();
+                                            pixel_color += integrator.radiance_aovs(&camera, ray, &world, &mut splats, &mut sample);
+                                            pixel_aovs.add(&sample);
+                                        } else {
+                                            pixel_color += integrator.radiance(&camera, ray, &world, &mut splats);
+                                        }
+                                    }
+                                }
+                                tile_pixels.push(pixel_color);
+                                if aovs {
+                                    tile_aovs.push(pixel_aovs);
+                                }
+                            }
                         }
 
-                        //Remove one from the row count and print. Exiting scope unlocks mutex.
-                        {
-                            let mut num = row_count.lock().expect("Failed to lock mutex");
-                            *num -= 1;
-                            println!("Rows Remaining: {} ({:?} completed Row {})", num, thread::current().id(), row);
-                        }                        
+                        //Send through channel to be copied into the image on the calling thread.
+                        tx_thread.send((x0, y0, x1, tile_pixels, tile_aovs)).expect("Failed to send tile to receiver.");
                     }
 
-                    //Send through channel to be collected at end.
-                    //We pass the thread id as well as the row to sort later.
-                    tx_thread.send((thread, evaluated_rows)).expect("Failed to send rows to receiver.");
+                    return (stats::take_thread_counters(), splats);
                 }
             });
 
             threads.push(handle);
         }
+        drop(tx);
+
+        //Collect tiles as they finish, the loop ends once every worker has dropped its sender.
+        for (tiles_finished, (x0, y0, x1, tile_pixels, tile_aovs)) in rx.iter().enumerate() {
+            let tile_width : usize = (x1 - x0) as usize;
+            for (index, pixel) in tile_pixels.into_iter().enumerate() {
+                let i : u32 = x0 + (index % tile_width) as u32;
+                let j : u32 = y0 + (index / tile_width) as u32;
+                framebuffer.set(i, j, pixel * sample_scale);
+                if let Some(layers) = &mut layers {
+                    tile_aovs[index].resolve(layers, i, j, sample_scale);
+                }
+            }
 
+            if let Some(reporter) = progress {
+                reporter.report(&Progress {tiles_completed : tiles_finished as u32 + 1, tiles_total : tiles_total, elapsed : render_start.elapsed()});
+            }
+        }
 
         //Wait for all threads to finish.
         for handle in threads {
-            handle.join().unwrap();
-        }
-
-        //Attach each row to a key in a hashmap (thread -> to an owned vector)
-        let mut rows_map : HashMap<usize, Vec<Vec<Vec3>>> = HashMap::new();
-        for (thread, rows) in rx.try_iter() {
-            rows_map.insert(thread, rows);
-        }
-
-        //Write data, could also be multithreaded.
-        writeln!(image, "P3\n{} {}\n255\n", self.image_width, self.image_height).expect("Failed to write data.");
-        for thread in 0..thread_count {
-            let rows : &Vec<Vec<Vec3>> = rows_map.get(&thread).expect("Failed to extract row from received channel.");
-            for row in rows {
-                for pixel in row {
-                    pixel.write_color(&mut image, self.samples_per_pixel)
-                }
+            let (counters, splats) = handle.join().expect("Render thread panicked.");
+            statistics.add_thread(counters);
+            framebuffer.add_splats(&splats, sample_scale);
+            if let Some(layers) = &mut layers {
+                layers.add_splats(&splats, sample_scale);
             }
         }
-    }
+        statistics.add_phase(stats::RENDER_PHASE, render_start.elapsed());
 
-    fn get_ray(&self, i : u32, j : u32, ray : &mut Ray) {
-        let pixel_center : Vec3 = self.pixel00_loc + (i as f64 * self.pixel_delta_u) + (j as f64 * self.pixel_delta_v);
-        let pixel_sample : Vec3 = pixel_center + self.pixel_sample_square();
-        let ray_origin : Vec3 = if self.defocus_angle <= 0.0 {self.center} else {self.defocus_disk_sample()};
-        let ray_direction : Vec3 = pixel_sample - ray_origin;
-        
-        ray.origin = ray_origin;
-        ray.dir = ray_direction;
-    }
-
-    fn pixel_sample_square(&self) -> Vec3 {
-        let mut RNG : rand::rngs::ThreadRng = rand::thread_rng();
-        let px = -0.5 * RNG.gen::<f64>(); 
-        let py = -0.5 * RNG.gen::<f64>();
-
-        return (px * self.pixel_delta_u) + (py * self.pixel_delta_v);
+        return (framebuffer, layers, statistics);
     }
 
-    fn defocus_disk_sample(&self) -> Vec3 {
-        let p = Vec3::random_in_unit_disk();
-        return self.center + (p[0] * self.defocus_disk_u) + (p[1] * self.defocus_disk_v);
+    //Ray through a random point inside pixel (i, j), None if the projection doesn't cover that pixel.
+    pub(crate) fn get_ray(&self, i : u32, j : u32) -> Option<Ray> {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let s : f64 = (i as f64 + rng.gen::<f64>()) / (self.image_width as f64);
+        let t : f64 = (j as f64 + rng.gen::<f64>()) / (self.image_height as f64);
+        return self.projection.generate_ray(&self.basis, s, t);
     }
 
-    fn initialize(&mut self, params : &mut global::Parameters) {
-        //FOV and viewport stuff
-        let theta : f64 = self.fov * (PI / 180.0);
-        let h : f64 = (theta/2.0).tan();
-        params.viewport_height = 2.0 * h * self.focus_distance;
-        params.viewport_width = params.viewport_height * ((self.image_width as f64)/ (self.image_height as f64));
-
+    fn initialize(&mut self) {
         //Calculate the basis vectors
-        self.w = (self.eye - self.target).unit_vector();
-        self.u = Vec3::cross(&self.up, &self.w).unit_vector();
-        self.v = Vec3::cross(&self.w, &self.u);
+        let w : Vec3 = (self.eye - self.target).unit_vector();
+        let u : Vec3 = Vec3::cross(&self.up, &w).unit_vector();
+        let v : Vec3 = Vec3::cross(&w, &u);
+
+        self.basis = CameraBasis {
+            origin : self.eye,
+            u : u,
+            v : v,
+            w : w,
+            aspect_ratio : (self.image_width as f64) / (self.image_height as f64)
+        };
+    }
+}
 
-        //Calculate the viewport vectors
-        let viewport_u : Vec3 = self.u * params.viewport_width;
-        let viewport_v : Vec3 = self.v.negate() * params.viewport_height;
-        
-        //Calculate the horizontal and vertical delta vectors per pixel
-        self.pixel_delta_u  = viewport_u / params.width as f64;
-        self.pixel_delta_v = viewport_v / params.height as f64;
-
-        //Calculate location of the upper left pixel
-        let viewport_upper_left = self.center - (self.focus_distance * self.w) - viewport_u/2.0 - viewport_v/2.0;
-        self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
-    
-        //Calculate the camera defocus disk basis vectors
-        let defocus_radius : f64 = self.focus_distance * ((self.defocus_angle/2.0) * (PI/180.0)).tan();
-        self.defocus_disk_u = self.u * defocus_radius;
-        self.defocus_disk_v = self.v * defocus_radius;
-    }
-
-    //Single threaded renderer here for legacy purposes.
-    fn single_threaded_render(mut self, params : &mut global::Parameters, world : HittableList) {
-        self.initialize(params);
-
-        let mut image : fs::File = fs::File::create("image.ppm").expect("Unable to create file");
-        let mut image_arr: Vec<Vec<Vec3>> = vec![vec![Vec3::default(); self.image_width as usize]; self.image_height as usize];
-        writeln!(image, "P3\n{} {}\n255\n", self.image_width, self.image_height).expect("Failed to write data.");
-
-
-        let mut ray : Ray = Ray::default();
-        for j in 0..self.image_height {
-            println!("Scanlines Remaining: {}\n", self.image_height - j);
-            for i in 0..self.image_width {
-                let mut pixel_color : Color3 = Color3::default();
-                for _ in 0..self.samples_per_pixel {
-                    self.get_ray(i, j, &mut ray);
-                    pixel_color += Camera::ray_color(&ray, self.max_depth, &world);
-                }
-                image_arr[j as usize][i as usize] = pixel_color;
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        for row in image_arr {
-            for pixel in row {
-                pixel.write_color(&mut image, self.samples_per_pixel);
-            }
-        }
+    #[test]
+    fn builder_rejects_zero_samples_and_width() {
+        assert_eq!(CameraBuilder::new().samples_per_pixel(0).build().err(), Some(CameraError::ZeroSamples));
+        assert_eq!(CameraBuilder::new().image_width(0).build().err(), Some(CameraError::ZeroWidth));
+        assert!(CameraBuilder::new().samples_per_pixel(1).build().is_ok());
     }
-}
\ No newline at end of file
+}
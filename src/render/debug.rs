@@ -0,0 +1,166 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use crate::math::vec3::{Vec3, Color3};
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::shapes::hittable::{HitRecord, Hittable, HittableList};
+use crate::shapes::material::Material;
+use crate::shapes::coating::ThinFilm;
+use super::camera::Camera;
+use super::framebuffer::Splats;
+use super::integrator::Integrator;
+use super::aov::{AovSample, LightPass};
+use super::stats;
+
+//Fast, non-physical views of the first surface each camera ray hits, for debugging scenes. They are integrators like
+//any other, so they render through the same tiles, film and image output as a regular render.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugView {
+    AmbientOcclusion {radius : f64, samples : u32}, //Fraction of cosine weighted rays that travel radius without hitting anything
+    ShadingNormal, //Normals mapped from [-1,1] to [0,1] per component
+    GeometricNormal,
+    Albedo,
+    Depth {far : f64}, //Distance to the hit, black at the camera and white at far or beyond
+    Uv, //Texture coordinates in red and green
+    Barycentrics, //Triangle vertex weights in red, green and blue, black on shapes that aren't triangles
+    MaterialId, //A flat color per distinct material
+    HitMiss //White where something was hit, black elsewhere
+}
+
+impl DebugView {
+    pub fn ambient_occlusion(radius : f64, samples : u32) -> Self {
+        return DebugView::AmbientOcclusion {radius : radius, samples : samples.max(1)};
+    }
+
+    pub fn depth(far : f64) -> Self {
+        return DebugView::Depth {far : far};
+    }
+
+    //Color of the view at a hit.
+    fn shade(&self, ray : &Ray, world : &HittableList, rec : &HitRecord) -> Color3 {
+        match self {
+            DebugView::AmbientOcclusion { radius, samples } => {
+                let mut unoccluded : u32 = 0;
+                for _ in 0..*samples {
+                    let mut direction : Vec3 = rec.normal + Vec3::random_unit_vector();
+                    if direction.near_zero() {
+                        direction = rec.normal;
+                    }
+                    stats::count_shadow_ray();
+                    let mut occluder : HitRecord = HitRecord::default();
+                    if !world.hit(&Ray::new(rec.p, direction.unit_vector()), Interval::new(0.001, *radius), &mut occluder) {
+                        unoccluded += 1;
+                    }
+                }
+                let visibility : f64 = unoccluded as f64 / *samples as f64;
+                return Color3::new(visibility, visibility, visibility);
+            }
+
+            DebugView::ShadingNormal => return 0.5 * (rec.normal + Vec3::new(1.0, 1.0, 1.0)),
+            DebugView::GeometricNormal => return 0.5 * (rec.geometric_normal + Vec3::new(1.0, 1.0, 1.0)),
+            DebugView::Albedo => return rec.material.albedo(),
+
+            DebugView::Depth { far } => {
+                let distance : f64 = rec.t * ray.dir.length() / far.max(f64::MIN_POSITIVE);
+                let shade : f64 = distance.min(1.0);
+                return Color3::new(shade, shade, shade);
+            }
+
+            DebugView::Uv => return Color3::new(rec.u, rec.v, 0.0),
+
+            DebugView::Barycentrics => {
+                return match rec.barycentrics {
+                    Some((b1, b2)) => Color3::new(1.0 - b1 - b2, b1, b2),
+                    None => Color3::default()
+                };
+            }
+
+            DebugView::MaterialId => return material_color(&rec.material),
+            DebugView::HitMiss => return Color3::new(1.0, 1.0, 1.0)
+        }
+    }
+}
+
+impl Integrator for DebugView {
+    fn radiance(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats) -> Color3 {
+        return self.radiance_aovs(camera, ray, world, splats, &mut AovSample::default());
+    }
+
+    //The view goes into the pass of the first hit, emission for misses, specular for specular surfaces and indirect
+    //diffuse otherwise.
+    fn radiance_aovs(&self, _camera : &Camera, ray : Ray, world : &HittableList, _splats : &mut Splats, aovs : &mut AovSample) -> Color3 {
+        stats::record_path_length(0);
+        let mut rec : HitRecord = HitRecord::default();
+        if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            //Misses are open sky for occlusion and infinitely far away for depth.
+            let miss : Color3 = match self {
+                DebugView::AmbientOcclusion { .. } | DebugView::Depth { .. } => Color3::new(1.0, 1.0, 1.0),
+                _ => Color3::default()
+            };
+            aovs.add(LightPass::Emission, miss);
+            return miss;
+        }
+
+        aovs.record_surface(&ray, &rec);
+        let shade : Color3 = self.shade(&ray, world, &rec);
+        aovs.add(if rec.material.is_specular() {LightPass::Specular} else {LightPass::IndirectDiffuse}, shade);
+        return shade;
+    }
+}
+
+//Color picked by hashing the material's kind and parameters, so equal materials always share a color.
+fn material_color(material : &Material) -> Color3 {
+    let mut hasher : DefaultHasher = DefaultHasher::new();
+    match material {
+        Material::Lambertian { albedo } => {
+            0u8.hash(&mut hasher);
+            hash_vec(albedo, &mut hasher);
+        }
+        Material::Metal { albedo, fuzz, coating } => {
+            1u8.hash(&mut hasher);
+            hash_vec(albedo, &mut hasher);
+            fuzz.to_bits().hash(&mut hasher);
+            hash_coating(coating, &mut hasher);
+        }
+        Material::Diaelectric { index_of_refraction, dispersion, absorption, thin_walled, priority, coating } => {
+            2u8.hash(&mut hasher);
+            index_of_refraction.to_bits().hash(&mut hasher);
+            hash_vec(absorption, &mut hasher);
+            thin_walled.hash(&mut hasher);
+            priority.hash(&mut hasher);
+            hash_coating(coating, &mut hasher);
+            for lambda in [450.0, 650.0] {
+                dispersion.ior(lambda).map(f64::to_bits).hash(&mut hasher);
+            }
+        }
+        Material::Subsurface { albedo, mean_free_path, method } => {
+            3u8.hash(&mut hasher);
+            hash_vec(albedo, &mut hasher);
+            hash_vec(mean_free_path, &mut hasher);
+            method.hash(&mut hasher);
+        }
+        Material::Mapped { base, maps } => {
+            4u8.hash(&mut hasher);
+            hash_vec(&material_color(base), &mut hasher);
+            std::ptr::hash(maps.as_ref(), &mut hasher);
+        }
+    }
+
+    //Spread the hash over three channels, keeping colors away from black so they stand out from misses.
+    let hash : u64 = hasher.finish();
+    let channel = |shift : u32| 0.2 + 0.8 * (((hash >> shift) & 0xff) as f64 / 255.0);
+    return Color3::new(channel(0), channel(8), channel(16));
+}
+
+fn hash_vec<H : Hasher>(v : &Vec3, hasher : &mut H) {
+    for axis in 0..3 {
+        v[axis].to_bits().hash(hasher);
+    }
+}
+
+fn hash_coating<H : Hasher>(coating : &Option<ThinFilm>, hasher : &mut H) {
+    if let Some(film) = coating {
+        film.thickness().to_bits().hash(hasher);
+        film.index_of_refraction().to_bits().hash(hasher);
+    }
+}
@@ -0,0 +1,184 @@
+use std::thread;
+use std::thread::available_parallelism;
+use crate::math::vec3::{Vec3, Color3};
+use super::framebuffer::Framebuffer;
+use super::aov::AovLayers;
+
+//Taps of the B3 spline used by every pass of the à-trous filter.
+const KERNEL : [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+//Edge-avoiding à-trous wavelet filter (Dammertz et al., as in SVGF) that smooths the noise out of a rendered image
+//while keeping the edges found in its albedo, normal and depth AOVs. The lighting is divided by the albedo before
+//filtering and multiplied back after, so textures stay sharp. Runs on the linear image, before tone mapping.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Denoiser {
+    strength : f64, //Blend between the noisy image at 0 and the fully filtered one at 1
+    iterations : u32, //Each doubles the filter's reach, 4 covers 61 pixels across
+    color_sigma : f64, //Tolerated difference in (compressed) lighting, halved every iteration
+    normal_power : f64, //Exponent on the cosine between normals, higher keeps creases sharper
+    depth_sigma : f64, //Tolerated relative depth difference per pixel of distance
+    albedo_sigma : f64,
+    keep_noisy : bool //Whether Camera::render_aovs keeps the unfiltered image in AovLayers::noisy
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        return Self {
+            strength : 1.0,
+            iterations : 4,
+            color_sigma : 0.2,
+            normal_power : 64.0,
+            depth_sigma : 0.02,
+            albedo_sigma : 0.1,
+            keep_noisy : false
+        };
+    }
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn with_strength(self, strength : f64) -> Self {
+        return Self {strength : strength.clamp(0.0, 1.0), ..self};
+    }
+
+    pub fn with_iterations(self, iterations : u32) -> Self {
+        return Self {iterations : iterations, ..self};
+    }
+
+    //Edge-stopping tolerances, larger values smooth more across differences in that feature.
+    pub fn with_edge_stopping(self, color_sigma : f64, normal_power : f64, depth_sigma : f64, albedo_sigma : f64) -> Self {
+        return Self {color_sigma : color_sigma, normal_power : normal_power, depth_sigma : depth_sigma, albedo_sigma : albedo_sigma, ..self};
+    }
+
+    pub fn keep_noisy(self, keep : bool) -> Self {
+        return Self {keep_noisy : keep, ..self};
+    }
+
+    pub fn keeps_noisy(&self) -> bool {
+        return self.keep_noisy;
+    }
+
+    //Filtered copy of beauty, guided by the surface layers rendered with it. beauty itself is left untouched.
+    pub fn denoise(&self, beauty : &Framebuffer, layers : &AovLayers) -> Framebuffer {
+        let width : usize = beauty.width as usize;
+        let albedo : &[Color3] = layers.albedo.pixels();
+        let normal : &[Color3] = layers.normal.pixels();
+        let depth : &[Color3] = layers.depth.pixels();
+
+        //Demodulate the albedo, pixels that missed everything keep their color as it is.
+        let demodulated : Vec<Color3> = beauty.pixels().iter().zip(albedo.iter()).map(|(color, albedo)| demodulate(color, albedo)).collect();
+
+        let mut current : Vec<Color3> = demodulated;
+        for iteration in 0..self.iterations {
+            let step : usize = 1 << iteration;
+            let color_sigma : f64 = self.color_sigma / (1u32 << iteration) as f64;
+            let guides : Guides = Guides {width : width, albedo : albedo, normal : normal, depth : depth, step : step, color_sigma : color_sigma};
+
+            let mut next : Vec<Color3> = vec![Color3::default(); current.len()];
+            let thread_count : usize = available_parallelism().map(|n| n.get()).unwrap_or(1);
+            let rows_per_thread : usize = beauty.height.div_ceil(thread_count as u32).max(1) as usize;
+            thread::scope(|scope| {
+                for (chunk, rows) in next.chunks_mut(rows_per_thread * width).enumerate() {
+                    let (current, guides) = (&current, &guides);
+                    scope.spawn(move || {
+                        for (offset, pixel) in rows.iter_mut().enumerate() {
+                            *pixel = self.filter_pixel(current, guides, chunk * rows_per_thread * width + offset);
+                        }
+                    });
+                }
+            });
+            current = next;
+        }
+
+        let mut denoised : Framebuffer = Framebuffer::new(beauty.width, beauty.height);
+        for (index, pixel) in denoised.pixels_mut().iter_mut().enumerate() {
+            let filtered : Color3 = current[index] * guard_albedo(&albedo[index]);
+            *pixel = (1.0 - self.strength) * beauty.pixels()[index] + self.strength * filtered;
+        }
+        return denoised;
+    }
+
+    //One 5x5 à-trous tap set around index, with taps step pixels apart.
+    fn filter_pixel(&self, color : &[Color3], guides : &Guides, index : usize) -> Color3 {
+        let width : usize = guides.width;
+        let height : usize = color.len() / width;
+        let (x, y) = ((index % width) as isize, (index / width) as isize);
+        let center : Color3 = color[index];
+
+        let mut sum : Color3 = Color3::default();
+        let mut total_weight : f64 = 0.0;
+        for (ky, ky_weight) in KERNEL.iter().enumerate() {
+            let qy : isize = y + (ky as isize - 2) * guides.step as isize;
+            if qy < 0 || qy >= height as isize {
+                continue;
+            }
+            for (kx, kx_weight) in KERNEL.iter().enumerate() {
+                let qx : isize = x + (kx as isize - 2) * guides.step as isize;
+                if qx < 0 || qx >= width as isize {
+                    continue;
+                }
+
+                let q : usize = qy as usize * width + qx as usize;
+                let weight : f64 = if q == index {ky_weight * kx_weight} else {ky_weight * kx_weight * self.edge_weight(color, guides, index, q)};
+                sum += weight * color[q];
+                total_weight += weight;
+            }
+        }
+        return if total_weight > 0.0 {sum / total_weight} else {center};
+    }
+
+    //How much pixel q may contribute to pixel p, from 1 for matching features down to 0 across an edge.
+    fn edge_weight(&self, color : &[Color3], guides : &Guides, p : usize, q : usize) -> f64 {
+        //Pixels that missed everything only blend with each other.
+        let (normal_p, normal_q) = (guides.normal[p], guides.normal[q]);
+        let normal_weight : f64 = match (normal_p.near_zero(), normal_q.near_zero()) {
+            (true, true) => 1.0,
+            (false, false) => Vec3::dot(&normal_p, &normal_q).max(0.0).powf(self.normal_power),
+            _ => 0.0
+        };
+        if normal_weight <= 0.0 {
+            return 0.0;
+        }
+
+        //Depth is allowed to change more between pixels further apart, so slanted surfaces still blend.
+        let (depth_p, depth_q) = (guides.depth[p].x(), guides.depth[q].x());
+        let pixels_apart : usize = (q % guides.width).abs_diff(p % guides.width) + (q / guides.width).abs_diff(p / guides.width);
+        let depth_difference : f64 = (depth_p - depth_q).abs() / (depth_p.max(1e-4) * self.depth_sigma * pixels_apart as f64);
+
+        let color_difference : f64 = (compress(color[p]) - compress(color[q])).length_squared() / (guides.color_sigma * guides.color_sigma).max(1e-12);
+        let albedo_difference : f64 = (guides.albedo[p] - guides.albedo[q]).length_squared() / (self.albedo_sigma * self.albedo_sigma).max(1e-12);
+
+        return normal_weight * (-(depth_difference + color_difference + albedo_difference)).exp();
+    }
+}
+
+//Feature buffers and settings shared by every pixel of one filter iteration.
+struct Guides<'a> {
+    width : usize,
+    albedo : &'a [Color3],
+    normal : &'a [Color3],
+    depth : &'a [Color3],
+    step : usize,
+    color_sigma : f64
+}
+
+//Albedo to divide lighting by, kept away from zero. Misses have no albedo and are left as they are.
+fn guard_albedo(albedo : &Color3) -> Color3 {
+    if albedo.near_zero() {
+        return Color3::new(1.0, 1.0, 1.0);
+    }
+    return Color3::new(albedo.x().max(0.01), albedo.y().max(0.01), albedo.z().max(0.01));
+}
+
+fn demodulate(color : &Color3, albedo : &Color3) -> Color3 {
+    let albedo : Color3 = guard_albedo(albedo);
+    return Color3::new(color.x() / albedo.x(), color.y() / albedo.y(), color.z() / albedo.z());
+}
+
+//Maps HDR colors into [0, 1) so bright fireflies don't dominate the color distance.
+fn compress(color : Color3) -> Color3 {
+    return color / (1.0 + color.luminance().max(0.0));
+}
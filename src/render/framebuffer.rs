@@ -0,0 +1,108 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use crate::math::vec3::Color3;
+
+//In memory image produced by a render. Pixels are stored row by row from the top left corner as linear
+//radiance, already averaged over the samples taken for each pixel.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub width : u32,
+    pub height : u32,
+    pixels : Vec<Color3>
+}
+
+impl Framebuffer {
+    pub fn new(width : u32, height : u32) -> Self {
+        return Self {
+            width : width,
+            height : height,
+            pixels : vec![Color3::default(); (width as usize) * (height as usize)]
+        };
+    }
+
+    fn index(&self, x : u32, y : u32) -> usize {
+        return (y as usize) * (self.width as usize) + (x as usize);
+    }
+
+    pub fn get(&self, x : u32, y : u32) -> Color3 {
+        return self.pixels[self.index(x, y)];
+    }
+
+    pub fn set(&mut self, x : u32, y : u32, color : Color3) {
+        let index : usize = self.index(x, y);
+        self.pixels[index] = color;
+    }
+
+    pub fn pixels(&self) -> &[Color3] {
+        return &self.pixels;
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color3] {
+        return &mut self.pixels;
+    }
+
+    //Adds light splatted during the render, scaled the same way as the pixel samples.
+    pub fn add_splats(&mut self, splats : &Splats, scale : f64) {
+        for (pixel, splat) in self.pixels.iter_mut().zip(splats.pixels.iter()) {
+            *pixel += scale * *splat;
+        }
+    }
+
+    //Gamma corrected 8 bit RGB triplets, row by row.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let mut bytes : Vec<u8> = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in self.pixels.iter() {
+            bytes.extend_from_slice(&pixel.to_rgb8());
+        }
+        return bytes;
+    }
+
+    //Writes the image as a plain text (P3) PPM file.
+    pub fn write_ppm<P : AsRef<Path>>(&self, path : P) -> io::Result<()> {
+        let mut image = BufWriter::new(fs::File::create(path)?);
+        writeln!(image, "P3\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in self.pixels.iter() {
+            pixel.write_color(&mut image, 1)?;
+        }
+        return image.flush();
+    }
+
+    pub fn write_png<P : AsRef<Path>>(&self, path : P) -> image::ImageResult<()> {
+        return image::save_buffer_with_format(path, &self.to_rgb8(), self.width, self.height, image::ExtendedColorType::Rgb8, image::ImageFormat::Png);
+    }
+
+    //Picks the format from the file extension, .ppm is written as text and anything else goes through the image crate.
+    pub fn save<P : AsRef<Path>>(&self, path : P) -> image::ImageResult<()> {
+        let path : &Path = path.as_ref();
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ppm")) {
+            return self.write_ppm(path).map_err(image::ImageError::IoError);
+        }
+        return image::save_buffer(path, &self.to_rgb8(), self.width, self.height, image::ExtendedColorType::Rgb8);
+    }
+}
+
+//Light a sample deposits on pixels other than its own, such as the light tracing paths of the bidirectional
+//integrator. Every worker thread has its own, the pixels are only allocated once something is splatted.
+#[derive(Clone, Debug)]
+pub struct Splats {
+    width : u32,
+    height : u32,
+    pixels : Vec<Color3>
+}
+
+impl Splats {
+    pub fn new(width : u32, height : u32) -> Self {
+        return Self {width : width, height : height, pixels : Vec::new()};
+    }
+
+    //Adds to the pixel at film position (s, t), both in [0, 1) from the top left like Projection::generate_ray.
+    pub fn add(&mut self, s : f64, t : f64, color : Color3) {
+        if self.pixels.is_empty() {
+            self.pixels = vec![Color3::default(); (self.width as usize) * (self.height as usize)];
+        }
+        let x : usize = ((s * self.width as f64) as usize).min(self.width as usize - 1);
+        let y : usize = ((t * self.height as f64) as usize).min(self.height as usize - 1);
+        self.pixels[y * (self.width as usize) + x] += color;
+    }
+}
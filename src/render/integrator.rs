@@ -0,0 +1,215 @@
+use std::sync::Arc;
+use rand::Rng;
+use crate::math::vec3::Color3;
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::shapes::hittable::{HitRecord, Hittable, HittableList};
+use crate::shapes::material::Material;
+use crate::lights::power_heuristic;
+use crate::lights::punctual::{Light, LightSample};
+use super::camera::Camera;
+use super::framebuffer::{Framebuffer, Splats};
+use super::progress::{ProgressReporter, CancellationToken};
+use super::stats::{self, RenderStatistics};
+use super::aov::{AovSample, AovLayers, LightPass};
+use super::subsurface::subsurface_exit;
+
+//Light transport algorithm used to estimate the light arriving along each camera ray. Cameras use the PathTracer
+//unless CameraBuilder::integrator is given another one.
+pub trait Integrator : Send + Sync {
+    //Radiance arriving at the camera along ray. Light the sample deposits on other pixels goes into splats.
+    fn radiance(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats) -> Color3;
+
+    //Same as radiance, also splitting the light into passes and recording the first surface in aovs, for
+    //Camera::render_aovs. The surface is recorded from the same hit the light was found from, so stochastic cut outs
+    //can't make the two disagree.
+    fn radiance_aovs(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats, aovs : &mut AovSample) -> Color3;
+
+    //Called by Camera::render before anything else. Integrators that need a pass over the scene first (photon
+    //tracing) or don't render one sample at a time take over the whole render here, returning the beauty and, when
+    //aovs is set, its AOV layers; None keeps the tile loop.
+    fn render(&self, _camera : &Camera, _world : &Arc<HittableList>, _aovs : bool, _single_threaded : bool, _progress : Option<&Arc<dyn ProgressReporter>>, _cancel : &CancellationToken) -> Option<(Framebuffer, Option<AovLayers>, RenderStatistics)> {
+        return None;
+    }
+}
+
+//Unidirectional path tracing with direct light sampling at every non-specular bounce.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn radiance(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats) -> Color3 {
+        return self.radiance_aovs(camera, ray, world, splats, &mut AovSample::default());
+    }
+
+    //Follows a path from the camera, adding up the light it picks up until it leaves the scene, is absorbed, reaches
+    //max_depth bounces or is ended by Russian roulette. throughput is the product of the attenuations along the path.
+    //The pass light goes into is decided by the first hit and whether the light has bounced since.
+    fn radiance_aovs(&self, camera : &Camera, ray : Ray, world : &HittableList, _splats : &mut Splats, aovs : &mut AovSample) -> Color3 {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let mut radiance : Color3 = Color3::default();
+        let mut throughput : Color3 = Color3::new(1.0, 1.0, 1.0);
+        let mut ray : Ray = ray;
+
+        //Density with which the previous bounce picked this ray, None for camera rays and specular bounces. It is used
+        //to weight light found by hitting the environment against light found by sampling it.
+        let mut scatter_pdf : Option<f64> = None;
+        let mut pass : LightPass = LightPass::Emission;
+
+        for bounce in 0..camera.max_depth() {
+            let mut rec : HitRecord = HitRecord::default();
+            if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                stats::record_path_length(bounce);
+                let background : Color3 = throughput * background_radiance(camera, &ray, scatter_pdf);
+                aovs.add(pass, background);
+                return radiance + background;
+            }
+
+            if bounce == 0 {
+                aovs.record_surface(&ray, &rec);
+                pass = if rec.material.is_specular() {LightPass::Specular} else {LightPass::DirectDiffuse};
+            } else if pass == LightPass::DirectDiffuse {
+                pass = LightPass::IndirectDiffuse;
+            }
+
+            //Light entering a subsurface material comes back out elsewhere on the object, the path carries on from there.
+            if matches!(rec.material, Material::Subsurface { .. }) {
+                match subsurface_exit(world, &rec) {
+                    Some((exit, weight)) => {
+                        throughput = throughput * weight;
+                        rec = exit;
+                    }
+                    None => {
+                        stats::record_path_length(bounce);
+                        return radiance;
+                    }
+                }
+            }
+
+            if !rec.material.is_specular() {
+                let direct : Color3 = throughput * (sample_background(camera, &rec, world) + sample_lights(&rec, world, |_| true));
+                aovs.add(pass, direct);
+                radiance += direct;
+            }
+
+            let mut scattered : Ray = Ray::default();
+            let mut attenuation : Color3 = Color3::default();
+            if !rec.material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                stats::record_path_length(bounce);
+                return radiance;
+            }
+
+            throughput = throughput * attenuation;
+            scatter_pdf = if rec.material.is_specular() {None} else {Some(rec.material.pdf(&rec, &scattered.dir))};
+
+            //Survival probability follows the throughput, so paths that can only add a little more light are cut short.
+            if bounce + 1 >= camera.russian_roulette() {
+                let survival : f64 = throughput.x().max(throughput.y()).max(throughput.z()).min(0.95);
+                if rng.gen::<f64>() >= survival {
+                    stats::record_path_length(bounce + 1);
+                    return radiance;
+                }
+                throughput /= survival;
+            }
+
+            stats::count_secondary_ray();
+            ray = scattered;
+        }
+
+        stats::record_path_length(camera.max_depth());
+        return radiance;
+    }
+}
+
+//Background seen by a ray that left the scene, MIS weighted against sample_background when the ray was scattered
+//from a non-specular surface with density scatter_pdf.
+pub(crate) fn background_radiance(camera : &Camera, ray : &Ray, scatter_pdf : Option<f64>) -> Color3 {
+    let background = camera.background();
+    let weight : f64 = match scatter_pdf {
+        Some(pdf) if background.is_sampled() => power_heuristic(pdf, background.pdf(&ray.dir)),
+        _ => 1.0
+    };
+    return weight * background.radiance(&ray.dir);
+}
+
+//Direct light from the background through a shadow ray, for backgrounds that can be importance sampled.
+pub(crate) fn sample_background(camera : &Camera, rec : &HitRecord, world : &HittableList) -> Color3 {
+    return match background_shadow_sample(camera, rec, world) {
+        Some((f, radiance, weight)) => weight * (f * radiance),
+        None => Color3::default()
+    };
+}
+
+//Unoccluded background sample seen from rec: the BSDF towards it, its radiance and the MIS weight over its density.
+pub(crate) fn background_shadow_sample(camera : &Camera, rec : &HitRecord, world : &HittableList) -> Option<(Color3, Color3, f64)> {
+    let (direction, radiance, light_pdf) = camera.background().sample()?;
+    if !rec.agrees_with_surface(&direction) {
+        return None;
+    }
+
+    let f : Color3 = rec.material.eval(rec, &direction);
+    if light_pdf <= 0.0 || f.near_zero() {
+        return None;
+    }
+
+    stats::count_shadow_ray();
+    let mut shadow_rec : HitRecord = HitRecord::default();
+    if world.hit(&Ray::new(rec.p, direction), Interval::new(0.001, f64::INFINITY), &mut shadow_rec) {
+        return None;
+    }
+
+    let weight : f64 = power_heuristic(light_pdf, rec.material.pdf(rec, &direction));
+    return Some((f, radiance, weight / light_pdf));
+}
+
+//Background reached by scattering a single ray off rec, MIS weighted the same way as a path tracer's bounce. Added to
+//sample_background it gives all direct light from the background, for integrators that don't trace diffuse bounces
+//from the camera themselves, and is the only way to find the light of backgrounds that can't be sampled. ray is the
+//one that found rec, which the material scatters from.
+pub(crate) fn scatter_to_background(camera : &Camera, ray : &Ray, rec : &HitRecord, world : &HittableList) -> Color3 {
+    let mut scattered : Ray = Ray::default();
+    let mut attenuation : Color3 = Color3::default();
+    if !rec.material.scatter(ray, rec, &mut attenuation, &mut scattered) {
+        return Color3::default();
+    }
+
+    stats::count_secondary_ray();
+    let mut hit : HitRecord = HitRecord::default();
+    if world.hit(&scattered, Interval::new(0.001, f64::INFINITY), &mut hit) {
+        return Color3::default();
+    }
+    return attenuation * background_radiance(camera, &scattered, Some(rec.material.pdf(rec, &scattered.dir)));
+}
+
+//Direct light from the world's punctual lights that pass the filter. They can't be hit by scattered rays, so no MIS
+//weight is needed.
+pub(crate) fn sample_lights<F : Fn(&Light) -> bool>(rec : &HitRecord, world : &HittableList, include : F) -> Color3 {
+    let mut direct : Color3 = Color3::default();
+    for_each_light_sample(rec, world, include, |_, sample, f| direct += f * sample.radiance);
+    return direct;
+}
+
+//Calls visit with every light passing the filter that reaches rec unoccluded, its sample and the BSDF towards it.
+pub(crate) fn for_each_light_sample<F : Fn(&Light) -> bool, V : FnMut(&Light, &LightSample, Color3)>(rec : &HitRecord, world : &HittableList, include : F, mut visit : V) {
+    for light in world.lights.iter().filter(|light| include(light)) {
+        let sample : LightSample = match light.sample(&rec.p) {
+            Some(sample) => sample,
+            None => continue
+        };
+        if !rec.agrees_with_surface(&sample.direction) {
+            continue;
+        }
+
+        let f : Color3 = rec.material.eval(rec, &sample.direction);
+        if f.near_zero() || sample.radiance.near_zero() {
+            continue;
+        }
+
+        stats::count_shadow_ray();
+        let mut shadow_rec : HitRecord = HitRecord::default();
+        if world.hit(&Ray::new(rec.p, sample.direction), Interval::new(0.001, sample.distance - 0.001), &mut shadow_rec) {
+            continue;
+        }
+        visit(light, &sample, f);
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use rand::Rng;
+use crate::math::vec3::Vec3;
+use crate::math::ray::Ray;
+use super::aperture::Aperture;
+use super::projection::{CameraBasis, Projection};
+
+//One row of a lens prescription, all lengths in millimetres. Rows are listed from the front (scene side) of the lens
+//to the back, and each row describes a surface plus the medium between it and the next surface.
+#[derive(Copy, Clone, Debug)]
+pub struct LensElement {
+    pub curvature_radius : f64, //Positive when the center of curvature is towards the film, 0 for the aperture stop
+    pub thickness : f64, //Distance along the axis to the next surface
+    pub ior : f64, //Index of refraction behind the surface, 0 or 1 for air
+    pub aperture_diameter : f64
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        return self.curvature_radius == 0.0;
+    }
+
+    fn medium_ior(&self) -> f64 {
+        return if self.ior == 0.0 {1.0} else {self.ior};
+    }
+}
+
+#[derive(Debug)]
+pub enum LensError {
+    Io(std::io::Error),
+    Parse {line : usize, message : String},
+    Empty
+}
+
+impl fmt::Display for LensError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LensError::Io(error) => write!(f, "could not read lens prescription: {}", error),
+            LensError::Parse { line, message } => write!(f, "lens prescription line {}: {}", line, message),
+            LensError::Empty => write!(f, "lens prescription has no elements")
+        }
+    }
+}
+
+impl std::error::Error for LensError {}
+
+//Physically based multi-element lens. Camera rays start on the film, are aimed at the rear element and refracted
+//through every spherical surface; anything that misses an element's rim or the stop is lost, which is what produces
+//vignetting, cat's eye bokeh towards the corners and the lens's natural distortion.
+//
+//Lens space puts the film at z = 0 with the optical axis pointing at the scene along +z.
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    elements : Vec<LensElement>,
+    vertices : Vec<f64>, //z of each surface's vertex for the current film distance
+    film_distance : f64, //Distance from the rear vertex to the film
+    film_diagonal : f64,
+    units_per_mm : f64, //Scene units in one millimetre, 0.001 for scenes modelled in metres
+    stop : Aperture //Shape of the aperture stop, scaled by the stop's diameter
+}
+
+//Double Gauss 50mm f/2 (US patent 2,673,491), as tabulated in Modern Lens Design and scaled to 50mm.
+const DOUBLE_GAUSS_50MM : &str = "
+# radius    thickness   ior     aperture
+29.475      3.76        1.67    25.2
+84.83       0.12        1       25.2
+19.275      4.025       1.67    23
+40.77       3.275       1.699   23
+12.75       5.705       1       18
+0           4.5         0       17.1
+-14.495     1.18        1.603   17
+40.77       6.065       1.658   20
+-20.385     0.19        1       20
+437.065     3.22        1.717   20
+-39.73      0           1       20
+";
+
+impl LensSystem {
+    //The thickness of the last element is used as the initial distance to the film.
+    pub fn new(elements : Vec<LensElement>, film_diagonal : f64, units_per_mm : f64) -> Result<Self, LensError> {
+        let film_distance : f64 = match elements.last() {
+            Some(element) => element.thickness,
+            None => return Err(LensError::Empty)
+        };
+
+        let mut lens : LensSystem = LensSystem {
+            elements : elements,
+            vertices : Vec::new(),
+            film_distance : film_distance,
+            film_diagonal : film_diagonal,
+            units_per_mm : units_per_mm,
+            stop : Aperture::Circular
+        };
+        lens.place_elements();
+        return Ok(lens);
+    }
+
+    //Parses a whitespace separated table of radius, thickness, ior and aperture diameter. Lines starting with # are comments.
+    pub fn from_prescription(table : &str, film_diagonal : f64, units_per_mm : f64) -> Result<Self, LensError> {
+        let mut elements : Vec<LensElement> = Vec::new();
+        for (index, line) in table.lines().enumerate() {
+            let line : &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values : Vec<f64> = line.split_whitespace().map(|value| value.parse::<f64>()).collect::<Result<_, _>>()
+                .map_err(|error| LensError::Parse {line : index + 1, message : error.to_string()})?;
+            if values.len() != 4 {
+                return Err(LensError::Parse {line : index + 1, message : format!("expected 4 columns, found {}", values.len())});
+            }
+
+            elements.push(LensElement {curvature_radius : values[0], thickness : values[1], ior : values[2], aperture_diameter : values[3]});
+        }
+
+        return LensSystem::new(elements, film_diagonal, units_per_mm);
+    }
+
+    pub fn load<P : AsRef<Path>>(path : P, film_diagonal : f64, units_per_mm : f64) -> Result<Self, LensError> {
+        let table : String = fs::read_to_string(path).map_err(LensError::Io)?;
+        return LensSystem::from_prescription(&table, film_diagonal, units_per_mm);
+    }
+
+    //50mm double Gauss on a full frame (43.3mm diagonal) sensor, for scenes modelled in metres.
+    pub fn double_gauss_50mm() -> Self {
+        return LensSystem::from_prescription(DOUBLE_GAUSS_50MM, 43.3, 0.001).expect("Built in lens prescription is valid");
+    }
+
+    pub fn with_stop(mut self, stop : Aperture) -> Self {
+        self.stop = stop;
+        return self;
+    }
+
+    //Scales the diameter of the aperture stop, values below 1 stop the lens down.
+    pub fn with_stop_scale(mut self, scale : f64) -> Self {
+        for element in self.elements.iter_mut().filter(|element| element.is_stop()) {
+            element.aperture_diameter *= scale;
+        }
+        return self;
+    }
+
+    fn place_elements(&mut self) {
+        //Walk from the rear element forwards, each surface sits one thickness in front of the next.
+        self.vertices = vec![0.0; self.elements.len()];
+        let mut z : f64 = self.film_distance;
+        for index in (0..self.elements.len()).rev() {
+            if index + 1 < self.elements.len() {
+                z += self.elements[index].thickness;
+            }
+            self.vertices[index] = z;
+        }
+    }
+
+    fn rear_element(&self) -> &LensElement {
+        return self.elements.last().expect("Lens systems always have elements");
+    }
+
+    //Intersects and refracts a lens space ray with surface index. entering_ior is the medium the ray travels in,
+    //exiting_ior the medium it enters. Returns None if the ray misses the surface, is blocked, or totally reflects.
+    fn trace_surface(&self, index : usize, ray : &Ray, entering_ior : f64, exiting_ior : f64) -> Option<Ray> {
+        let element : &LensElement = &self.elements[index];
+        let vertex : f64 = self.vertices[index];
+        let aperture_radius : f64 = element.aperture_diameter / 2.0;
+
+        if element.is_stop() {
+            let t : f64 = (vertex - ray.origin.z()) / ray.dir.z();
+            if t <= 0.0 {
+                return None;
+            }
+            let p : Vec3 = ray.at(t);
+            let transmission : f64 = self.stop.transmission(p.x() / aperture_radius, p.y() / aperture_radius);
+            if transmission <= 0.0 || (transmission < 1.0 && rand::thread_rng().gen::<f64>() > transmission) {
+                return None;
+            }
+            return Some(Ray::new(p, ray.dir));
+        }
+
+        //Sphere centered on the axis, pick the intersection on the vertex's side of the sphere.
+        let center : Vec3 = Vec3::new(0.0, 0.0, vertex - element.curvature_radius);
+        let oc : Vec3 = ray.origin - center;
+        let a : f64 = ray.dir.length_squared();
+        let half_b : f64 = Vec3::dot(&oc, &ray.dir);
+        let c : f64 = oc.length_squared() - element.curvature_radius * element.curvature_radius;
+        let discriminant : f64 = half_b*half_b - a*c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let sqrtd : f64 = discriminant.sqrt();
+        let near : f64 = (-half_b - sqrtd) / a;
+        let far : f64 = (-half_b + sqrtd) / a;
+        let t : f64 = if (ray.at(near).z() - vertex).abs() < (ray.at(far).z() - vertex).abs() {near} else {far};
+        if t <= 0.0 {
+            return None;
+        }
+
+        let p : Vec3 = ray.at(t);
+        if p.x()*p.x() + p.y()*p.y() > aperture_radius * aperture_radius {
+            return None;
+        }
+
+        //Refract about the normal facing the incoming ray.
+        let mut normal : Vec3 = (p - center).unit_vector();
+        if Vec3::dot(&normal, &ray.dir) > 0.0 {
+            normal = normal.negate();
+        }
+
+        let unit_direction : Vec3 = ray.dir.unit_vector();
+        let ratio : f64 = entering_ior / exiting_ior;
+        let cos_theta : f64 = Vec3::dot(&unit_direction.negate(), &normal).min(1.0);
+        if ratio * (1.0 - cos_theta*cos_theta).sqrt() > 1.0 {
+            return None;
+        }
+
+        return Some(Ray::new(p, Vec3::refract(&unit_direction, &normal, ratio)));
+    }
+
+    //Traces a lens space ray leaving the film out through the front of the lens.
+    pub fn trace_from_film(&self, ray : &Ray) -> Option<Ray> {
+        let mut ray : Ray = Ray::new(ray.origin, ray.dir);
+        for index in (0..self.elements.len()).rev() {
+            let entering_ior : f64 = self.elements[index].medium_ior();
+            let exiting_ior : f64 = if index == 0 {1.0} else {self.elements[index - 1].medium_ior()};
+            ray = self.trace_surface(index, &ray, entering_ior, exiting_ior)?;
+        }
+        return Some(ray);
+    }
+
+    //Traces a lens space ray from the scene in through the front of the lens towards the film.
+    pub fn trace_from_scene(&self, ray : &Ray) -> Option<Ray> {
+        let mut ray : Ray = Ray::new(ray.origin, ray.dir);
+        for index in 0..self.elements.len() {
+            let entering_ior : f64 = if index == 0 {1.0} else {self.elements[index - 1].medium_ior()};
+            let exiting_ior : f64 = self.elements[index].medium_ior();
+            ray = self.trace_surface(index, &ray, entering_ior, exiting_ior)?;
+        }
+        return Some(ray);
+    }
+
+    //Moves the film so that points focus_distance scene units in front of the front element are sharp. A paraxial ray
+    //from the on axis point at that distance is traced in, and the film goes where it crosses the axis.
+    pub fn focus(mut self, focus_distance : f64) -> Self {
+        self.film_distance = 0.0;
+        self.place_elements();
+
+        let object : Vec3 = Vec3::new(0.0, 0.0, self.vertices[0] + focus_distance / self.units_per_mm);
+        let height : f64 = self.rear_element().aperture_diameter * 0.01;
+        let target : Vec3 = Vec3::new(0.0, height, self.vertices[0]);
+
+        if let Some(ray) = self.trace_from_scene(&Ray::new(object, target - object)) {
+            if ray.dir.y() < 0.0 {
+                let t : f64 = -ray.origin.y() / ray.dir.y();
+                self.film_distance = -ray.at(t).z();
+            }
+        }
+        self.place_elements();
+        return self;
+    }
+
+    fn sample_rear_element(&self) -> Vec3 {
+        let p = Vec3::random_in_unit_disk();
+        let radius : f64 = self.rear_element().aperture_diameter / 2.0;
+        return Vec3::new(p[0] * radius, p[1] * radius, self.film_distance);
+    }
+}
+
+impl Projection for LensSystem {
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray> {
+        //Film size from the diagonal and the image aspect ratio. The lens inverts the image, so the film is flipped.
+        let film_height : f64 = self.film_diagonal / (1.0 + basis.aspect_ratio * basis.aspect_ratio).sqrt();
+        let film_width : f64 = film_height * basis.aspect_ratio;
+        let film_point : Vec3 = Vec3::new((0.5 - s) * film_width, (t - 0.5) * film_height, 0.0);
+
+        let exit : Ray = self.trace_from_film(&Ray::new(film_point, self.sample_rear_element() - film_point))?;
+
+        //Lens space to world space, +z in lens space is the viewing direction -w.
+        let origin : Vec3 = basis.origin + self.units_per_mm * basis.to_world(exit.origin.x(), exit.origin.y(), -exit.origin.z());
+        let direction : Vec3 = basis.to_world(exit.dir.x(), exit.dir.y(), -exit.dir.z());
+        return Some(Ray::new(origin, direction));
+    }
+}
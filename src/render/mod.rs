@@ -1 +1,19 @@
-pub mod camera;
\ No newline at end of file
+pub mod camera;
+pub mod stats;
+pub mod progress;
+pub mod framebuffer;
+pub mod projection;
+pub mod stereo;
+pub mod aperture;
+pub mod lens;
+pub mod animation;
+pub mod integrator;
+pub mod bdpt;
+pub mod photon_map;
+pub mod photon_mapping;
+pub mod debug;
+pub mod aov;
+pub mod denoise;
+pub mod post;
+pub mod spectral;
+pub mod subsurface;
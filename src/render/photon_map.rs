@@ -0,0 +1,320 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::thread;
+use rand::Rng;
+use crate::math::vec3::{Vec3, Point3, Color3};
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::math::distribution::Distribution1D;
+use crate::shapes::hittable::{HitRecord, Hittable, HittableList};
+use super::stats::{self, RayCounters};
+
+//How the light carried by a photon got to the surface it is stored on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PhotonPath {
+    Direct, //Straight from the light
+    Caustic, //Through one or more specular bounces and nothing else
+    Indirect //Everything else, at least one bounce off a diffuse surface
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Photon {
+    pub position : Point3,
+    pub direction : Vec3, //Direction the photon was travelling in when it landed
+    pub power : Color3,
+    pub path : PhotonPath,
+    axis : u8 //Splitting axis of this photon's kd-tree node
+}
+
+//Photons landed on diffuse surfaces, balanced into a kd-tree so the ones around a point can be found quickly.
+//The tree is implicit: the photon at the middle of every range splits it along its axis.
+#[derive(Clone, Debug, Default)]
+pub struct PhotonMap {
+    photons : Vec<Photon>
+}
+
+//Photon nearest-neighbour candidate ordered by distance, for the max-heap in PhotonMap::nearest.
+struct Candidate(f64, usize);
+
+impl PartialEq for Candidate {
+    fn eq(&self, other : &Self) -> bool {
+        return self.0 == other.0;
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other : &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other : &Self) -> Ordering {
+        return self.0.total_cmp(&other.0);
+    }
+}
+
+impl PhotonMap {
+    pub fn new(mut photons : Vec<Photon>) -> Self {
+        balance(&mut photons);
+        return Self {photons : photons};
+    }
+
+    pub fn len(&self) -> usize {
+        return self.photons.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.photons.is_empty();
+    }
+
+    //Calls f with every photon within radius of p.
+    pub fn within<F : FnMut(&Photon)>(&self, p : &Point3, radius : f64, mut f : F) {
+        self.visit(0, self.photons.len(), p, radius * radius, &mut |index, _| {
+            f(&self.photons[index]);
+            radius * radius
+        });
+    }
+
+    //Up to count photons nearest p within max_radius, with the squared distance to the furthest one returned.
+    pub fn nearest(&self, p : &Point3, count : usize, max_radius : f64) -> (Vec<&Photon>, f64) {
+        let mut heap : BinaryHeap<Candidate> = BinaryHeap::with_capacity(count + 1);
+        self.visit(0, self.photons.len(), p, max_radius * max_radius, &mut |index, distance_squared| {
+            heap.push(Candidate(distance_squared, index));
+            if heap.len() > count {
+                heap.pop();
+            }
+            //Once the heap is full only photons closer than its furthest are worth looking at.
+            if heap.len() == count {heap.peek().map_or(max_radius * max_radius, |furthest| furthest.0)} else {max_radius * max_radius}
+        });
+
+        let radius_squared : f64 = heap.peek().map_or(0.0, |furthest| furthest.0);
+        return (heap.into_iter().map(|candidate| &self.photons[candidate.1]).collect(), radius_squared);
+    }
+
+    //Walks the tree below the range [low, high), calling visitor with every photon closer than radius_squared. The
+    //visitor returns the search radius to use from then on, so a k nearest search can shrink it.
+    fn visit<F : FnMut(usize, f64) -> f64>(&self, low : usize, high : usize, p : &Point3, radius_squared : f64, visitor : &mut F) -> f64 {
+        if low >= high {
+            return radius_squared;
+        }
+
+        let middle : usize = (low + high) / 2;
+        let photon : &Photon = &self.photons[middle];
+        let axis : usize = photon.axis as usize;
+        let delta : f64 = p[axis] - photon.position[axis];
+        let (near, far) = if delta < 0.0 {((low, middle), (middle + 1, high))} else {((middle + 1, high), (low, middle))};
+
+        let mut radius_squared : f64 = self.visit(near.0, near.1, p, radius_squared, visitor);
+        let distance_squared : f64 = (photon.position - *p).length_squared();
+        if distance_squared < radius_squared {
+            radius_squared = visitor(middle, distance_squared);
+        }
+        if delta * delta < radius_squared {
+            radius_squared = self.visit(far.0, far.1, p, radius_squared, visitor);
+        }
+        return radius_squared;
+    }
+}
+
+//Sorts photons into an implicit kd-tree, splitting each range at its median along the axis it is widest in.
+fn balance(photons : &mut [Photon]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let mut minimum : Vec3 = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut maximum : Vec3 = Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for photon in photons.iter() {
+        for axis in 0..3 {
+            minimum[axis] = minimum[axis].min(photon.position[axis]);
+            maximum[axis] = maximum[axis].max(photon.position[axis]);
+        }
+    }
+    let extent : Vec3 = maximum - minimum;
+    let axis : usize = if extent.x() >= extent.y() && extent.x() >= extent.z() {0} else if extent.y() >= extent.z() {1} else {2};
+
+    let middle : usize = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+    photons[middle].axis = axis as u8;
+
+    let (below, above) = photons.split_at_mut(middle);
+    balance(below);
+    balance(&mut above[1..]);
+}
+
+//Which landings trace_photons keeps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PhotonStorage {
+    All,
+    CausticOnly,
+    IndirectOnly //Caustic and indirect, everything but direct light
+}
+
+//Emits count photons from the world's point and spot lights, picked in proportion to their power, and follows each
+//one through the scene, storing it wherever it lands on a diffuse surface. Powers are divided by count, so the photons
+//together carry the lights' total power. Work is split over thread_count threads whose counters are returned.
+pub fn trace_photons(world : &HittableList, count : usize, storage : PhotonStorage, max_depth : u32, roulette_min_bounces : u32, thread_count : usize) -> (Vec<Photon>, Vec<RayCounters>) {
+    let power : Vec<f64> = world.lights.iter().map(|light| if light.is_positional() {light.power()} else {0.0}).collect();
+    if count == 0 || power.iter().sum::<f64>() <= 0.0 {
+        return (Vec::new(), Vec::new());
+    }
+    let light_choice : Distribution1D = Distribution1D::new(&power);
+
+    let thread_count : usize = thread_count.clamp(1, count);
+    let results : Vec<(Vec<Photon>, RayCounters)> = thread::scope(|scope| {
+        let handles : Vec<_> = (0..thread_count).map(|worker| {
+            let light_choice : &Distribution1D = &light_choice;
+            scope.spawn(move || {
+                let emitted : usize = count / thread_count + if worker < count % thread_count {1} else {0};
+                let mut photons : Vec<Photon> = Vec::new();
+                for _ in 0..emitted {
+                    trace_photon(world, light_choice, count, storage, max_depth, roulette_min_bounces, &mut photons);
+                }
+                return (photons, stats::take_thread_counters());
+            })
+        }).collect();
+        return handles.into_iter().map(|handle| handle.join().expect("Photon thread panicked.")).collect();
+    });
+
+    let mut photons : Vec<Photon> = Vec::new();
+    let mut counters : Vec<RayCounters> = Vec::new();
+    for (worker_photons, worker_counters) in results {
+        photons.extend(worker_photons);
+        counters.push(worker_counters);
+    }
+    return (photons, counters);
+}
+
+fn trace_photon(world : &HittableList, light_choice : &Distribution1D, count : usize, storage : PhotonStorage, max_depth : u32, roulette_min_bounces : u32, photons : &mut Vec<Photon>) {
+    let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+    let (index, light_pdf) = light_choice.sample_discrete(rng.gen());
+    let light = &world.lights[index];
+    let (origin, direction, intensity, direction_pdf) = match light.sample_emission() {
+        Some(emission) if emission.3 > 0.0 => emission,
+        _ => return
+    };
+
+    let mut power : Color3 = (1.0 / (light_pdf * direction_pdf * count as f64)) * intensity;
+    let mut ray : Ray = Ray::new(origin, direction);
+    let mut path : PhotonPath = PhotonPath::Direct;
+
+    for bounce in 0..max_depth {
+        stats::count_secondary_ray();
+        let mut rec : HitRecord = HitRecord::default();
+        if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return;
+        }
+        if bounce == 0 {
+            power = light.falloff_scale((rec.p - origin).length()) * power;
+        }
+
+        let specular : bool = rec.material.is_specular();
+        if !specular {
+            let keep : bool = match storage {
+                PhotonStorage::All => true,
+                PhotonStorage::CausticOnly => path == PhotonPath::Caustic,
+                PhotonStorage::IndirectOnly => path != PhotonPath::Direct
+            };
+            if keep {
+                photons.push(Photon {position : rec.p, direction : ray.dir.unit_vector(), power : power, path : path, axis : 0});
+            }
+            //Only caustic paths are wanted, and this one just left a diffuse surface.
+            if storage == PhotonStorage::CausticOnly {
+                return;
+            }
+        }
+
+        let mut scattered : Ray = Ray::default();
+        let mut attenuation : Color3 = Color3::default();
+        if !rec.material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+            return;
+        }
+
+        path = match (path, specular) {
+            (PhotonPath::Direct, true) | (PhotonPath::Caustic, true) => PhotonPath::Caustic,
+            _ => PhotonPath::Indirect
+        };
+        power = power * attenuation;
+
+        //Photons keep their power through roulette by surviving with the probability of the surface's reflectance.
+        if bounce + 1 >= roulette_min_bounces {
+            let survival : f64 = attenuation.x().max(attenuation.y()).max(attenuation.z()).min(0.95);
+            if rng.gen::<f64>() >= survival {
+                return;
+            }
+            power /= survival;
+        }
+        ray = scattered;
+    }
+}
+
+//Power a photon reflects off the surface at rec towards the viewer. Summed over the photons around the hit and divided
+//by the area of the disc they were gathered from, this gives the reflected radiance. Photons arriving from behind the
+//surface reflect nothing.
+pub fn reflected_power(rec : &HitRecord, photon : &Photon) -> Color3 {
+    let incoming : Vec3 = photon.direction.negate();
+    let cosine : f64 = Vec3::dot(&rec.normal, &incoming);
+    if cosine <= 0.0 {
+        return Color3::default();
+    }
+    return (1.0 / cosine) * (rec.material.eval(rec, &incoming) * photon.power);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    fn random_map(count : usize, rng : &mut StdRng) -> (PhotonMap, Vec<Point3>) {
+        let positions : Vec<Point3> = (0..count).map(|_| Point3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-0.2..0.2))).collect();
+        let photons : Vec<Photon> = positions.iter().map(|position| Photon {position : *position, direction : Vec3::new(0.0, 0.0, -1.0), power : Color3::new(1.0, 1.0, 1.0), path : PhotonPath::Direct, axis : 0}).collect();
+        return (PhotonMap::new(photons), positions);
+    }
+
+    fn sorted_distances<'a, I : Iterator<Item = &'a Point3>>(positions : I, p : &Point3) -> Vec<f64> {
+        let mut distances : Vec<f64> = positions.map(|position| (*position - *p).length_squared()).collect();
+        distances.sort_by(f64::total_cmp);
+        return distances;
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut rng : StdRng = StdRng::seed_from_u64(7);
+        let (map, positions) = random_map(2000, &mut rng);
+        for _ in 0..50 {
+            let p : Point3 = Point3::new(rng.gen_range(-1.2..1.2), rng.gen_range(-1.2..1.2), rng.gen_range(-0.3..0.3));
+            let (count, max_radius) : (usize, f64) = (rng.gen_range(1..40), rng.gen_range(0.05..0.5));
+
+            let expected : Vec<f64> = sorted_distances(positions.iter(), &p).into_iter().filter(|d| *d < max_radius * max_radius).take(count).collect();
+            let (found, radius_squared) = map.nearest(&p, count, max_radius);
+            let found : Vec<f64> = sorted_distances(found.iter().map(|photon| &photon.position), &p);
+
+            assert_eq!(found, expected);
+            assert_eq!(radius_squared, expected.last().copied().unwrap_or(0.0));
+        }
+    }
+
+    #[test]
+    fn within_matches_brute_force() {
+        let mut rng : StdRng = StdRng::seed_from_u64(11);
+        let (map, positions) = random_map(500, &mut rng);
+        let p : Point3 = Point3::new(0.1, -0.3, 0.0);
+        let radius : f64 = 0.4;
+
+        let mut found : Vec<Point3> = Vec::new();
+        map.within(&p, radius, |photon| found.push(photon.position));
+        let expected : Vec<f64> = sorted_distances(positions.iter(), &p).into_iter().filter(|d| *d < radius * radius).collect();
+        assert_eq!(sorted_distances(found.iter(), &p), expected);
+    }
+
+    #[test]
+    fn empty_map_finds_nothing() {
+        let map : PhotonMap = PhotonMap::default();
+        let (found, radius_squared) = map.nearest(&Point3::default(), 10, 1.0);
+        assert!(found.is_empty());
+        assert_eq!(radius_squared, 0.0);
+    }
+}
@@ -0,0 +1,426 @@
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::available_parallelism;
+use std::time::{Duration, Instant};
+use crate::math::vec3::Color3;
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::shapes::hittable::{HitRecord, Hittable, HittableList};
+use super::camera::Camera;
+use super::framebuffer::{Framebuffer, Splats};
+use super::progress::{Progress, ProgressReporter, CancellationToken};
+use super::stats::{self, RenderStatistics, RayCounters};
+use super::aov::{AovSample, AovPixel, AovLayers, LightPass};
+use super::integrator::{Integrator, background_radiance, sample_background, scatter_to_background, sample_lights};
+use super::photon_map::{Photon, PhotonMap, PhotonPath, PhotonStorage, trace_photons, reflected_power};
+
+//Two pass photon mapping after Jensen. Photons from the point and spot lights are traced into a global map and a
+//caustic map before rendering; camera rays then take direct light from shadow rays, caustics from the caustic map and
+//the remaining indirect light from the global map, either read at the first diffuse hit or through final gathering.
+//The environment and directional lights emit no photons, their indirect light only arrives through final gathering.
+#[derive(Clone, Debug)]
+pub struct PhotonMapping {
+    photons : usize, //Emitted for the global map
+    caustic_photons : usize, //Emitted for the caustic map, only those reaching a diffuse surface through specular bounces are kept
+    gather_count : usize, //Photons used per density estimate
+    max_radius : f64, //Furthest a density estimate looks for photons
+    final_gather : u32, //Rays sent out from each diffuse hit to find indirect light, 0 reads the global map directly
+    maps : Option<Arc<PhotonMaps>>
+}
+
+#[derive(Debug, Default)]
+struct PhotonMaps {
+    global : PhotonMap,
+    caustic : PhotonMap
+}
+
+impl Default for PhotonMapping {
+    fn default() -> Self {
+        return Self {
+            photons : 100_000,
+            caustic_photons : 200_000,
+            gather_count : 80,
+            max_radius : 0.25,
+            final_gather : 16,
+            maps : None
+        };
+    }
+}
+
+impl PhotonMapping {
+    pub fn new(photons : usize, caustic_photons : usize) -> Self {
+        return Self {photons : photons, caustic_photons : caustic_photons, ..Self::default()};
+    }
+
+    //Nearest count photons within max_radius of a point make up each estimate. More photons blur more but are smoother.
+    pub fn with_gather(self, count : usize, max_radius : f64) -> Self {
+        return Self {gather_count : count.max(1), max_radius : max_radius, ..self};
+    }
+
+    pub fn with_final_gather(self, rays : u32) -> Self {
+        return Self {final_gather : rays, ..self};
+    }
+
+    //Incoming light at a diffuse hit found by cosine sampled gather rays. Where they land on another diffuse surface the
+    //maps give all the light leaving it, caustics from the denser caustic map, and shadow rays add the environment and
+    //directional light the photons don't carry. Background seen straight from rec is returned apart as direct light. incoming is the ray that found rec.
+    fn gather(&self, camera : &Camera, incoming : &Ray, rec : &HitRecord, world : &HittableList, maps : &PhotonMaps) -> (Color3, Color3) {
+        let mut direct : Color3 = Color3::default();
+        let mut gathered : Color3 = Color3::default();
+        for _ in 0..self.final_gather {
+            let mut scattered : Ray = Ray::default();
+            let mut throughput : Color3 = Color3::default();
+            if !rec.material.scatter(incoming, rec, &mut throughput, &mut scattered) {
+                continue;
+            }
+            let mut scatter_pdf : Option<f64> = Some(rec.material.pdf(rec, &scattered.dir));
+            let mut ray : Ray = scattered;
+
+            for _ in 1..camera.max_depth() {
+                stats::count_secondary_ray();
+                let mut hit : HitRecord = HitRecord::default();
+                if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut hit) {
+                    let background : Color3 = throughput * background_radiance(camera, &ray, scatter_pdf);
+                    if scatter_pdf.is_some() {direct += background} else {gathered += background}
+                    break;
+                }
+
+                if !hit.material.is_specular() {
+                    let mapped : Color3 = estimate(&maps.global, &hit, self.gather_count, self.max_radius, |photon| photon.path != PhotonPath::Caustic)
+                        + estimate(&maps.caustic, &hit, self.gather_count, self.max_radius, |_| true);
+                    let unmapped : Color3 = sample_background(camera, &hit, world) + sample_lights(&hit, world, |light| !light.is_positional());
+                    gathered += throughput * (mapped + unmapped);
+                    break;
+                }
+
+                let mut next : Ray = Ray::default();
+                let mut attenuation : Color3 = Color3::default();
+                if !hit.material.scatter(&ray, &hit, &mut attenuation, &mut next) {
+                    break;
+                }
+                throughput = throughput * attenuation;
+                scatter_pdf = None;
+                ray = next;
+            }
+        }
+        let scale : f64 = 1.0 / (self.final_gather.max(1) as f64);
+        return (scale * direct, scale * gathered);
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn radiance(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats) -> Color3 {
+        return self.radiance_aovs(camera, ray, world, splats, &mut AovSample::default());
+    }
+
+    //Follows specular bounces from the camera to the first diffuse surface and shades it from the photon maps. Without
+    //maps, when called outside Camera::render, only direct light is found.
+    fn radiance_aovs(&self, camera : &Camera, ray : Ray, world : &HittableList, _splats : &mut Splats, aovs : &mut AovSample) -> Color3 {
+        let empty : PhotonMaps = PhotonMaps::default();
+        let maps : &PhotonMaps = self.maps.as_deref().unwrap_or(&empty);
+        let mut throughput : Color3 = Color3::new(1.0, 1.0, 1.0);
+        let mut ray : Ray = ray;
+
+        for bounce in 0..camera.max_depth() {
+            let mut rec : HitRecord = HitRecord::default();
+            if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                stats::record_path_length(bounce);
+                let background : Color3 = throughput * background_radiance(camera, &ray, None);
+                aovs.add(if bounce == 0 {LightPass::Emission} else {LightPass::Specular}, background);
+                return background;
+            }
+            aovs.record_surface(&ray, &rec);
+
+            if !rec.material.is_specular() {
+                stats::record_path_length(bounce);
+                let mut direct : Color3 = sample_background(camera, &rec, world) + sample_lights(&rec, world, |_| true);
+                let caustic : Color3 = estimate(&maps.caustic, &rec, self.gather_count, self.max_radius, |_| true);
+                let indirect : Color3 = if self.final_gather > 0 {
+                    let (gathered_direct, gathered) = self.gather(camera, &ray, &rec, world, maps);
+                    direct += gathered_direct;
+                    gathered
+                } else {
+                    direct += scatter_to_background(camera, &ray, &rec, world);
+                    estimate(&maps.global, &rec, self.gather_count, self.max_radius, |photon| photon.path == PhotonPath::Indirect)
+                };
+                if bounce == 0 {
+                    aovs.add(LightPass::DirectDiffuse, throughput * direct);
+                    aovs.add(LightPass::IndirectDiffuse, throughput * (caustic + indirect));
+                } else {
+                    aovs.add(LightPass::Specular, throughput * (direct + caustic + indirect));
+                }
+                return throughput * (direct + caustic + indirect);
+            }
+
+            let mut scattered : Ray = Ray::default();
+            let mut attenuation : Color3 = Color3::default();
+            if !rec.material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                stats::record_path_length(bounce);
+                return Color3::default();
+            }
+            throughput = throughput * attenuation;
+            stats::count_secondary_ray();
+            ray = scattered;
+        }
+
+        stats::record_path_length(camera.max_depth());
+        return Color3::default();
+    }
+
+    //Traces both photon maps, then renders the image with the usual tile loop using a copy of this integrator that
+    //holds them.
+    fn render(&self, camera : &Camera, world : &Arc<HittableList>, aovs : bool, single_threaded : bool, progress : Option<&Arc<dyn ProgressReporter>>, cancel : &CancellationToken) -> Option<(Framebuffer, Option<AovLayers>, RenderStatistics)> {
+        if self.maps.is_some() {
+            return None;
+        }
+
+        let thread_count : usize = if single_threaded {1} else {available_parallelism().map(|n| n.get()).unwrap_or(1)};
+        let mut statistics : RenderStatistics = RenderStatistics::new();
+        let (maps, counters) = statistics.time_phase(stats::PHOTON_PHASE, || {
+            let (global, mut counters) = trace_photons(world, self.photons, PhotonStorage::All, camera.max_depth(), camera.russian_roulette(), thread_count);
+            let (caustic, caustic_counters) = trace_photons(world, self.caustic_photons, PhotonStorage::CausticOnly, camera.max_depth(), camera.russian_roulette(), thread_count);
+            counters.extend(caustic_counters);
+            return (PhotonMaps {global : PhotonMap::new(global), caustic : PhotonMap::new(caustic)}, counters);
+        });
+        for thread_counters in counters {
+            statistics.add_thread(thread_counters);
+        }
+
+        let prepared : PhotonMapping = PhotonMapping {maps : Some(Arc::new(maps)), ..self.clone()};
+        let (framebuffer, layers, render_statistics) = camera.clone().render_tiles(Arc::new(prepared), Arc::clone(world), aovs, single_threaded, progress, cancel);
+        statistics.merge(render_statistics);
+        return Some((framebuffer, layers, statistics));
+    }
+}
+
+//Reflected radiance at a diffuse hit from the nearest photons that pass the filter, their reflected power over the
+//area of the disc holding them.
+fn estimate<F : Fn(&Photon) -> bool>(map : &PhotonMap, rec : &HitRecord, count : usize, max_radius : f64, filter : F) -> Color3 {
+    if map.is_empty() {
+        return Color3::default();
+    }
+    //With fewer than count photons inside max_radius the disc they were searched in is the area to use, the distance
+    //to the furthest of a handful of stray photons would make a bright speck out of them.
+    let (photons, furthest_squared) = map.nearest(&rec.p, count, max_radius);
+    let radius_squared : f64 = if photons.len() < count {max_radius * max_radius} else {furthest_squared};
+    if radius_squared <= 0.0 {
+        return Color3::default();
+    }
+
+    let mut reflected : Color3 = Color3::default();
+    for photon in photons.into_iter().filter(|photon| filter(photon)) {
+        reflected += reflected_power(rec, photon);
+    }
+    return reflected / (PI * radius_squared);
+}
+
+//Stochastic progressive photon mapping (Hachisuka and Jensen). Every one of the camera's samples per pixel is a pass:
+//a fresh batch of photons is traced, then a jittered camera ray per pixel finds a diffuse point and gathers the photons
+//around it. Each pixel's radius shrinks as photons arrive, so the estimate converges to the right answer without a
+//radius having to be chosen for the whole scene, and caustics sharpen as the passes go on.
+//Direct light comes from shadow rays, the photons carry everything else. Like PhotonMapping, only point and spot
+//lights emit photons. Progress is reported per pass and cancellation is checked between passes.
+#[derive(Copy, Clone, Debug)]
+pub struct ProgressivePhotonMapping {
+    photons_per_pass : usize,
+    initial_radius : f64,
+    alpha : f64 //Fraction of each pass's photons kept when shrinking the radius, between 0 and 1
+}
+
+//Running estimate of one pixel.
+#[derive(Copy, Clone, Debug)]
+struct PixelEstimate {
+    radius_squared : f64,
+    photon_count : f64,
+    flux : Color3, //Unnormalized flux gathered so far, scaled down as the radius shrinks
+    specular_flux : Color3, //Part of flux gathered through a specular first hit
+    direct : Color3, //Sum of every pass's directly lit and directly seen light
+    aovs : AovPixel //The same split into passes, with the first surfaces
+}
+
+impl Default for ProgressivePhotonMapping {
+    fn default() -> Self {
+        return Self {photons_per_pass : 200_000, initial_radius : 0.05, alpha : 0.7};
+    }
+}
+
+impl ProgressivePhotonMapping {
+    pub fn new(photons_per_pass : usize, initial_radius : f64) -> Self {
+        return Self {photons_per_pass : photons_per_pass, initial_radius : initial_radius, ..Self::default()};
+    }
+
+    pub fn with_alpha(self, alpha : f64) -> Self {
+        return Self {alpha : alpha.clamp(0.01, 1.0), ..self};
+    }
+
+    //One pass over the pixels in row, adding this pass's light to their estimates.
+    fn shade_row(&self, camera : &Camera, world : &HittableList, map : &PhotonMap, row : u32, pixels : &mut [PixelEstimate]) {
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let ray : Ray = match camera.get_ray(i as u32, row) {
+                Some(ray) => ray,
+                None => continue
+            };
+            stats::count_camera_ray();
+
+            let mut sample : AovSample = AovSample::default();
+            let (direct, visible) = self.visible_point(camera, ray, world, &mut sample);
+            pixel.direct += direct;
+            pixel.aovs.add(&sample);
+            let (rec, throughput, pass) = match visible {
+                Some(visible) => visible,
+                None => continue
+            };
+
+            let mut gathered : f64 = 0.0;
+            let mut flux : Color3 = Color3::default();
+            map.within(&rec.p, pixel.radius_squared.sqrt(), |photon| {
+                gathered += 1.0;
+                flux += reflected_power(&rec, photon);
+            });
+            if gathered == 0.0 {
+                continue;
+            }
+
+            let photon_count : f64 = pixel.photon_count + self.alpha * gathered;
+            let ratio : f64 = photon_count / (pixel.photon_count + gathered);
+            pixel.radius_squared *= ratio;
+            pixel.flux = ratio * (pixel.flux + throughput * flux);
+            if pass == LightPass::Specular {
+                pixel.specular_flux = ratio * (pixel.specular_flux + throughput * flux);
+            } else {
+                pixel.specular_flux = ratio * pixel.specular_flux;
+            }
+            pixel.photon_count = photon_count;
+        }
+    }
+
+    //Follows specular bounces from the camera to the first diffuse surface. Returns the light found on the way, including
+    //the direct light at that surface, and the surface with the throughput reaching it and the pass its photons go in.
+    fn visible_point(&self, camera : &Camera, ray : Ray, world : &HittableList, aovs : &mut AovSample) -> (Color3, Option<(HitRecord, Color3, LightPass)>) {
+        let mut throughput : Color3 = Color3::new(1.0, 1.0, 1.0);
+        let mut ray : Ray = ray;
+
+        for bounce in 0..camera.max_depth() {
+            let mut rec : HitRecord = HitRecord::default();
+            if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                stats::record_path_length(bounce);
+                let background : Color3 = throughput * background_radiance(camera, &ray, None);
+                aovs.add(if bounce == 0 {LightPass::Emission} else {LightPass::Specular}, background);
+                return (background, None);
+            }
+            aovs.record_surface(&ray, &rec);
+
+            if !rec.material.is_specular() {
+                stats::record_path_length(bounce);
+                let background : Color3 = sample_background(camera, &rec, world) + scatter_to_background(camera, &ray, &rec, world);
+                let direct : Color3 = throughput * (background + sample_lights(&rec, world, |_| true));
+                let (direct_pass, photon_pass) = if bounce == 0 {(LightPass::DirectDiffuse, LightPass::IndirectDiffuse)} else {(LightPass::Specular, LightPass::Specular)};
+                aovs.add(direct_pass, direct);
+                return (direct, Some((rec, throughput, photon_pass)));
+            }
+
+            let mut scattered : Ray = Ray::default();
+            let mut attenuation : Color3 = Color3::default();
+            if !rec.material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                stats::record_path_length(bounce);
+                return (Color3::default(), None);
+            }
+            throughput = throughput * attenuation;
+            stats::count_secondary_ray();
+            ray = scattered;
+        }
+
+        stats::record_path_length(camera.max_depth());
+        return (Color3::default(), None);
+    }
+}
+
+impl Integrator for ProgressivePhotonMapping {
+    //Direct light only. The photon contribution needs every pass's estimate, which only the render below keeps.
+    fn radiance(&self, camera : &Camera, ray : Ray, world : &HittableList, _splats : &mut Splats) -> Color3 {
+        return self.visible_point(camera, ray, world, &mut AovSample::default()).0;
+    }
+
+    fn radiance_aovs(&self, camera : &Camera, ray : Ray, world : &HittableList, _splats : &mut Splats, aovs : &mut AovSample) -> Color3 {
+        return self.visible_point(camera, ray, world, aovs).0;
+    }
+
+    fn render(&self, camera : &Camera, world : &Arc<HittableList>, aovs : bool, single_threaded : bool, progress : Option<&Arc<dyn ProgressReporter>>, cancel : &CancellationToken) -> Option<(Framebuffer, Option<AovLayers>, RenderStatistics)> {
+        let render_start : Instant = Instant::now();
+        let width : u32 = camera.image_width();
+        let height : u32 = camera.image_height();
+        let passes : u32 = camera.samples_per_pixel();
+        let thread_count : usize = if single_threaded {1} else {available_parallelism().map(|n| n.get()).unwrap_or(1)};
+
+        let initial : PixelEstimate = PixelEstimate {radius_squared : self.initial_radius * self.initial_radius, photon_count : 0.0, flux : Color3::default(), specular_flux : Color3::default(), direct : Color3::default(), aovs : AovPixel::default()};
+        let mut pixels : Vec<PixelEstimate> = vec![initial; (width * height) as usize];
+        let mut counters : Vec<RayCounters> = vec![RayCounters::default(); thread_count];
+        let mut photon_time : Duration = Duration::ZERO;
+        let mut passes_done : u32 = 0;
+
+        for pass in 0..passes {
+            if cancel.is_cancelled() {
+                break;
+            }
+
+            let photon_start : Instant = Instant::now();
+            let (photons, photon_counters) = trace_photons(world, self.photons_per_pass, PhotonStorage::IndirectOnly, camera.max_depth(), camera.russian_roulette(), thread_count);
+            let map : PhotonMap = PhotonMap::new(photons);
+            photon_time += photon_start.elapsed();
+            for (total, thread_counters) in counters.iter_mut().zip(photon_counters.iter()) {
+                total.merge(thread_counters);
+            }
+
+            //Rows are handed out from a shared queue like the tiles of the regular render.
+            let rows : Mutex<Vec<(u32, &mut [PixelEstimate])>> = Mutex::new(pixels.chunks_mut(width as usize).enumerate().map(|(j, row)| (j as u32, row)).rev().collect());
+            let pass_counters : Vec<RayCounters> = thread::scope(|scope| {
+                let handles : Vec<_> = (0..thread_count).map(|_| scope.spawn(|| {
+                    loop {
+                        let next : Option<(u32, &mut [PixelEstimate])> = rows.lock().expect("Row queue poisoned.").pop();
+                        match next {
+                            Some((j, row)) => self.shade_row(camera, world, &map, j, row),
+                            None => break
+                        }
+                    }
+                    return stats::take_thread_counters();
+                })).collect();
+                return handles.into_iter().map(|handle| handle.join().expect("Render thread panicked.")).collect();
+            });
+            for (total, thread_counters) in counters.iter_mut().zip(pass_counters.iter()) {
+                total.merge(thread_counters);
+            }
+
+            passes_done = pass + 1;
+            if let Some(reporter) = progress {
+                reporter.report(&Progress {tiles_completed : passes_done, tiles_total : passes, elapsed : render_start.elapsed()});
+            }
+        }
+
+        let mut framebuffer : Framebuffer = Framebuffer::new(width, height);
+        let mut layers : Option<AovLayers> = if aovs {Some(AovLayers::new(width, height))} else {None};
+        if passes_done > 0 {
+            let scale : f64 = 1.0 / (passes_done as f64);
+            for (index, pixel) in pixels.iter().enumerate() {
+                let (x, y) = (index as u32 % width, index as u32 / width);
+                let photons : Color3 = pixel.flux / (PI * pixel.radius_squared);
+                framebuffer.set(x, y, scale * (pixel.direct + photons));
+
+                if let Some(layers) = &mut layers {
+                    let specular_photons : Color3 = pixel.specular_flux / (PI * pixel.radius_squared);
+                    pixel.aovs.resolve(layers, x, y, scale);
+                    layers.indirect_diffuse.set(x, y, layers.indirect_diffuse.get(x, y) + scale * (photons - specular_photons));
+                    layers.specular.set(x, y, layers.specular.get(x, y) + scale * specular_photons);
+                }
+            }
+        }
+
+        let mut statistics : RenderStatistics = RenderStatistics::new();
+        for thread_counters in counters {
+            statistics.add_thread(thread_counters);
+        }
+        statistics.add_phase(stats::PHOTON_PHASE, photon_time);
+        statistics.add_phase(stats::RENDER_PHASE, render_start.elapsed().saturating_sub(photon_time));
+        return Some((framebuffer, layers, statistics));
+    }
+}
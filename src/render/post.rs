@@ -0,0 +1,301 @@
+use std::f64::consts::PI;
+use crate::math::vec3::Color3;
+use super::framebuffer::Framebuffer;
+
+//Lens and film effects applied to the finished HDR image, before it is encoded. Effects run in the order they were
+//added. Sizes are fractions of the image width so the same stack works at any resolution.
+#[derive(Clone, Debug, Default)]
+pub struct PostProcess {
+    effects : Vec<PostEffect>
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum PostEffect {
+    Bloom {threshold : f64, intensity : f64, radius : f64}, //Light above threshold luminance, blurred over radius and added back
+    Glare {threshold : f64, intensity : f64, length : f64, streaks : u32}, //Star shaped streaks from light above threshold
+    Vignette {strength : f64}, //Darkening towards the corners, 0 is none and 1 makes the corners black
+    ChromaticAberration {strength : f64}, //Red and blue pushed apart radially, by this fraction of the width at the corners
+    FilmGrain {amount : f64, seed : u64}, //Noise strongest in the midtones, change the seed per frame for moving grain
+    ColorGrade(ColorGrade)
+}
+
+//Color correction. Lift raises the blacks, gamma bends the midtones and gain scales the whites, per channel. White
+//balance neutralizes light of the given color temperature.
+#[derive(Copy, Clone, Debug)]
+pub struct ColorGrade {
+    pub lift : Color3,
+    pub gamma : Color3,
+    pub gain : Color3,
+    pub saturation : f64, //0 is grayscale, 1 leaves colors alone
+    pub white_balance : f64 //Kelvin, 6500 leaves colors alone and lower values cool the image down
+}
+
+impl Default for ColorGrade {
+    fn default() -> Self {
+        return Self {
+            lift : Color3::new(0.0, 0.0, 0.0),
+            gamma : Color3::new(1.0, 1.0, 1.0),
+            gain : Color3::new(1.0, 1.0, 1.0),
+            saturation : 1.0,
+            white_balance : 6500.0
+        };
+    }
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn with_effect(mut self, effect : PostEffect) -> Self {
+        self.effects.push(effect);
+        return self;
+    }
+
+    pub fn effects(&self) -> &[PostEffect] {
+        return &self.effects;
+    }
+
+    pub fn apply(&self, image : &Framebuffer) -> Framebuffer {
+        let mut image : Framebuffer = image.clone();
+        for effect in self.effects.iter() {
+            image = effect.apply(&image);
+        }
+        return image;
+    }
+}
+
+impl PostEffect {
+    pub fn bloom(threshold : f64, intensity : f64, radius : f64) -> Self {
+        return PostEffect::Bloom {threshold : threshold, intensity : intensity, radius : radius};
+    }
+
+    pub fn glare(threshold : f64, intensity : f64, length : f64, streaks : u32) -> Self {
+        return PostEffect::Glare {threshold : threshold, intensity : intensity, length : length, streaks : streaks.max(1)};
+    }
+
+    pub fn vignette(strength : f64) -> Self {
+        return PostEffect::Vignette {strength : strength.clamp(0.0, 1.0)};
+    }
+
+    pub fn chromatic_aberration(strength : f64) -> Self {
+        return PostEffect::ChromaticAberration {strength : strength};
+    }
+
+    pub fn film_grain(amount : f64, seed : u64) -> Self {
+        return PostEffect::FilmGrain {amount : amount, seed : seed};
+    }
+
+    pub fn apply(&self, image : &Framebuffer) -> Framebuffer {
+        let width : f64 = image.width as f64;
+        match self {
+            PostEffect::Bloom { threshold, intensity, radius } => {
+                let blurred : Framebuffer = gaussian_blur(&bright_pass(image, *threshold), radius * width);
+                return add_scaled(image, &blurred, *intensity);
+            }
+
+            PostEffect::Glare { threshold, intensity, length, streaks } => {
+                let streaked : Framebuffer = glare_streaks(&bright_pass(image, *threshold), length * width, *streaks);
+                return add_scaled(image, &streaked, *intensity);
+            }
+
+            PostEffect::Vignette { strength } => {
+                let mut result : Framebuffer = image.clone();
+                for_each_pixel(&mut result, |_, _, radius, color| {
+                    let falloff : f64 = 1.0 - strength * radius * radius;
+                    return falloff.max(0.0).powi(2) * color;
+                });
+                return result;
+            }
+
+            PostEffect::ChromaticAberration { strength } => {
+                let (center_x, center_y) = (0.5 * image.width as f64, 0.5 * image.height as f64);
+                let mut result : Framebuffer = image.clone();
+                for_each_pixel(&mut result, |x, y, _, color| {
+                    //Red is magnified and blue shrunk about the center, green stays put.
+                    let (dx, dy) = (x - center_x, y - center_y);
+                    let scale : f64 = strength * width / (center_x * center_x + center_y * center_y).sqrt();
+                    let red : f64 = sample_bilinear(image, center_x + dx * (1.0 - scale), center_y + dy * (1.0 - scale)).x();
+                    let blue : f64 = sample_bilinear(image, center_x + dx * (1.0 + scale), center_y + dy * (1.0 + scale)).z();
+                    return Color3::new(red, color.y(), blue);
+                });
+                return result;
+            }
+
+            PostEffect::FilmGrain { amount, seed } => {
+                let mut result : Framebuffer = image.clone();
+                for_each_pixel(&mut result, |x, y, _, color| {
+                    //Triangular noise from two hashed uniforms, weighted to peak at mid gray.
+                    let index : u64 = (y as u64) << 32 | x as u64;
+                    let noise : f64 = hash_uniform(index, *seed) + hash_uniform(index, seed.wrapping_add(0x9e37_79b9)) - 1.0;
+                    let luminance : f64 = color.luminance().clamp(0.0, 1.0);
+                    let weight : f64 = 4.0 * luminance * (1.0 - luminance);
+                    return (1.0 + amount * weight * noise).max(0.0) * color;
+                });
+                return result;
+            }
+
+            PostEffect::ColorGrade(grade) => {
+                let mut result : Framebuffer = image.clone();
+                let balance : Color3 = white_balance(grade.white_balance);
+                for_each_pixel(&mut result, |_, _, _, color| grade.apply(balance * color));
+                return result;
+            }
+        }
+    }
+}
+
+impl ColorGrade {
+    //Lift/gamma/gain on a white balanced color, then saturation around its luminance.
+    fn apply(&self, color : Color3) -> Color3 {
+        let mut graded : Color3 = Color3::default();
+        for axis in 0..3 {
+            let lifted : f64 = self.gain[axis] * (color[axis] + self.lift[axis] * (1.0 - color[axis]));
+            graded[axis] = lifted.max(0.0).powf(1.0 / self.gamma[axis].max(1e-4));
+        }
+        let luminance : Color3 = graded.luminance() * Color3::new(1.0, 1.0, 1.0);
+        let saturated : Color3 = luminance + self.saturation * (graded - luminance);
+        return Color3::new(saturated.x().max(0.0), saturated.y().max(0.0), saturated.z().max(0.0));
+    }
+}
+
+//Calls f with the position, distance from the center (1 at the corners) and color of every pixel, storing the result.
+fn for_each_pixel<F : FnMut(f64, f64, f64, Color3) -> Color3>(image : &mut Framebuffer, mut f : F) {
+    let (width, height) = (image.width, image.height);
+    let (center_x, center_y) = (0.5 * width as f64, 0.5 * height as f64);
+    let corner : f64 = (center_x * center_x + center_y * center_y).sqrt();
+    for y in 0..height {
+        for x in 0..width {
+            let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+            let radius : f64 = ((px - center_x).powi(2) + (py - center_y).powi(2)).sqrt() / corner;
+            let color : Color3 = image.get(x, y);
+            image.set(x, y, f(px, py, radius, color));
+        }
+    }
+}
+
+//Only the part of each pixel brighter than threshold, keeping its hue.
+fn bright_pass(image : &Framebuffer, threshold : f64) -> Framebuffer {
+    let mut bright : Framebuffer = image.clone();
+    for pixel in bright.pixels_mut().iter_mut() {
+        let luminance : f64 = pixel.luminance();
+        *pixel = if luminance > threshold {((luminance - threshold) / luminance) * *pixel} else {Color3::default()};
+    }
+    return bright;
+}
+
+fn add_scaled(image : &Framebuffer, other : &Framebuffer, scale : f64) -> Framebuffer {
+    let mut result : Framebuffer = image.clone();
+    for (pixel, added) in result.pixels_mut().iter_mut().zip(other.pixels().iter()) {
+        *pixel += scale * *added;
+    }
+    return result;
+}
+
+//Separable Gaussian blur with standard deviation sigma in pixels, clamping at the borders.
+fn gaussian_blur(image : &Framebuffer, sigma : f64) -> Framebuffer {
+    if sigma < 0.5 {
+        return image.clone();
+    }
+    let reach : i64 = (3.0 * sigma).ceil() as i64;
+    let weights : Vec<f64> = (-reach..=reach).map(|offset| (-((offset * offset) as f64) / (2.0 * sigma * sigma)).exp()).collect();
+    let total : f64 = weights.iter().sum();
+
+    let blur_pass = |source : &Framebuffer, horizontal : bool| -> Framebuffer {
+        let mut result : Framebuffer = Framebuffer::new(source.width, source.height);
+        let (width, height) = (source.width as i64, source.height as i64);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum : Color3 = Color3::default();
+                for (tap, weight) in weights.iter().enumerate() {
+                    let offset : i64 = tap as i64 - reach;
+                    let (sx, sy) = if horizontal {((x + offset).clamp(0, width - 1), y)} else {(x, (y + offset).clamp(0, height - 1))};
+                    sum += *weight * source.get(sx as u32, sy as u32);
+                }
+                result.set(x as u32, y as u32, sum / total);
+            }
+        }
+        return result;
+    };
+    return blur_pass(&blur_pass(image, true), false);
+}
+
+//Smears every bright pixel along evenly spaced directions, fading out over length pixels.
+fn glare_streaks(bright : &Framebuffer, length : f64, streaks : u32) -> Framebuffer {
+    let mut result : Framebuffer = Framebuffer::new(bright.width, bright.height);
+    let steps : u32 = length.ceil().max(1.0) as u32;
+    let decay : f64 = (0.01f64).powf(1.0 / steps as f64); //Down to 1% at the end of the streak
+    let normalization : f64 = (1.0 - decay) / (streaks as f64);
+
+    for streak in 0..streaks {
+        //Offset by half a step so a 4 streak glare forms a diagonal cross.
+        let angle : f64 = 2.0 * PI * (streak as f64 + 0.5) / (streaks as f64);
+        let (dx, dy) = (angle.cos(), angle.sin());
+        for y in 0..bright.height {
+            for x in 0..bright.width {
+                let mut sum : Color3 = Color3::default();
+                let mut weight : f64 = 1.0;
+                for step in 0..steps {
+                    let sx : f64 = x as f64 - dx * step as f64;
+                    let sy : f64 = y as f64 - dy * step as f64;
+                    if sx < 0.0 || sy < 0.0 || sx >= bright.width as f64 || sy >= bright.height as f64 {
+                        break;
+                    }
+                    sum += weight * bright.get(sx as u32, sy as u32);
+                    weight *= decay;
+                }
+                result.set(x, y, result.get(x, y) + normalization * sum);
+            }
+        }
+    }
+    return result;
+}
+
+//Bilinear lookup at continuous pixel coordinates, where pixel (i, j) covers [i, i+1) x [j, j+1).
+fn sample_bilinear(image : &Framebuffer, x : f64, y : f64) -> Color3 {
+    let fx : f64 = (x - 0.5).clamp(0.0, (image.width - 1) as f64);
+    let fy : f64 = (y - 0.5).clamp(0.0, (image.height - 1) as f64);
+    let (x0, y0) = (fx.floor() as u32, fy.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(image.width - 1), (y0 + 1).min(image.height - 1));
+    let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+
+    let top : Color3 = (1.0 - tx) * image.get(x0, y0) + tx * image.get(x1, y0);
+    let bottom : Color3 = (1.0 - tx) * image.get(x0, y1) + tx * image.get(x1, y1);
+    return (1.0 - ty) * top + ty * bottom;
+}
+
+//Uniform number in [0, 1) from a pixel index and seed (SplitMix64).
+fn hash_uniform(index : u64, seed : u64) -> f64 {
+    let mut z : u64 = index.wrapping_add(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    return (z >> 11) as f64 / (1u64 << 53) as f64;
+}
+
+//Per channel gains that make light of the given color temperature white, relative to 6500K. The chromaticity
+//follows the Planckian locus fit of Kim et al., valid from 1667K to 25000K.
+fn white_balance(kelvin : f64) -> Color3 {
+    let reference : Color3 = planckian_rgb(6500.0);
+    let white : Color3 = planckian_rgb(kelvin);
+    return Color3::new(reference.x() / white.x(), reference.y() / white.y(), reference.z() / white.z());
+}
+
+fn planckian_rgb(kelvin : f64) -> Color3 {
+    let t : f64 = kelvin.clamp(1667.0, 25000.0);
+    let x : f64 = if t <= 4000.0 {
+        -0.2661239e9 / (t * t * t) - 0.2343589e6 / (t * t) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / (t * t * t) + 2.1070379e6 / (t * t) + 0.2226347e3 / t + 0.240390
+    };
+    let y : f64 = if t <= 2222.0 {
+        -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x * x * x - 5.87338670 * x * x + 3.75112997 * x - 0.37001483
+    };
+
+    let rgb : Color3 = Color3::from_xyz(x / y, 1.0, (1.0 - x - y) / y);
+    return Color3::new(rgb.x().max(1e-4), rgb.y().max(1e-4), rgb.z().max(1e-4));
+}
@@ -0,0 +1,91 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+//Snapshot of how far along a render is, handed to a ProgressReporter after every finished tile.
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    pub tiles_completed : u32,
+    pub tiles_total : u32,
+    pub elapsed : Duration
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.tiles_total == 0 {
+            return 1.0;
+        }
+        return self.tiles_completed as f64 / self.tiles_total as f64;
+    }
+
+    //Estimated time left, extrapolated from the average time per finished tile.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.tiles_completed == 0 {
+            return None;
+        }
+        let remaining : u32 = self.tiles_total - self.tiles_completed;
+        return Some(self.elapsed.mul_f64(remaining as f64 / self.tiles_completed as f64));
+    }
+}
+
+//Implemented by anything that wants to be told about render progress. Reports are always delivered
+//on the thread that called Camera::render, never on a worker thread.
+pub trait ProgressReporter : Send + Sync {
+    fn report(&self, progress : &Progress);
+}
+
+//Shared flag used to stop a render early. Workers check it between tiles, so a cancelled render
+//finishes the tiles already in flight and leaves the rest of the image black.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken {
+    cancelled : Arc<AtomicBool>
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::Relaxed);
+    }
+}
+
+//Single line progress bar for the command line, redrawn in place with a carriage return.
+pub struct ConsoleProgressBar {
+    pub width : usize
+}
+
+impl ConsoleProgressBar {
+    pub fn new(width : usize) -> Self {
+        return Self {width : width};
+    }
+}
+
+impl Default for ConsoleProgressBar {
+    fn default() -> Self {
+        return Self::new(40);
+    }
+}
+
+impl ProgressReporter for ConsoleProgressBar {
+    fn report(&self, progress : &Progress) {
+        let filled : usize = (progress.fraction() * self.width as f64) as usize;
+        let eta : String = match progress.eta() {
+            Some(eta) => format!("{:02}:{:02}", eta.as_secs() / 60, eta.as_secs() % 60),
+            None => String::from("--:--")
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(stdout, "\r[{}{}] {:>5.1}% {}/{} tiles ETA {}", "#".repeat(filled), " ".repeat(self.width - filled), progress.fraction() * 100.0, progress.tiles_completed, progress.tiles_total, eta);
+        if progress.tiles_completed == progress.tiles_total {
+            let _ = writeln!(stdout);
+        }
+        let _ = stdout.flush();
+    }
+}
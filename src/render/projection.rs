@@ -0,0 +1,233 @@
+use std::f64::consts::PI;
+use crate::math::vec3::Vec3;
+use crate::math::ray::Ray;
+use super::aperture::Aperture;
+
+//Look at frame shared by every projection. u points right, v points up and w points backwards, away from the target.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct CameraBasis {
+    pub origin : Vec3,
+    pub u : Vec3,
+    pub v : Vec3,
+    pub w : Vec3,
+    pub aspect_ratio : f64 //Image width over image height
+}
+
+impl CameraBasis {
+    //Converts a direction given in camera space (x right, y up, z backwards) to world space.
+    pub fn to_world(&self, x : f64, y : f64, z : f64) -> Vec3 {
+        return x * self.u + y * self.v + z * self.w;
+    }
+}
+
+//Maps a position on the film to a ray leaving the camera.
+pub trait Projection : Send + Sync {
+    //Ray through the film position (s, t), both in [0, 1] from the top left corner of the image. Returns None when
+    //the position is outside the area the projection covers, such as the corners around a fisheye image circle.
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray>;
+
+    //Solid angle density of generate_ray producing this ray, with film positions picked uniformly. Projections that
+    //return zero can't be reached by paths traced from lights.
+    fn direction_pdf(&self, _basis : &CameraBasis, _ray : &Ray) -> f64 {
+        return 0.0;
+    }
+
+    //Connects a point in the scene to the lens for light tracing, None when the camera doesn't see the point.
+    fn sample_importance(&self, _basis : &CameraBasis, _p : &Vec3) -> Option<ImportanceSample> {
+        return None;
+    }
+}
+
+//Path from a point in the scene into the camera, found by Projection::sample_importance.
+#[derive(Copy, Clone, Debug)]
+pub struct ImportanceSample {
+    pub s : f64, //Film position the point is seen at, as passed to generate_ray
+    pub t : f64,
+    pub direction : Vec3, //Unit vector from the point towards the lens
+    pub distance : f64,
+    pub weight : f64 //Camera importance over the solid angle density of the lens point as seen from the point
+}
+
+//Thin lens perspective projection, the default for cameras made by CameraBuilder.
+#[derive(Clone, Debug)]
+pub struct Perspective {
+    half_height : f64, //tan(fov/2)
+    focus_distance : f64,
+    defocus_radius : f64,
+    aperture : Aperture
+}
+
+impl Perspective {
+    //Vertical field of view and defocus angle are in degrees.
+    pub fn new(fov : f64, focus_distance : f64, defocus_angle : f64) -> Self {
+        return Self {
+            half_height : (fov.to_radians() / 2.0).tan(),
+            focus_distance : focus_distance,
+            defocus_radius : focus_distance * (defocus_angle.to_radians() / 2.0).tan(),
+            aperture : Aperture::Circular
+        };
+    }
+
+    //Shape of the lens opening, which is the shape out of focus highlights take on.
+    pub fn with_aperture(mut self, aperture : Aperture) -> Self {
+        self.aperture = aperture;
+        return self;
+    }
+
+    fn defocus_disk_sample(&self, basis : &CameraBasis) -> Vec3 {
+        let (x, y) = self.aperture.sample();
+        return basis.origin + self.defocus_radius * (x * basis.u + y * basis.v);
+    }
+
+    //Area of the film placed one unit in front of the lens.
+    fn film_area(&self, basis : &CameraBasis) -> f64 {
+        let height : f64 = 2.0 * self.half_height;
+        return height * height * basis.aspect_ratio;
+    }
+
+    //Film position of a ray leaving the lens at origin, found where it crosses the plane of focus.
+    fn film_position(&self, basis : &CameraBasis, origin : &Vec3, direction : &Vec3) -> Option<(f64, f64)> {
+        let cos_theta : f64 = Vec3::dot(direction, &basis.w.negate());
+        if cos_theta <= 0.0 {
+            return None;
+        }
+
+        let viewport_height : f64 = 2.0 * self.half_height * self.focus_distance;
+        let viewport_width : f64 = viewport_height * basis.aspect_ratio;
+        let offset : Vec3 = (*origin + (self.focus_distance / cos_theta) * *direction) - basis.origin;
+        let s : f64 = Vec3::dot(&offset, &basis.u) / viewport_width + 0.5;
+        let t : f64 = 0.5 - Vec3::dot(&offset, &basis.v) / viewport_height;
+        if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+            return None;
+        }
+        return Some((s, t));
+    }
+}
+
+impl Projection for Perspective {
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray> {
+        let viewport_height : f64 = 2.0 * self.half_height * self.focus_distance;
+        let viewport_width : f64 = viewport_height * basis.aspect_ratio;
+
+        //Point on the plane of focus, which every ray through this film position passes through.
+        let focus_point : Vec3 = basis.origin + basis.to_world((s - 0.5) * viewport_width, (0.5 - t) * viewport_height, -self.focus_distance);
+        let ray_origin : Vec3 = if self.defocus_radius <= 0.0 {basis.origin} else {self.defocus_disk_sample(basis)};
+
+        return Some(Ray::new(ray_origin, focus_point - ray_origin));
+    }
+
+    fn direction_pdf(&self, basis : &CameraBasis, ray : &Ray) -> f64 {
+        let direction : Vec3 = ray.dir.unit_vector();
+        let cos_theta : f64 = Vec3::dot(&direction, &basis.w.negate());
+        if cos_theta <= 0.0 || self.film_position(basis, &ray.origin, &direction).is_none() {
+            return 0.0;
+        }
+        return 1.0 / (self.film_area(basis) * cos_theta * cos_theta * cos_theta);
+    }
+
+    fn sample_importance(&self, basis : &CameraBasis, p : &Vec3) -> Option<ImportanceSample> {
+        let lens : Vec3 = if self.defocus_radius <= 0.0 {basis.origin} else {self.defocus_disk_sample(basis)};
+        let to_lens : Vec3 = lens - *p;
+        let distance : f64 = to_lens.length();
+        let direction : Vec3 = to_lens / distance;
+
+        let cos_theta : f64 = Vec3::dot(&direction, &basis.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let (s, t) = self.film_position(basis, &lens, &direction.negate())?;
+
+        //Importance is 1 / (A * lens area * cos^4) and the lens point's density seen from p is distance^2 / (cos * lens area).
+        let weight : f64 = 1.0 / (self.film_area(basis) * cos_theta * cos_theta * cos_theta * distance * distance);
+        return Some(ImportanceSample {s : s, t : t, direction : direction, distance : distance, weight : weight});
+    }
+}
+
+//Parallel projection, every ray points straight down the view direction.
+#[derive(Copy, Clone, Debug)]
+pub struct Orthographic {
+    pub view_height : f64 //Height of the visible region in world units
+}
+
+impl Orthographic {
+    pub fn new(view_height : f64) -> Self {
+        return Self {view_height : view_height};
+    }
+}
+
+impl Projection for Orthographic {
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray> {
+        let view_width : f64 = self.view_height * basis.aspect_ratio;
+        let origin : Vec3 = basis.origin + basis.to_world((s - 0.5) * view_width, (0.5 - t) * self.view_height, 0.0);
+        return Some(Ray::new(origin, -1.0 * basis.w));
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    Equidistant, //Radius on the film is proportional to the angle from the view direction
+    Equisolid //Radius is proportional to sin(angle/2), preserving relative areas
+}
+
+//Circular fisheye. The image circle is inscribed in the shorter side of the image and everything outside it is black.
+#[derive(Copy, Clone, Debug)]
+pub struct Fisheye {
+    pub fov : f64, //Full angle covered by the image circle in degrees, 180 for a hemisphere
+    pub mapping : FisheyeMapping
+}
+
+impl Fisheye {
+    pub fn new(fov : f64, mapping : FisheyeMapping) -> Self {
+        return Self {fov : fov, mapping : mapping};
+    }
+}
+
+impl Projection for Fisheye {
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray> {
+        //Film position relative to the image circle, the circle has radius 1.
+        let mut x : f64 = 2.0 * s - 1.0;
+        let mut y : f64 = 1.0 - 2.0 * t;
+        if basis.aspect_ratio >= 1.0 {
+            x *= basis.aspect_ratio;
+        }
+        else {
+            y /= basis.aspect_ratio;
+        }
+
+        let r : f64 = (x*x + y*y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta_max : f64 = self.fov.to_radians() / 2.0;
+        let theta : f64 = match self.mapping {
+            FisheyeMapping::Equidistant => r * theta_max,
+            FisheyeMapping::Equisolid => 2.0 * (r * (theta_max / 2.0).sin()).asin()
+        };
+        let phi : f64 = y.atan2(x);
+
+        let direction : Vec3 = basis.to_world(theta.sin() * phi.cos(), theta.sin() * phi.sin(), -theta.cos());
+        return Some(Ray::new(basis.origin, direction));
+    }
+}
+
+//Full 360x180 latitude/longitude panorama, the view direction sits in the middle of the image.
+//Use an image with a 2:1 aspect ratio to keep the pixels square.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Equirectangular;
+
+impl Equirectangular {
+    //Direction in camera space for the film position (s, t).
+    pub fn direction(s : f64, t : f64) -> (f64, f64, f64) {
+        let longitude : f64 = (s - 0.5) * 2.0 * PI;
+        let latitude : f64 = (0.5 - t) * PI;
+        return (latitude.cos() * longitude.sin(), latitude.sin(), -latitude.cos() * longitude.cos());
+    }
+}
+
+impl Projection for Equirectangular {
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray> {
+        let (x, y, z) = Equirectangular::direction(s, t);
+        return Some(Ray::new(basis.origin, basis.to_world(x, y, z)));
+    }
+}
@@ -0,0 +1,123 @@
+use rand::Rng;
+use crate::math::vec3::Color3;
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::math::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::shapes::hittable::{HitRecord, Hittable, HittableList};
+use crate::shapes::material::Material;
+use super::camera::Camera;
+use super::framebuffer::Splats;
+use super::integrator::{Integrator, background_radiance, background_shadow_sample, for_each_light_sample};
+use super::aov::{AovSample, LightPass};
+use super::subsurface::subsurface_exit;
+use super::stats;
+
+//Path tracer that carries light as a spectrum instead of RGB, with hero wavelength sampling (Wilkie et al. 2014):
+//every camera sample follows one path for a few wavelengths spread over the visible range. RGB albedos and lights are
+//upsampled to smooth spectra, dispersive glass splits white light into its colors and black body lights keep their
+//exact spectrum. Light is converted to XYZ and then linear sRGB as it reaches the film, which is the same as
+//converting the average spectrum of the pixel since the conversion is linear.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SpectralPathTracer;
+
+impl Integrator for SpectralPathTracer {
+    fn radiance(&self, camera : &Camera, ray : Ray, world : &HittableList, splats : &mut Splats) -> Color3 {
+        return self.radiance_aovs(camera, ray, world, splats, &mut AovSample::default());
+    }
+
+    //Same path as the PathTracer's, with a spectral throughput. Each bit of light found is converted to RGB with the
+    //wavelengths that are still alive at that point, so light found before the path reached dispersive glass still
+    //counts for every wavelength.
+    fn radiance_aovs(&self, camera : &Camera, ray : Ray, world : &HittableList, _splats : &mut Splats, aovs : &mut AovSample) -> Color3 {
+        let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+        let mut wavelengths : SampledWavelengths = SampledWavelengths::sample_uniform(rng.gen::<f64>());
+        let mut radiance : Color3 = Color3::default();
+        let mut throughput : SampledSpectrum = SampledSpectrum::constant(1.0);
+        let mut ray : Ray = ray;
+        let mut scatter_pdf : Option<f64> = None;
+        let mut pass : LightPass = LightPass::Emission;
+
+        for bounce in 0..camera.max_depth() {
+            let mut rec : HitRecord = HitRecord::default();
+            if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                stats::record_path_length(bounce);
+                let emitted : SampledSpectrum = SampledSpectrum::from_illuminant(&background_radiance(camera, &ray, scatter_pdf), &wavelengths);
+                let background : Color3 = (throughput * emitted).to_rgb(&wavelengths);
+                aovs.add(pass, background);
+                return radiance + background;
+            }
+
+            if bounce == 0 {
+                aovs.record_surface(&ray, &rec);
+                pass = if rec.material.is_specular() {LightPass::Specular} else {LightPass::DirectDiffuse};
+            } else if pass == LightPass::DirectDiffuse {
+                pass = LightPass::IndirectDiffuse;
+            }
+
+            if matches!(rec.material, Material::Subsurface { .. }) {
+                match subsurface_exit(world, &rec) {
+                    Some((exit, weight)) => {
+                        throughput *= SampledSpectrum::from_reflectance(&weight, &wavelengths);
+                        rec = exit;
+                    }
+                    None => {
+                        stats::record_path_length(bounce);
+                        return radiance;
+                    }
+                }
+            }
+
+            if !rec.material.is_specular() {
+                let direct : Color3 = (throughput * direct_light(camera, &rec, world, &wavelengths)).to_rgb(&wavelengths);
+                aovs.add(pass, direct);
+                radiance += direct;
+            }
+
+            //Dispersive materials bend each wavelength differently, so only the hero follows the path from here on.
+            let dispersed : Option<Material> = if rec.material.is_dispersive() {
+                wavelengths.terminate_secondary();
+                Some(rec.material.at_wavelength(wavelengths.hero()))
+            } else {
+                None
+            };
+            let material : &Material = dispersed.as_ref().unwrap_or(&rec.material);
+
+            let mut scattered : Ray = Ray::default();
+            let mut attenuation : Color3 = Color3::default();
+            if !material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                stats::record_path_length(bounce);
+                return radiance;
+            }
+
+            throughput *= material.coating_spectrum(&ray, &rec, &scattered, &wavelengths).unwrap_or_else(|| SampledSpectrum::from_reflectance(&attenuation, &wavelengths));
+            scatter_pdf = if rec.material.is_specular() {None} else {Some(rec.material.pdf(&rec, &scattered.dir))};
+
+            if bounce + 1 >= camera.russian_roulette() {
+                let survival : f64 = throughput.max_value().min(0.95);
+                if rng.gen::<f64>() >= survival {
+                    stats::record_path_length(bounce + 1);
+                    return radiance;
+                }
+                throughput = (1.0 / survival) * throughput;
+            }
+
+            stats::count_secondary_ray();
+            ray = scattered;
+        }
+
+        stats::record_path_length(camera.max_depth());
+        return radiance;
+    }
+}
+
+//Spectral radiance reaching rec from the background and the punctual lights, as the PathTracer samples it.
+fn direct_light(camera : &Camera, rec : &HitRecord, world : &HittableList, wavelengths : &SampledWavelengths) -> SampledSpectrum {
+    let mut direct : SampledSpectrum = SampledSpectrum::default();
+    if let Some((f, radiance, weight)) = background_shadow_sample(camera, rec, world) {
+        direct += weight * (SampledSpectrum::from_reflectance(&f, wavelengths) * SampledSpectrum::from_illuminant(&radiance, wavelengths));
+    }
+    for_each_light_sample(rec, world, |_| true, |light, sample, f| {
+        direct += SampledSpectrum::from_reflectance(&f, wavelengths) * light.spectrum().sample(&sample.radiance, wavelengths);
+    });
+    return direct;
+}
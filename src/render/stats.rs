@@ -0,0 +1,240 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fmt::Write as FmtWrite;
+use std::time::Duration;
+
+//Counters collected by a single worker thread over the course of a render.
+#[derive(Clone, Default, Debug)]
+pub struct RayCounters {
+    pub camera_rays : u64,
+    pub secondary_rays : u64,
+    pub shadow_rays : u64,
+    pub intersection_tests : u64,
+    pub bvh_node_visits : u64,
+    pub path_lengths : Vec<u64> //path_lengths[n] is the number of paths that terminated after n bounces.
+}
+
+impl RayCounters {
+    pub fn total_rays(&self) -> u64 {
+        return self.camera_rays + self.secondary_rays + self.shadow_rays;
+    }
+
+    pub fn merge(&mut self, other : &RayCounters) {
+        self.camera_rays += other.camera_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.intersection_tests += other.intersection_tests;
+        self.bvh_node_visits += other.bvh_node_visits;
+
+        if self.path_lengths.len() < other.path_lengths.len() {
+            self.path_lengths.resize(other.path_lengths.len(), 0);
+        }
+        for (bucket, count) in other.path_lengths.iter().enumerate() {
+            self.path_lengths[bucket] += count;
+        }
+    }
+}
+
+//The hot paths (intersection tests especially) only ever touch thread local cells, so counting is just an
+//increment with no synchronization. Workers hand their counters back with take_thread_counters() when done.
+thread_local! {
+    static CAMERA_RAYS : Cell<u64> = const { Cell::new(0) };
+    static SECONDARY_RAYS : Cell<u64> = const { Cell::new(0) };
+    static SHADOW_RAYS : Cell<u64> = const { Cell::new(0) };
+    static INTERSECTION_TESTS : Cell<u64> = const { Cell::new(0) };
+    static BVH_NODE_VISITS : Cell<u64> = const { Cell::new(0) };
+    static PATH_LENGTHS : RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+fn increment(counter : &'static std::thread::LocalKey<Cell<u64>>) {
+    counter.with(|c| c.set(c.get() + 1));
+}
+
+pub fn count_camera_ray() {
+    increment(&CAMERA_RAYS);
+}
+
+pub fn count_secondary_ray() {
+    increment(&SECONDARY_RAYS);
+}
+
+pub fn count_shadow_ray() {
+    increment(&SHADOW_RAYS);
+}
+
+pub fn count_intersection_test() {
+    increment(&INTERSECTION_TESTS);
+}
+
+pub fn count_bvh_node_visit() {
+    increment(&BVH_NODE_VISITS);
+}
+
+pub fn record_path_length(bounces : u32) {
+    PATH_LENGTHS.with(|lengths| {
+        let mut lengths = lengths.borrow_mut();
+        let bucket : usize = bounces as usize;
+        if lengths.len() <= bucket {
+            lengths.resize(bucket + 1, 0);
+        }
+        lengths[bucket] += 1;
+    });
+}
+
+//Returns the counters accumulated on the calling thread and resets them to zero.
+pub fn take_thread_counters() -> RayCounters {
+    return RayCounters {
+        camera_rays : CAMERA_RAYS.with(|c| c.replace(0)),
+        secondary_rays : SECONDARY_RAYS.with(|c| c.replace(0)),
+        shadow_rays : SHADOW_RAYS.with(|c| c.replace(0)),
+        intersection_tests : INTERSECTION_TESTS.with(|c| c.replace(0)),
+        bvh_node_visits : BVH_NODE_VISITS.with(|c| c.replace(0)),
+        path_lengths : PATH_LENGTHS.with(|lengths| lengths.take())
+    };
+}
+
+//Statistics for a whole render: the counters of every worker thread plus the wall time of each phase.
+#[derive(Clone, Default, Debug)]
+pub struct RenderStatistics {
+    pub threads : Vec<RayCounters>,
+    pub phases : Vec<(String, Duration)>
+}
+
+//Name of the phase used for throughput (Mrays/s) calculations.
+pub const RENDER_PHASE : &str = "render";
+
+//Name of the phase photon mapping integrators spend tracing photons.
+pub const PHOTON_PHASE : &str = "photons";
+
+//Name of the phase spent denoising the finished image.
+pub const DENOISE_PHASE : &str = "denoise";
+
+//Name of the phase spent on post-processing effects.
+pub const POST_PHASE : &str = "post";
+
+impl RenderStatistics {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn add_thread(&mut self, counters : RayCounters) {
+        self.threads.push(counters);
+    }
+
+    //Folds the statistics of another render into this one, adding up phases with the same name.
+    pub fn merge(&mut self, other : RenderStatistics) {
+        self.threads.extend(other.threads);
+        for (name, duration) in other.phases {
+            match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+                Some((_, total)) => *total += duration,
+                None => self.phases.push((name, duration))
+            }
+        }
+    }
+
+    pub fn add_phase(&mut self, name : &str, duration : Duration) {
+        self.phases.push((name.to_string(), duration));
+    }
+
+    //Runs f and records how long it took under the given phase name.
+    pub fn time_phase<T, F : FnOnce() -> T>(&mut self, name : &str, f : F) -> T {
+        let start = std::time::Instant::now();
+        let result : T = f();
+        self.add_phase(name, start.elapsed());
+        return result;
+    }
+
+    pub fn phase(&self, name : &str) -> Option<Duration> {
+        return self.phases.iter().find(|(phase, _)| phase == name).map(|(_, duration)| *duration);
+    }
+
+    pub fn total_time(&self) -> Duration {
+        return self.phases.iter().map(|(_, duration)| *duration).sum();
+    }
+
+    pub fn totals(&self) -> RayCounters {
+        let mut totals : RayCounters = RayCounters::default();
+        for counters in self.threads.iter() {
+            totals.merge(counters);
+        }
+        return totals;
+    }
+
+    //Millions of rays (camera, secondary and shadow) traced per second of the render phase.
+    pub fn mrays_per_second(&self) -> f64 {
+        let seconds : f64 = self.phase(RENDER_PHASE).unwrap_or_default().as_secs_f64();
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+        return self.totals().total_rays() as f64 / seconds / 1.0e6;
+    }
+
+    pub fn to_json(&self) -> String {
+        let totals : RayCounters = self.totals();
+        let mut json : String = String::new();
+
+        json.push_str("{\n");
+        let _ = writeln!(json, "  \"mrays_per_second\": {:.4},", self.mrays_per_second());
+        let _ = writeln!(json, "  \"totals\": {},", counters_to_json(&totals));
+
+        json.push_str("  \"threads\": [");
+        for (index, counters) in self.threads.iter().enumerate() {
+            let separator : &str = if index == 0 {""} else {","};
+            let _ = write!(json, "{}\n    {}", separator, counters_to_json(counters));
+        }
+        json.push_str("\n  ],\n");
+
+        json.push_str("  \"phases\": {");
+        for (index, (name, duration)) in self.phases.iter().enumerate() {
+            let separator : &str = if index == 0 {""} else {","};
+            let _ = write!(json, "{}\n    \"{}\": {:.6}", separator, name, duration.as_secs_f64());
+        }
+        json.push_str("\n  }\n}\n");
+
+        return json;
+    }
+}
+
+fn counters_to_json(counters : &RayCounters) -> String {
+    let histogram : Vec<String> = counters.path_lengths.iter().map(|count| count.to_string()).collect();
+    return format!(
+        "{{\"camera_rays\": {}, \"secondary_rays\": {}, \"shadow_rays\": {}, \"intersection_tests\": {}, \"bvh_node_visits\": {}, \"path_lengths\": [{}]}}",
+        counters.camera_rays, counters.secondary_rays, counters.shadow_rays, counters.intersection_tests, counters.bvh_node_visits, histogram.join(", ")
+    );
+}
+
+//Human readable summary table.
+impl fmt::Display for RenderStatistics {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let totals : RayCounters = self.totals();
+
+        writeln!(f, "{:<8} {:>14} {:>14} {:>14} {:>16} {:>14}", "Thread", "Camera", "Secondary", "Shadow", "Intersections", "BVH Visits")?;
+        for (index, counters) in self.threads.iter().enumerate() {
+            writeln!(f, "{:<8} {:>14} {:>14} {:>14} {:>16} {:>14}", index, counters.camera_rays, counters.secondary_rays, counters.shadow_rays, counters.intersection_tests, counters.bvh_node_visits)?;
+        }
+        writeln!(f, "{:<8} {:>14} {:>14} {:>14} {:>16} {:>14}", "Total", totals.camera_rays, totals.secondary_rays, totals.shadow_rays, totals.intersection_tests, totals.bvh_node_visits)?;
+        writeln!(f)?;
+
+        writeln!(f, "Throughput: {:.3} Mrays/s", self.mrays_per_second())?;
+        writeln!(f)?;
+
+        //Histogram bars are scaled relative to the most common path length.
+        let paths : u64 = totals.path_lengths.iter().sum();
+        let most_common : u64 = totals.path_lengths.iter().copied().max().unwrap_or(0);
+        writeln!(f, "Path length histogram ({} paths):", paths)?;
+        for (bounces, count) in totals.path_lengths.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let bar : usize = if most_common == 0 {0} else {((*count as f64 / most_common as f64) * 40.0).ceil() as usize};
+            writeln!(f, "{:>4} {:>12} {}", bounces, count, "#".repeat(bar))?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "Phases:")?;
+        for (name, duration) in self.phases.iter() {
+            writeln!(f, "  {:<12} {:>10.2?}", name, duration)?;
+        }
+        write!(f, "  {:<12} {:>10.2?}", "total", self.total_time())
+    }
+}
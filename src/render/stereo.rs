@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use crate::math::vec3::Vec3;
+use crate::math::ray::Ray;
+use crate::shapes::hittable::HittableList;
+use super::camera::{Camera, CameraError};
+use super::framebuffer::Framebuffer;
+use super::projection::{CameraBasis, Projection, Equirectangular};
+use super::stats::RenderStatistics;
+
+//How the two eyes are packed into a single image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    TopBottom, //Left eye on top
+    SideBySide //Left eye on the left
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StereoMode {
+    Planar, //Two views through the camera's own projection, offset along the camera's right vector
+    OmniDirectional //ODS: a 360x180 equirectangular panorama per eye
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right
+}
+
+impl Eye {
+    //Direction of the eye's offset along the camera's right vector.
+    fn sign(&self) -> f64 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0
+        }
+    }
+}
+
+//Wraps another projection and moves it sideways for one eye. The rays are re-aimed so that both eyes see the same
+//point on the convergence plane, i.e. an off-axis frustum rather than toeing the cameras in, so there is no keystoning.
+pub struct StereoEye {
+    inner : Arc<dyn Projection>,
+    offset : f64, //Signed distance along u from the center of the camera
+    convergence_distance : f64 //f64::INFINITY for parallel eyes
+}
+
+impl StereoEye {
+    pub fn new(inner : Arc<dyn Projection>, eye : Eye, interocular_distance : f64, convergence_distance : f64) -> Self {
+        return Self {
+            inner : inner,
+            offset : eye.sign() * interocular_distance / 2.0,
+            convergence_distance : convergence_distance
+        };
+    }
+}
+
+impl Projection for StereoEye {
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray> {
+        let center_ray : Ray = self.inner.generate_ray(basis, s, t)?;
+        let origin : Vec3 = center_ray.origin + self.offset * basis.u;
+
+        //Distance along the ray to the convergence plane, which lies perpendicular to the view direction.
+        let forward : f64 = -Vec3::dot(&center_ray.dir, &basis.w);
+        if !self.convergence_distance.is_finite() || forward <= 0.0 {
+            return Some(Ray::new(origin, center_ray.dir));
+        }
+
+        let depth : f64 = self.convergence_distance - Vec3::dot(&(center_ray.origin - basis.origin), &basis.w.negate());
+        let converged : Vec3 = center_ray.at(depth / forward);
+        return Some(Ray::new(origin, converged - origin));
+    }
+}
+
+//Omni-directional stereo panorama for one eye. Every column of the panorama is seen from a point on a circle with
+//a diameter of the interocular distance, tangent to the viewing direction, as a head turning in place would see it.
+//The offset fades out towards the poles to avoid the swirl ODS otherwise produces straight up and down.
+pub struct OmniDirectionalStereo {
+    offset : f64
+}
+
+impl OmniDirectionalStereo {
+    pub fn new(eye : Eye, interocular_distance : f64) -> Self {
+        return Self {offset : eye.sign() * interocular_distance / 2.0};
+    }
+}
+
+impl Projection for OmniDirectionalStereo {
+    fn generate_ray(&self, basis : &CameraBasis, s : f64, t : f64) -> Option<Ray> {
+        let (x, y, z) = Equirectangular::direction(s, t);
+
+        //Horizontal unit vector pointing to the right of the viewing direction.
+        let longitude : f64 = (s - 0.5) * 2.0 * std::f64::consts::PI;
+        let latitude_scale : f64 = (1.0 - y*y).sqrt();
+        let origin : Vec3 = basis.origin + (self.offset * latitude_scale) * basis.to_world(longitude.cos(), 0.0, longitude.sin());
+
+        return Some(Ray::new(origin, basis.to_world(x, y, z)));
+    }
+}
+
+//Renders left and right eye views of an existing camera and packs them into one image.
+#[derive(Clone)]
+pub struct StereoCamera {
+    camera : Camera,
+    pub interocular_distance : f64,
+    pub convergence_distance : f64,
+    pub layout : StereoLayout,
+    pub mode : StereoMode
+}
+
+impl StereoCamera {
+    //Planar side by side stereo, converging at the camera's focus distance.
+    pub fn new(camera : Camera, interocular_distance : f64) -> Self {
+        let convergence_distance : f64 = camera.focus_distance();
+        return Self {
+            camera : camera,
+            interocular_distance : interocular_distance,
+            convergence_distance : convergence_distance,
+            layout : StereoLayout::SideBySide,
+            mode : StereoMode::Planar
+        };
+    }
+
+    pub fn convergence(mut self, distance : f64) -> Self {
+        self.convergence_distance = distance;
+        return self;
+    }
+
+    pub fn layout(mut self, layout : StereoLayout) -> Self {
+        self.layout = layout;
+        return self;
+    }
+
+    pub fn mode(mut self, mode : StereoMode) -> Self {
+        self.mode = mode;
+        return self;
+    }
+
+    //The camera for a single eye. ODS eyes always get a 2:1 panorama at the camera's image width.
+    pub fn eye_camera(&self, eye : Eye) -> Result<Camera, CameraError> {
+        match self.mode {
+            StereoMode::Planar => {
+                let projection : StereoEye = StereoEye::new(self.camera.projection(), eye, self.interocular_distance, self.convergence_distance);
+                return self.camera.to_builder().projection(projection).build();
+            }
+
+            StereoMode::OmniDirectional => {
+                let projection : OmniDirectionalStereo = OmniDirectionalStereo::new(eye, self.interocular_distance);
+                return self.camera.to_builder().aspect_ratio(2.0).projection(projection).build();
+            }
+        }
+    }
+
+    pub fn render(&self, world : HittableList) -> Result<(Framebuffer, RenderStatistics), CameraError> {
+        let world : Arc<HittableList> = Arc::new(world);
+        let (left, mut statistics) = self.eye_camera(Eye::Left)?.render_shared(Arc::clone(&world), None, None, None);
+        let (right, right_statistics) = self.eye_camera(Eye::Right)?.render_shared(world, None, None, None);
+        statistics.merge(right_statistics);
+
+        return Ok((pack_stereo_pair(&left, &right, self.layout), statistics));
+    }
+}
+
+//Packs two equally sized images into one according to the layout.
+pub fn pack_stereo_pair(left : &Framebuffer, right : &Framebuffer, layout : StereoLayout) -> Framebuffer {
+    let (width, height) = (left.width, left.height);
+    let (offset_x, offset_y) = match layout {
+        StereoLayout::TopBottom => (0, height),
+        StereoLayout::SideBySide => (width, 0)
+    };
+
+    let mut packed : Framebuffer = Framebuffer::new(width + offset_x, height + offset_y);
+    for y in 0..height {
+        for x in 0..width {
+            packed.set(x, y, left.get(x, y));
+            packed.set(x + offset_x, y + offset_y, right.get(x, y));
+        }
+    }
+    return packed;
+}
@@ -0,0 +1,156 @@
+use std::f64::consts::PI;
+use rand::Rng;
+use crate::math::vec3::{Vec3, Color3};
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::shapes::hittable::{HitRecord, Hittable, HittableList};
+use crate::shapes::material::{Material, SubsurfaceMethod};
+use super::stats;
+
+//Scattering events a random walk takes before the light is taken to never come back out.
+const MAX_WALK_STEPS : u32 = 256;
+
+//Steps after which walks that have lost most of their light start being cut short by Russian roulette.
+const WALK_ROULETTE_STEPS : u32 = 8;
+
+//Where light that entered the subsurface material at rec comes back out of the object, and the fraction of it that
+//does per channel. The exit has a white Lambertian material, as the light has its color from the walk and leaves
+//spread over the hemisphere like diffuse reflection, so integrators carry on from it as from any diffuse hit. Its t
+//is rec's, which absorption along the ray that reached rec is worked out from. None if the light never got out or
+//rec isn't a subsurface material.
+pub(crate) fn subsurface_exit(world : &HittableList, rec : &HitRecord) -> Option<(HitRecord, Color3)> {
+    let (albedo, mean_free_path, method) = match &rec.material {
+        Material::Subsurface { albedo, mean_free_path, method } => (*albedo, *mean_free_path, *method),
+        _ => return None
+    };
+
+    let object : &dyn Hittable = world.objects.get(rec.object_id)?.as_ref();
+    let (mut exit, weight) = match method {
+        SubsurfaceMethod::RandomWalk => random_walk(object, rec, &albedo, &mean_free_path)?,
+        SubsurfaceMethod::Diffusion => diffusion_probe(object, rec, &albedo, &mean_free_path)?
+    };
+
+    //Turn the exit to face out of the object, the side the light leaves on.
+    let outward : Vec3 = if exit.front_face {exit.normal} else {exit.normal.negate()};
+    let geometric_outward : Vec3 = if exit.front_face {exit.geometric_normal} else {exit.geometric_normal.negate()};
+    exit.normal = outward;
+    exit.geometric_normal = geometric_outward;
+    exit.front_face = true;
+    exit.material = Material::Lambertian {albedo : Color3::new(1.0, 1.0, 1.0)};
+    exit.object_id = rec.object_id;
+    exit.t = rec.t;
+    return Some((exit, weight));
+}
+
+//Follows light through the inside of the object with isotropic scattering until it leaves. Distances are sampled
+//from one channel's extinction at a time and weighted against all three, so each color can spread by its own
+//mean free path.
+fn random_walk(object : &dyn Hittable, rec : &HitRecord, albedo : &Color3, mean_free_path : &Color3) -> Option<(HitRecord, Color3)> {
+    let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+    let mut single_scattering : [f64; 3] = [0.0; 3];
+    let mut extinction : [f64; 3] = [0.0; 3];
+    for channel in 0..3 {
+        (single_scattering[channel], extinction[channel]) = walk_coefficients(albedo[channel], mean_free_path[channel]);
+    }
+    let transmittance = |distance : f64| Color3::new((-extinction[0] * distance).exp(), (-extinction[1] * distance).exp(), (-extinction[2] * distance).exp());
+
+    //Light gets in through a rough boundary, so it starts in a cosine weighted direction into the surface.
+    let mut direction : Vec3 = rec.normal.negate() + Vec3::random_unit_vector();
+    if direction.near_zero() {
+        direction = rec.normal.negate();
+    }
+    let mut ray : Ray = Ray::new(rec.p, direction.unit_vector());
+    let mut throughput : Color3 = Color3::new(1.0, 1.0, 1.0);
+
+    for step in 0..MAX_WALK_STEPS {
+        let channel : usize = rng.gen_range(0..3);
+        let distance : f64 = -(1.0 - rng.gen::<f64>()).ln() / extinction[channel];
+
+        stats::count_secondary_ray();
+        let mut exit : HitRecord = HitRecord::default();
+        if object.hit(&ray, Interval::new(0.001, distance), &mut exit) {
+            //Reached the surface before scattering again, with the chance of a flight this long in any channel.
+            let survived : Color3 = transmittance(exit.t);
+            throughput = (3.0 / (survived.x() + survived.y() + survived.z())) * (throughput * survived);
+            return Some((exit, throughput));
+        }
+
+        let survived : Color3 = transmittance(distance);
+        let scattered : Color3 = Color3::new(single_scattering[0] * extinction[0] * survived.x(), single_scattering[1] * extinction[1] * survived.y(), single_scattering[2] * extinction[2] * survived.z());
+        let pdf : f64 = (extinction[0] * survived.x() + extinction[1] * survived.y() + extinction[2] * survived.z()) / 3.0;
+        throughput = (1.0 / pdf) * (throughput * scattered);
+
+        if step >= WALK_ROULETTE_STEPS {
+            let survival : f64 = throughput.x().max(throughput.y()).max(throughput.z()).min(0.95);
+            if rng.gen::<f64>() >= survival {
+                return None;
+            }
+            throughput /= survival;
+        }
+        ray = Ray::new(ray.at(distance), Vec3::random_unit_vector());
+    }
+    return None;
+}
+
+//Single scattering albedo and extinction coefficient of a channel, picked so that a thick slab reflects albedo
+//overall and light spreads about mean_free_path (Chiang, Kutz and Burley 2016).
+fn walk_coefficients(albedo : f64, mean_free_path : f64) -> (f64, f64) {
+    let a : f64 = albedo.clamp(0.0, 0.999);
+    let single_scattering : f64 = 1.0 - (a * (-5.09406 + a * (2.61188 - a * 4.31805))).exp();
+    let s : f64 = 1.9 - a + 3.5 * (a - 0.8) * (a - 0.8);
+    return (single_scattering, 1.0 / (mean_free_path.max(1e-6) * s));
+}
+
+//Burley's normalized diffusion profile, R(r) = A k (e^(-k r) + e^(-k r / 3)) / (8 pi r) with k the channel's
+//falloff. A radius is sampled from a random channel's profile, and the exit found by probing the object along the
+//normal from that far away on the tangent plane.
+fn diffusion_probe(object : &dyn Hittable, rec : &HitRecord, albedo : &Color3, mean_free_path : &Color3) -> Option<(HitRecord, Color3)> {
+    let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+    let mut falloff : [f64; 3] = [0.0; 3];
+    for (channel, k) in falloff.iter_mut().enumerate() {
+        let a : f64 = albedo[channel].clamp(0.0, 1.0);
+        *k = (1.85 - a + 7.0 * (a - 0.8).abs().powi(3)) / mean_free_path[channel].max(1e-6);
+    }
+    //Radial density of each channel's profile, r R(r) 2 pi / A.
+    let radial_pdf = |channel : usize, r : f64| 0.25 * falloff[channel] * ((-falloff[channel] * r).exp() + (-falloff[channel] * r / 3.0).exp());
+
+    //The profile is a mix of two exponentials, one three times wider and carrying three quarters of the light.
+    let channel : usize = rng.gen_range(0..3);
+    let width : f64 = if rng.gen::<f64>() < 0.25 {1.0} else {3.0};
+    let r : f64 = -width * (1.0 - rng.gen::<f64>()).ln() / falloff[channel];
+
+    //Past this the profiles have fallen off to almost nothing.
+    let reach : f64 = 24.0 / falloff[0].min(falloff[1]).min(falloff[2]);
+    if r >= reach {
+        return None;
+    }
+
+    let normal : Vec3 = rec.normal;
+    let helper : Vec3 = if normal.x().abs() > 0.9 {Vec3::new(0.0, 1.0, 0.0)} else {Vec3::new(1.0, 0.0, 0.0)};
+    let tangent : Vec3 = Vec3::cross(&helper, &normal).unit_vector();
+    let bitangent : Vec3 = Vec3::cross(&normal, &tangent);
+    let phi : f64 = 2.0 * PI * rng.gen::<f64>();
+    let offset : Vec3 = (r * phi.cos()) * tangent + (r * phi.sin()) * bitangent;
+
+    //Probe the chord of the sphere of radius reach through the offset point.
+    let half_chord : f64 = (reach * reach - r * r).sqrt();
+    stats::count_secondary_ray();
+    let mut exit : HitRecord = HitRecord::default();
+    if !object.hit(&Ray::new(rec.p + offset + half_chord * normal, normal.negate()), Interval::new(0.0, 2.0 * half_chord), &mut exit) {
+        return None;
+    }
+
+    //Density of the exit over the surface: the radius over the disk, projected onto the surface along the normal.
+    //Steep parts of the surface are clamped, giving up a little light there rather than leaving fireflies.
+    let distance : f64 = (exit.p - rec.p).length().max(1e-9);
+    let projection : f64 = Vec3::dot(&exit.normal, &normal).abs().max(0.1);
+    let pdf : f64 = (radial_pdf(0, r) + radial_pdf(1, r) + radial_pdf(2, r)) / 3.0 / (2.0 * PI * r.max(1e-9)) * projection;
+
+    let mut weight : Color3 = Color3::default();
+    for channel in 0..3 {
+        let k : f64 = falloff[channel];
+        let profile : f64 = albedo[channel] * k * ((-k * distance).exp() + (-k * distance / 3.0).exp()) / (8.0 * PI * distance);
+        weight[channel] = profile / pdf;
+    }
+    return Some((exit, weight));
+}
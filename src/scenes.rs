@@ -0,0 +1,61 @@
+use rand::Rng;
+use crate::math::vec3::{Vec3, Color3};
+use crate::shapes::hittable::HittableList;
+use crate::shapes::material::Material;
+use crate::shapes::sphere::Sphere;
+
+//Final scene from Ray Tracing in One Weekend: a field of small random spheres around three large ones.
+//Looks best from (13, 2, 3) towards the origin.
+pub fn random_spheres() -> HittableList {
+    let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+
+    //Create materials
+    let material_ground: Material = Material::Lambertian { albedo: Color3::new(0.5, 0.5, 0.5) };
+    let scene_focus : Vec3 = Vec3::new(4.0, 0.2, 0.0);
+
+    //World
+    let mut world : HittableList = HittableList::new();
+
+    world.add(Box::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, material_ground)));
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat : f64 = rng.gen::<f64>();
+            let center : Vec3 = Vec3::new(a as f64 + 0.9*rng.gen::<f64>(), 0.2, b as f64 + 0.9*rng.gen::<f64>());
+
+            if (center - scene_focus).length() > 0.9 {
+                let sphere_material : Material;
+
+                if choose_mat < 0.8 {
+                    //Diffuse
+                    let albedo : Vec3 = Color3::random_vec() * Color3::random_vec();
+                    sphere_material = Material::Lambertian { albedo: albedo };
+                    world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+                }
+
+                else if choose_mat < 0.95 {
+                    //Metal 
+                    let albedo : Vec3 = Color3::random_vec_range(0.5, 1.0);
+                    let fuzz : f64 = rng.gen_range(0.0..0.5);
+                    sphere_material = Material::metal(albedo, fuzz);
+                    world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+                }
+
+                else {
+                    //Glass
+                    sphere_material = Material::dielectric(1.5);
+                    world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+                }
+            }
+        }
+    }
+
+    let material1 : Material = Material::dielectric(1.5);
+    world.add(Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, material1)));
+
+    let material2 : Material = Material::Lambertian { albedo: Color3::new(0.4, 0.2, 0.1) };
+    world.add(Box::new(Sphere::new(Vec3::new(-4.0, -1.0, 0.0), 1.0, material2)));
+    let material3 : Material = Material::metal(Color3::new(0.7, 0.6, 0.5), 0.0);
+    world.add(Box::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, material3)));
+
+    return world;
+}
@@ -0,0 +1,98 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use crate::math::vec3::Color3;
+use crate::math::spectrum::{reflectance_to_rgb, rgb_to_reflectance};
+use crate::shapes::hittable::HitRecord;
+use crate::shapes::texture::Texture;
+
+//Thin transparent layer on top of a surface, like an anti-reflective lens coating, oil on metal or the wall of a soap
+//bubble. Light reflected off its top and bottom interferes, so its reflectance swings between colors with the
+//film's thickness and the viewing angle.
+#[derive(Clone, Debug)]
+pub struct ThinFilm {
+    thickness : f64, //In nanometres, visible colors appear from about 100 to 1000
+    index_of_refraction : f64,
+    thickness_map : Option<Arc<Texture>> //Scales the thickness by its value across the surface, shared so films stay cheap to clone
+}
+
+//What a film is lying on.
+#[derive(Copy, Clone, Debug)]
+pub enum Substrate {
+    Dielectric(f64), //Index of refraction
+    Conductor(Color3) //Reflectance of the bare metal
+}
+
+impl ThinFilm {
+    pub fn new(thickness : f64, index_of_refraction : f64) -> Self {
+        return Self {thickness : thickness.max(0.0), index_of_refraction : index_of_refraction.max(1.0), thickness_map : None};
+    }
+
+    pub fn with_thickness_map(self, thickness_map : Texture) -> Self {
+        return Self {thickness_map : Some(Arc::new(thickness_map)), ..self};
+    }
+
+    pub fn thickness(&self) -> f64 {
+        return self.thickness;
+    }
+
+    pub fn index_of_refraction(&self) -> f64 {
+        return self.index_of_refraction;
+    }
+
+    //Reflectance in RGB for light arriving at rec from a medium with index outer_ior, at cos_theta to the normal,
+    //integrated over the spectrum.
+    pub fn reflectance(&self, rec : &HitRecord, cos_theta : f64, outer_ior : f64, substrate : Substrate) -> Color3 {
+        let thickness : f64 = self.thickness_at(rec);
+        let rgb : Color3 = reflectance_to_rgb(|lambda| airy(lambda, thickness, cos_theta, outer_ior, self.index_of_refraction, substrate));
+        return Color3::new(rgb.x().clamp(0.0, 1.0), rgb.y().clamp(0.0, 1.0), rgb.z().clamp(0.0, 1.0));
+    }
+
+    //Same as reflectance, at a single wavelength in nanometres.
+    pub fn reflectance_at(&self, rec : &HitRecord, cos_theta : f64, outer_ior : f64, substrate : Substrate, lambda : f64) -> f64 {
+        return airy(lambda, self.thickness_at(rec), cos_theta, outer_ior, self.index_of_refraction, substrate);
+    }
+
+    fn thickness_at(&self, rec : &HitRecord) -> f64 {
+        return self.thickness * self.thickness_map.as_ref().map_or(1.0, |map| map.scalar(rec.u, rec.v, &rec.p).max(0.0));
+    }
+}
+
+//Reflectance of a film between an outer medium and a substrate, summing the waves bouncing inside it (Airy), averaged
+//over both polarizations. Metals are treated as a perfect phase flip with their own reflectance.
+fn airy(lambda : f64, thickness : f64, cos_outer : f64, outer_ior : f64, film_ior : f64, substrate : Substrate) -> f64 {
+    let sin2_outer : f64 = (1.0 - cos_outer * cos_outer).max(0.0);
+    let cos_film_squared : f64 = 1.0 - (outer_ior / film_ior).powi(2) * sin2_outer;
+    if cos_film_squared <= 0.0 {
+        return 1.0;
+    }
+    let cos_film : f64 = cos_film_squared.sqrt();
+
+    let (r12_s, r12_p) = fresnel_amplitudes(outer_ior, film_ior, cos_outer, cos_film);
+    let (r23_s, r23_p) = match substrate {
+        Substrate::Dielectric(substrate_ior) => {
+            let cos_substrate_squared : f64 = 1.0 - (outer_ior / substrate_ior).powi(2) * sin2_outer;
+            if cos_substrate_squared <= 0.0 {
+                return 1.0;
+            }
+            fresnel_amplitudes(film_ior, substrate_ior, cos_film, cos_substrate_squared.sqrt())
+        }
+        Substrate::Conductor(reflectance) => {
+            let amplitude : f64 = -rgb_to_reflectance(&reflectance, lambda).clamp(0.0, 1.0).sqrt();
+            (amplitude, amplitude)
+        }
+    };
+
+    let cos_phase : f64 = (4.0 * PI * film_ior * thickness * cos_film / lambda).cos();
+    let combine = |r12 : f64, r23 : f64| {
+        let cross : f64 = 2.0 * r12 * r23 * cos_phase;
+        (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+    };
+    return 0.5 * (combine(r12_s, r23_s) + combine(r12_p, r23_p));
+}
+
+//Fresnel reflection amplitudes for s and p polarized light going from index n1 into n2.
+fn fresnel_amplitudes(n1 : f64, n2 : f64, cos1 : f64, cos2 : f64) -> (f64, f64) {
+    let s : f64 = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let p : f64 = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    return (s, p);
+}
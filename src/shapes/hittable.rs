@@ -1,14 +1,22 @@
-use crate::math::vec3::{Vec3, Color3};
+use crate::math::vec3::Vec3;
 use crate::math::ray::Ray;
 use crate::math::interval::Interval;
 use crate::shapes::material::Material;
+use crate::lights::punctual::Light;
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Clone)]
 pub struct HitRecord {
     pub p : Vec3,
-    pub normal : Vec3,
+    pub normal : Vec3, //Shading normal, facing against the ray
+    pub geometric_normal : Vec3, //Normal of the actual surface, facing against the ray
     pub material : Material,
     pub t : f64,
+    pub u : f64, //Surface texture coordinates
+    pub v : f64,
+    pub tangent : Vec3, //How p changes with u, zero where the shape has no texture coordinates to follow
+    pub bitangent : Vec3, //How p changes with v
+    pub barycentrics : Option<(f64, f64)>, //Weights of a triangle's second and third vertex, None for other shapes
+    pub object_id : usize, //Index of the hit object in the world's object list
     pub front_face : bool
 }
 
@@ -16,33 +24,61 @@ pub trait Hittable : Sync + Send {
     fn hit(&self, ray : &Ray, interval : Interval, hit_record : &mut HitRecord) -> bool;
 }
 
+#[derive(Default)]
 pub struct HittableList {
-    pub objects : Vec<Box<dyn Hittable>>
+    pub objects : Vec<Box<dyn Hittable>>,
+    pub lights : Vec<Light> //Point, spot and directional lights, which have no geometry of their own
 }
 
 impl HitRecord {
     pub fn set_face_normal(&mut self, ray : &Ray, outward_normal : &Vec3){
         self.front_face = Vec3::dot(&ray.dir, outward_normal) < 0.0;
-        if(self.front_face){
+        if self.front_face {
             self.normal = *outward_normal;
         }
         else {
             self.normal = -1.0 * (*outward_normal);
         }
+        self.geometric_normal = self.normal;
+    }
+
+    //Whether direction is on the same side of the actual surface as it is of the shading normal. Interpolated and
+    //mapped normals tilt away from the surface, and directions between the two would pass through it, leaking light
+    //in or out. Records without a geometric normal take the shading normal's side.
+    pub fn agrees_with_surface(&self, direction : &Vec3) -> bool {
+        if self.geometric_normal.near_zero() {
+            return true;
+        }
+        return (Vec3::dot(direction, &self.normal) > 0.0) == (Vec3::dot(direction, &self.geometric_normal) > 0.0);
+    }
+
+    //Swaps a mapped material for the one under it with its textures looked up here, and tilts the shading normal by
+    //its maps. Worlds do this for the closest hit only, so nothing past intersection sees Material::Mapped.
+    pub fn apply_surface_maps(&mut self) {
+        if let Material::Mapped { base, maps } = &self.material {
+            let (base, maps) = (base.clone(), maps.clone());
+            maps.perturb(self);
+            self.material = maps.textured(&base, self);
+        }
     }
 }
 
 impl HittableList {
     pub fn new() -> Self {
-        Self {objects : Vec::new()}
+        return Self {objects : Vec::new(), lights : Vec::new()};
     }
 
     pub fn add(&mut self, object : Box<dyn Hittable>){
         self.objects.push(object);
     }
 
+    pub fn add_light(&mut self, light : Light){
+        self.lights.push(light);
+    }
+
     pub fn clear(&mut self){
         self.objects.clear();
+        self.lights.clear();
     }
 }
 
@@ -52,16 +88,21 @@ impl Hittable for HittableList {
         let mut hit_anything : bool = false;
         let mut closest_so_far : f64 = interval.max;
 
-        for object in self.objects.iter() {
+        for (index, object) in self.objects.iter().enumerate() {
             if object.hit(ray, Interval::new(interval.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
+                temp_rec.object_id = index;
                 
                 //Clone the hit record values 
-                *hit_record = temp_rec;
+                *hit_record = temp_rec.clone();
             }
         }
 
+        if hit_anything {
+            hit_record.apply_surface_maps();
+        }
+
         return hit_anything;
     }
 }
@@ -1,15 +1,77 @@
 use std::fmt;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 use rand::Rng;
 
-use crate::{Vec3, Color3, Ray};
-use crate::{HitRecord, Hittable, HittableList};
+use crate::math::vec3::{Vec3, Color3, Point3};
+use crate::math::ray::Ray;
+use crate::shapes::hittable::HitRecord;
+use crate::shapes::medium::{Medium, MediumStack};
+use crate::shapes::coating::{ThinFilm, Substrate};
+use crate::shapes::surface::{SurfaceMaps, AlphaMode};
+use crate::shapes::texture::Texture;
+use crate::math::spectrum::{SampledSpectrum, SampledWavelengths, SPECTRUM_SAMPLES};
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum Material {
     Lambertian {albedo : Vec3},
-    Metal {albedo : Vec3, fuzz : f64}, //Fuzz is the distortion of the reflection, clamped to [0,1]. 0 is a mirror, 1 is very rough reflection.
-    Diaelectric {index_of_refraction : f64}
+    Metal {albedo : Vec3, fuzz : f64, coating : Option<ThinFilm>}, //Fuzz is the distortion of the reflection, clamped to [0,1]. 0 is a mirror, 1 is very rough reflection.
+    Diaelectric {
+        index_of_refraction : f64, //Used in RGB, dispersion only by the spectral integrator
+        dispersion : Dispersion,
+        absorption : Color3, //Beer-Lambert absorption coefficient per unit distance travelled inside
+        thin_walled : bool, //An infinitely thin sheet with nothing inside, like a soap bubble or a window pane
+        priority : u32, //Which of overlapping dielectrics owns the space they share, see MediumStack
+        coating : Option<ThinFilm>
+    },
+    //Translucent material light travels through below the surface before coming back out, like skin, wax, marble or
+    //milk. mean_free_path is per channel in scene units, longer lets that color spread further. The path tracers
+    //trace it inside the object, which must be closed; other integrators see it as Lambertian.
+    Subsurface {albedo : Color3, mean_free_path : Color3, method : SubsurfaceMethod},
+    //Another material with normal or bump maps on top. Worlds resolve it to base when they find the closest hit, see
+    //HitRecord::apply_surface_maps, so scattering only ever sees the base material.
+    Mapped {base : Arc<Material>, maps : Arc<SurfaceMaps>}
+}
+
+//How light under a subsurface material's surface is simulated.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SubsurfaceMethod {
+    #[default]
+    RandomWalk, //Brute force scattering inside the volume, handles thin and detailed shapes
+    Diffusion //Burley's normalized diffusion profile, faster and smoother but assumes a locally flat surface
+}
+
+//How a dielectric's index of refraction changes with wavelength, seen as colored fringes by the spectral integrator.
+//Wavelengths are in micrometres in the formulas.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Dispersion {
+    #[default]
+    None,
+    Cauchy {a : f64, b : f64}, //n = a + b / lambda^2
+    Sellmeier {b : [f64; 3], c : [f64; 3]} //n^2 = 1 + sum of b * lambda^2 / (lambda^2 - c)
+}
+
+//Sellmeier coefficients of Schott N-BK7 crown glass and dense flint N-SF11.
+pub const BK7 : Dispersion = Dispersion::Sellmeier {b : [1.03961212, 0.231792344, 1.01046945], c : [0.00600069867, 0.0200179144, 103.560653]};
+pub const SF11 : Dispersion = Dispersion::Sellmeier {b : [1.73759695, 0.313747346, 1.89878101], c : [0.013188707, 0.0623068142, 155.23629]};
+
+//Wavelength of the helium d line in nanometres, where glasses quote their index of refraction.
+const D_LINE : f64 = 587.6;
+
+impl Dispersion {
+    //Index of refraction at a wavelength in nanometres, None if the material doesn't disperse.
+    pub fn ior(&self, lambda : f64) -> Option<f64> {
+        let l2 : f64 = (lambda * 1e-3) * (lambda * 1e-3);
+        match self {
+            Dispersion::None => None,
+            Dispersion::Cauchy { a, b } => Some(a + b / l2),
+            Dispersion::Sellmeier { b, c } => {
+                let n2 : f64 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                Some(n2.max(1.0).sqrt())
+            }
+        }
+    }
 }
 
 impl Default for Material {
@@ -21,9 +83,151 @@ impl Default for Material {
 }
 
 impl Material {
+    pub fn subsurface(albedo : Color3, mean_free_path : Color3) -> Self {
+        return Material::Subsurface {albedo : albedo, mean_free_path : mean_free_path, method : SubsurfaceMethod::RandomWalk};
+    }
+
+    pub fn with_subsurface_method(mut self, subsurface_method : SubsurfaceMethod) -> Self {
+        if let Material::Subsurface { method, .. } = self.base_mut() {
+            *method = subsurface_method;
+        }
+        return self;
+    }
+
+    pub fn metal(albedo : Color3, fuzz : f64) -> Self {
+        return Material::Metal {albedo : albedo, fuzz : fuzz, coating : None};
+    }
+
+    //Clear, solid dielectric.
+    pub fn dielectric(index_of_refraction : f64) -> Self {
+        return Material::Diaelectric {index_of_refraction : index_of_refraction, dispersion : Dispersion::None, absorption : Color3::default(), thin_walled : false, priority : 0, coating : None};
+    }
+
+    //Dispersive dielectric, its RGB index of refraction is the one at the d line.
+    pub fn dispersive(dispersion : Dispersion) -> Self {
+        return Material::Diaelectric {index_of_refraction : dispersion.ior(D_LINE).unwrap_or(1.5), dispersion : dispersion, absorption : Color3::default(), thin_walled : false, priority : 0, coating : None};
+    }
+
+    //Tints a dielectric so that white light comes out as color after travelling distance through it. Other
+    //materials are returned unchanged, as are the following builders.
+    pub fn with_absorption(mut self, color : Color3, distance : f64) -> Self {
+        let coefficient = |transmitted : f64| -transmitted.clamp(1e-6, 1.0).ln() / distance.max(1e-6);
+        if let Material::Diaelectric { absorption, .. } = self.base_mut() {
+            *absorption = Color3::new(coefficient(color.x()), coefficient(color.y()), coefficient(color.z()));
+        }
+        return self;
+    }
+
+    //Thin walled dielectrics don't bend light or absorb any, it passes straight through or is reflected.
+    pub fn thin_walled(mut self, thin : bool) -> Self {
+        if let Material::Diaelectric { thin_walled, .. } = self.base_mut() {
+            *thin_walled = thin;
+        }
+        return self;
+    }
+
+    //Higher priorities win where dielectrics overlap, e.g. a liquid over the glass it is in.
+    pub fn with_priority(mut self, nesting_priority : u32) -> Self {
+        if let Material::Diaelectric { priority, .. } = self.base_mut() {
+            *priority = nesting_priority;
+        }
+        return self;
+    }
+
+    //Puts a thin film on a metal or dielectric. On a thin walled dielectric the film is the wall itself, like a soap
+    //bubble, and replaces its own reflection.
+    pub fn with_coating(mut self, film : ThinFilm) -> Self {
+        if let Material::Metal { coating, .. } | Material::Diaelectric { coating, .. } = self.base_mut() {
+            *coating = Some(film);
+        }
+        return self;
+    }
+
+    //Multiplies the albedo of a Lambertian, metal or subsurface material by a texture, like a painted pattern.
+    pub fn with_albedo_map(self, albedo_map : Texture) -> Self {
+        return self.with_maps(|maps| maps.albedo_map = Some(albedo_map));
+    }
+
+    //Multiplies a metal's fuzz by a texture, for surfaces polished in some places and worn in others.
+    pub fn with_roughness_map(self, roughness_map : Texture) -> Self {
+        return self.with_maps(|maps| maps.roughness_map = Some(roughness_map));
+    }
+
+    //Tilts the shading normal by a tangent space normal map, with green pointing towards increasing v as most tools
+    //export them. strength scales the tilt, 1 uses the map as it is.
+    pub fn with_normal_map(self, normal_map : Texture, strength : f64) -> Self {
+        return self.with_maps(|maps| maps.normal_map = Some((normal_map, strength)));
+    }
+
+    //Shades the surface as if it was moved along its normal by the height map's value times scale, in scene units.
+    //Applied before a normal map if there are both.
+    pub fn with_bump_map(self, height_map : Texture, scale : f64) -> Self {
+        return self.with_maps(|maps| maps.bump_map = Some((height_map, scale)));
+    }
+
+    //Cuts holes in the surface where the opacity map is low, like the gaps around a leaf on a single quad. Shapes
+    //skip hits on the holes, so shadow rays pass through them as well.
+    pub fn with_opacity(self, opacity_map : Texture, mode : AlphaMode) -> Self {
+        return self.with_maps(|maps| maps.opacity = Some((opacity_map, mode)));
+    }
+
+    //Whether a ray crossing the surface at u, v, p goes through a hole cut by the opacity map.
+    pub fn is_cut_out(&self, u : f64, v : f64, p : &Point3) -> bool {
+        match self {
+            Material::Mapped { maps, .. } => maps.is_cut_out(u, v, p),
+            _ => false
+        }
+    }
+
+    fn with_maps<F : FnOnce(&mut SurfaceMaps)>(self, edit : F) -> Self {
+        let (base, mut maps) = match self {
+            Material::Mapped { base, maps } => (base, (*maps).clone()),
+            material => (Arc::new(material), SurfaceMaps::default())
+        };
+        edit(&mut maps);
+        return Material::Mapped {base : base, maps : Arc::new(maps)};
+    }
+
+    //The material the builders change, under any maps.
+    fn base_mut(&mut self) -> &mut Material {
+        match self {
+            Material::Mapped { base, .. } => Arc::make_mut(base),
+            material => material
+        }
+    }
+
+    //Whether the material scatters each wavelength differently, which ends the secondary wavelengths of spectral paths.
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Material::Mapped { base, .. } => base.is_dispersive(),
+            _ => matches!(self, Material::Diaelectric { dispersion, .. } if *dispersion != Dispersion::None)
+        }
+    }
+
+    //The material as light of a single wavelength in nanometres sees it, with its index of refraction at that wavelength.
+    pub fn at_wavelength(&self, lambda : f64) -> Material {
+        if let Material::Mapped { base, .. } = self {
+            return base.at_wavelength(lambda);
+        }
+        let mut material : Material = self.clone();
+        if let Material::Diaelectric { index_of_refraction, dispersion, .. } = &mut material {
+            if let Some(ior) = dispersion.ior(lambda) {
+                *index_of_refraction = ior;
+                *dispersion = Dispersion::None;
+            }
+        }
+        return material;
+    }
+
+    //Light is absorbed on the way to rec by the dielectric the ray is inside, if any, and scattered rays remember the
+    //dielectrics they are inside. Reflected and refracted directions that would cross the actual surface on the other
+    //side from the shading normal are absorbed.
     pub fn scatter(&self, ray_in : &Ray, rec : &HitRecord, attenuation : &mut Color3, scattered : &mut Ray) -> bool {
+        let transmittance : Color3 = ray_in.media.transmittance(rec.t * ray_in.dir.length());
+        let outer_ior : f64 = ray_in.media.current().map_or(1.0, |medium| medium.index_of_refraction);
+        scattered.media = ray_in.media;
         match self {
-            Material::Lambertian { albedo } => {
+            Material::Lambertian { albedo } | Material::Subsurface { albedo, .. } => {
                 let mut scatter_direction : Vec3 = rec.normal + Vec3::random_unit_vector();
                 if scatter_direction.near_zero() {
                     scatter_direction = rec.normal;
@@ -32,53 +236,200 @@ impl Material {
                 scattered.origin = rec.p;
                 scattered.dir = scatter_direction;
                 
-                *attenuation = *albedo;
-                return true;
+                *attenuation = transmittance * *albedo;
+                return rec.agrees_with_surface(&scattered.dir);
             }
 
-            Material::Metal { albedo, fuzz } => {
-                let reflected : Vec3 = Vec3::reflect(&ray_in.dir.unit_vector(), &rec.normal);
+            Material::Metal { albedo, fuzz, coating } => {
+                let unit_direction : Vec3 = ray_in.dir.unit_vector();
+                let reflected : Vec3 = Vec3::reflect(&unit_direction, &rec.normal);
                 scattered.origin = rec.p;
                 scattered.dir = reflected + fuzz.clamp(0.0, 1.0)*Vec3::random_unit_vector();
 
-                *attenuation = *albedo;
-                return Vec3::dot(&scattered.dir, &rec.normal) > 0.0;
+                let reflectance : Color3 = match coating {
+                    Some(film) => film.reflectance(rec, Vec3::dot(&unit_direction.negate(), &rec.normal).clamp(0.0, 1.0), outer_ior, Substrate::Conductor(*albedo)),
+                    None => *albedo
+                };
+                *attenuation = transmittance * reflectance;
+                return Vec3::dot(&scattered.dir, &rec.normal) > 0.0 && rec.agrees_with_surface(&scattered.dir);
             }
 
-            Material::Diaelectric { index_of_refraction } => {
-                *attenuation = Color3::new(1.0, 1.0, 1.0);
-                let refraction_ratio : f64 = if rec.front_face {1.0/index_of_refraction} else {*index_of_refraction};
+            Material::Diaelectric { index_of_refraction, absorption, thin_walled, priority, coating, .. } => {
+                *attenuation = transmittance;
+                scattered.origin = rec.p;
+                let unit_direction : Vec3 = ray_in.dir.unit_vector();
+                let cos_theta : f64 = Vec3::dot(&unit_direction.negate(), &rec.normal).min(1.0);
+                let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
+
+                //Light bounces back and forth between the two faces of a thin wall, and what gets through leaves in
+                //the direction it arrived.
+                if *thin_walled {
+                    let reflect : bool = match coating {
+                        Some(film) => choose_reflection(film.reflectance(rec, cos_theta, outer_ior, Substrate::Dielectric(outer_ior)), attenuation),
+                        None => {
+                            let single : f64 = reflectance(cos_theta, 1.0 / index_of_refraction);
+                            2.0 * single / (1.0 + single) > rng.gen::<f64>()
+                        }
+                    };
+                    scattered.dir = if reflect {Vec3::reflect(&unit_direction, &rec.normal)} else {unit_direction};
+                    return !reflect || rec.agrees_with_surface(&scattered.dir);
+                }
+
+                //Surfaces that don't change which dielectric owns the space are false interfaces inside one with a
+                //higher priority, the ray carries on through them untouched.
+                let medium : Medium = Medium {object_id : rec.object_id, priority : *priority, index_of_refraction : *index_of_refraction, absorption : *absorption};
+                let (after, iors) = dielectric_interface(ray_in, rec, medium);
+                let (outer_ior, inner_ior) = match iors {
+                    Some(iors) => iors,
+                    None => {
+                        scattered.dir = ray_in.dir;
+                        scattered.media = after;
+                        return true;
+                    }
+                };
+                let refraction_ratio : f64 = outer_ior / inner_ior;
 
                 //Before determining if we refract, we need to see if snell's law has a solution, this can be found if the ratio of
                 //the refractive indices are > 1, if they are, then we cannot have a solution and must do a pure reflection of the 
                 //surface rather than a refraction.
-                let unit_direction : Vec3 = ray_in.dir.unit_vector();
-                let cos_theta : f64 = Vec3::dot(&unit_direction.negate(), &rec.normal).min(1.0);
                 let sin_theta : f64 = (1.0 - cos_theta*cos_theta).sqrt();
-
                 let can_refract : bool = refraction_ratio * sin_theta < 1.0;
-                let mut direction : Vec3 = Vec3::default();
-                let mut rng : rand::rngs::ThreadRng = rand::thread_rng();
 
-                if can_refract && reflectance(cos_theta, refraction_ratio) > rng.gen::<f64>() {
-                    direction = Vec3::refract(&unit_direction, &rec.normal, refraction_ratio);
+                let reflect : bool = !can_refract || match coating {
+                    Some(film) => choose_reflection(film.reflectance(rec, cos_theta, outer_ior, Substrate::Dielectric(inner_ior)), attenuation),
+                    None => reflectance(cos_theta, refraction_ratio) > rng.gen::<f64>()
+                };
+
+                if reflect {
+                    scattered.dir = Vec3::reflect(&unit_direction, &rec.normal);
                 }
 
                 else {
-                    direction = Vec3::reflect(&unit_direction, &rec.normal);
+                    scattered.dir = Vec3::refract(&unit_direction, &rec.normal, refraction_ratio);
+                    scattered.media = after;
                 }
 
-                scattered.origin = rec.p;
-                scattered.dir = direction;
+                return rec.agrees_with_surface(&scattered.dir);
+            }
+
+            Material::Mapped { base, .. } => return base.scatter(ray_in, rec, attenuation, scattered)
+        }
+    }
+
+    //Spectral attenuation of a scatter off a coated material that went from ray_in to scattered, the coating's
+    //reflectance or transmittance at each wavelength in place of the RGB value scatter() gave. The direction doesn't
+    //depend on the wavelength, so spectral paths keep all of theirs. None where the coating plays no part.
+    pub fn coating_spectrum(&self, ray_in : &Ray, rec : &HitRecord, scattered : &Ray, wavelengths : &SampledWavelengths) -> Option<SampledSpectrum> {
+        let cos_theta : f64 = Vec3::dot(&ray_in.dir.unit_vector().negate(), &rec.normal).clamp(0.0, 1.0);
+        let outer_ior : f64 = ray_in.media.current().map_or(1.0, |medium| medium.index_of_refraction);
+        let (film, outer_ior, substrate, conductor) = match self {
+            Material::Metal { albedo, coating : Some(film), .. } => (film, outer_ior, Substrate::Conductor(*albedo), true),
+            Material::Diaelectric { thin_walled : true, coating : Some(film), .. } => (film, outer_ior, Substrate::Dielectric(outer_ior), false),
+            Material::Diaelectric { index_of_refraction, absorption, priority, coating : Some(film), .. } => {
+                let medium : Medium = Medium {object_id : rec.object_id, priority : *priority, index_of_refraction : *index_of_refraction, absorption : *absorption};
+                let (outer_ior, inner_ior) = dielectric_interface(ray_in, rec, medium).1?;
+                let sin_theta : f64 = (1.0 - cos_theta * cos_theta).sqrt();
+                if outer_ior / inner_ior * sin_theta >= 1.0 {
+                    return None;
+                }
+                (film, outer_ior, Substrate::Dielectric(inner_ior), false)
+            }
+            Material::Mapped { base, .. } => return base.coating_spectrum(ray_in, rec, scattered, wavelengths),
+            _ => return None
+        };
+
+        //Undo the choice scatter() made between reflection and transmission with the same RGB based probability.
+        let reflected : bool = Vec3::dot(&scattered.dir, &rec.normal) > 0.0;
+        let rgb : Color3 = film.reflectance(rec, cos_theta, outer_ior, substrate);
+        let probability : f64 = ((rgb.x() + rgb.y() + rgb.z()) / 3.0).clamp(1e-6, 1.0 - 1e-6);
+
+        let mut weights : SampledSpectrum = SampledSpectrum::default();
+        for i in 0..SPECTRUM_SAMPLES {
+            let reflectance : f64 = film.reflectance_at(rec, cos_theta, outer_ior, substrate, wavelengths.lambda(i));
+            weights[i] = if conductor {reflectance} else if reflected {reflectance / probability} else {(1.0 - reflectance) / (1.0 - probability)};
+        }
+        let transmittance : Color3 = ray_in.media.transmittance(rec.t * ray_in.dir.length());
+        return Some(SampledSpectrum::from_reflectance(&transmittance, wavelengths) * weights);
+    }
+
+    //Perfectly specular materials only scatter in a single direction, so they can't be lit by sampling lights.
+    //Fuzzed metal is treated the same way, its lobe has no density to weight light samples against.
+    pub fn is_specular(&self) -> bool {
+        match self {
+            Material::Mapped { base, .. } => base.is_specular(),
+            _ => !matches!(self, Material::Lambertian { .. } | Material::Subsurface { .. })
+        }
+    }
+
+    //Overall color the material reflects, for albedo views. Glass lets all light through, so it is white.
+    pub fn albedo(&self) -> Color3 {
+        match self {
+            Material::Lambertian { albedo } | Material::Metal { albedo, .. } | Material::Subsurface { albedo, .. } => *albedo,
+            Material::Diaelectric { .. } => Color3::new(1.0, 1.0, 1.0),
+            Material::Mapped { base, .. } => base.albedo()
+        }
+    }
 
-                return true;
+    //BSDF times the cosine of the angle to the normal, for light arriving from direction. Light from behind the actual
+    //surface can't arrive, however the shading normal is tilted.
+    pub fn eval(&self, rec : &HitRecord, direction : &Vec3) -> Color3 {
+        match self {
+            Material::Lambertian { albedo } | Material::Subsurface { albedo, .. } => {
+                let cosine : f64 = Vec3::dot(&rec.normal, &direction.unit_vector());
+                return if cosine > 0.0 && rec.agrees_with_surface(direction) {(cosine / PI) * *albedo} else {Color3::default()};
             }
+            Material::Mapped { base, .. } => base.eval(rec, direction),
+            _ => Color3::default()
+        }
+    }
+
+    //Solid angle density of scatter() producing direction.
+    pub fn pdf(&self, rec : &HitRecord, direction : &Vec3) -> f64 {
+        match self {
+            Material::Lambertian { .. } | Material::Subsurface { .. } => Vec3::dot(&rec.normal, &direction.unit_vector()).max(0.0) / PI,
+            Material::Mapped { base, .. } => base.pdf(rec, direction),
+            _ => 0.0
         }
     }
 }
 
 //Private helper functions
 
+//Stack of dielectrics a ray refracted through a dielectric's surface at rec ends up inside, and the indices of
+//refraction outside and inside the surface, None if it is a false interface. A ray leaving an object it was never
+//seen entering, like a camera ray starting inside glass, is taken to have been inside it.
+fn dielectric_interface(ray_in : &Ray, rec : &HitRecord, medium : Medium) -> (MediumStack, Option<(f64, f64)>) {
+    let mut before : MediumStack = ray_in.media;
+    if !rec.front_face && !before.contains(rec.object_id) {
+        before.push(medium);
+    }
+    let after : MediumStack = if rec.front_face {
+        let mut after : MediumStack = before;
+        after.push(medium);
+        after
+    } else {
+        before.without(rec.object_id)
+    };
+
+    let (outer, inner) = (before.current(), after.current());
+    if outer.map(|medium| medium.object_id) == inner.map(|medium| medium.object_id) {
+        return (after, None);
+    }
+    return (after, Some((outer.map_or(1.0, |medium| medium.index_of_refraction), inner.map_or(1.0, |medium| medium.index_of_refraction))));
+}
+
+//Picks reflection or transmission off a coated dielectric with probability following its reflectance, and weights
+//attenuation by the chosen side's share of each channel over that probability.
+fn choose_reflection(reflectance : Color3, attenuation : &mut Color3) -> bool {
+    let probability : f64 = ((reflectance.x() + reflectance.y() + reflectance.z()) / 3.0).clamp(0.0, 1.0);
+    if rand::thread_rng().gen::<f64>() < probability {
+        *attenuation = (1.0 / probability) * (*attenuation * reflectance);
+        return true;
+    }
+    *attenuation = (1.0 / (1.0 - probability)) * (*attenuation * (Color3::new(1.0, 1.0, 1.0) - reflectance));
+    return false;
+}
+
 //Implementation of the Schlick Approximation for Reflective surfaces
 fn reflectance(cosine : f64, ref_idx : f64) -> f64 {
     let mut r0 : f64 = (1.0 - ref_idx) / (1.0 + ref_idx);
@@ -93,13 +444,53 @@ impl fmt::Display for Material {
                 write!(f, "Albedo: [{}, {}, {}]", albedo[0], albedo[1], albedo[2])
             }
 
-            Material::Metal { albedo, fuzz } => {
+            Material::Metal { albedo, fuzz, .. } => {
                 write!(f, "Albedo: {}\nFuzz: {}", albedo, fuzz)
             }
 
+            Material::Mapped { base, .. } => base.fmt(f),
+
             _ => {
                 write!(f, "No print output designed for this material.")
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::interval::Interval;
+    use crate::shapes::hittable::{Hittable, HittableList};
+    use crate::shapes::mesh::TriangleMesh;
+
+    #[test]
+    fn tilted_normals_dont_scatter_or_gather_through_the_surface() {
+        let positions : Vec<Point3> = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        let material : Material = Material::Lambertian { albedo : Color3::new(0.8, 0.8, 0.8) }.with_normal_map(Texture::Constant(Color3::new(1.0, 0.5, 0.5)), 4.0);
+        let mesh : TriangleMesh = TriangleMesh::builder(positions, vec![[0, 1, 2], [0, 2, 3]])
+            .uvs(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)])
+            .material(material)
+            .build().unwrap();
+        let mut world : HittableList = HittableList::new();
+        world.add(Box::new(mesh));
+
+        let ray_in : Ray = Ray::new(Point3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec : HitRecord = HitRecord::default();
+        assert!(world.hit(&ray_in, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!(rec.normal.z() < 0.1, "shading normal {:?} is barely tilted", rec.normal);
+
+        for _ in 0..1000 {
+            let mut attenuation : Color3 = Color3::default();
+            let mut scattered : Ray = Ray::new(Point3::default(), Vec3::default());
+            if rec.material.scatter(&ray_in, &rec, &mut attenuation, &mut scattered) {
+                assert!(Vec3::dot(&scattered.dir, &rec.geometric_normal) > 0.0, "scattered {:?} into the surface", scattered.dir);
+            }
+        }
+
+        //Above the shading normal but below the actual surface.
+        let below : Vec3 = Vec3::new(1.0, 0.0, -0.05);
+        assert!(Vec3::dot(&below, &rec.normal) > 0.0);
+        assert!(rec.material.eval(&rec, &below).near_zero());
+        assert!(!rec.material.eval(&rec, &Vec3::new(1.0, 0.0, 0.5)).near_zero());
+    }
+}
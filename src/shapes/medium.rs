@@ -0,0 +1,68 @@
+use crate::math::vec3::Color3;
+
+//Most dielectrics a ray can be inside at once. Entering more than this ignores the innermost ones.
+const MAX_NESTING : usize = 4;
+
+//Interior of a dielectric object a ray is travelling through.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Medium {
+    pub object_id : usize, //Object whose surface the ray entered through
+    pub priority : u32,
+    pub index_of_refraction : f64,
+    pub absorption : Color3 //Absorption coefficient per unit distance for each channel
+}
+
+//Dielectrics enclosing a ray, in the order it entered them, for nested dielectrics (Schmidt and Budge 2002). Where
+//objects overlap, the space belongs to the one with the highest priority, or the one entered last on a tie. Surfaces
+//of the others inside it are false interfaces the ray passes straight through, which lets liquid be modelled
+//overlapping the glass holding it.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MediumStack {
+    media : [Medium; MAX_NESTING],
+    len : usize
+}
+
+impl MediumStack {
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    //Medium that owns the space the ray is in, None in the open air.
+    pub fn current(&self) -> Option<&Medium> {
+        let mut current : Option<&Medium> = None;
+        for medium in self.media[..self.len].iter() {
+            if current.is_none_or(|current| medium.priority >= current.priority) {
+                current = Some(medium);
+            }
+        }
+        return current;
+    }
+
+    pub fn contains(&self, object_id : usize) -> bool {
+        return self.media[..self.len].iter().any(|medium| medium.object_id == object_id);
+    }
+
+    pub fn push(&mut self, medium : Medium) {
+        if self.len < MAX_NESTING {
+            self.media[self.len] = medium;
+            self.len += 1;
+        }
+    }
+
+    //Stack after leaving the object, unchanged if the ray wasn't inside it.
+    pub fn without(&self, object_id : usize) -> MediumStack {
+        let mut remaining : MediumStack = MediumStack::default();
+        for medium in self.media[..self.len].iter().filter(|medium| medium.object_id != object_id) {
+            remaining.push(*medium);
+        }
+        return remaining;
+    }
+
+    //Fraction of light left after travelling distance through the current medium, by the Beer-Lambert law.
+    pub fn transmittance(&self, distance : f64) -> Color3 {
+        return match self.current() {
+            Some(medium) => Color3::new((-medium.absorption.x() * distance).exp(), (-medium.absorption.y() * distance).exp(), (-medium.absorption.z() * distance).exp()),
+            None => Color3::new(1.0, 1.0, 1.0)
+        };
+    }
+}
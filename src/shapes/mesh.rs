@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::fmt;
+use super::hittable::{HitRecord, Hittable};
+use super::material::Material;
+use super::texture::Texture;
+use crate::math::vec3::{Vec3, Point3};
+use crate::math::ray::Ray;
+use crate::math::interval::Interval;
+use crate::render::stats;
+
+//Most triangles a leaf of the bounding volume hierarchy holds.
+const LEAF_TRIANGLES : usize = 4;
+
+//Displacement stops subdividing before a mesh grows past this many triangles, however long its edges still are.
+const MAX_DISPLACED_TRIANGLES : usize = 1 << 22;
+
+//Indexed triangle mesh, one object in the world however many triangles it has. Built and validated by
+//TriangleMeshBuilder, which also tessellates and displaces it, and kept in a bounding volume hierarchy of its own.
+pub struct TriangleMesh {
+    positions : Vec<Point3>,
+    normals : Option<Vec<Vec3>>, //Smooth shading normals, the flat face normals are used without them
+    uvs : Option<Vec<(f64, f64)>>,
+    tangents : Option<Vec<(Vec3, f64)>>, //Direction of increasing u, and the handedness the bitangent is flipped by
+    triangles : Vec<[usize; 3]>, //Reordered to follow the hierarchy's leaves
+    material : Material,
+    nodes : Vec<BvhNode>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    NoTriangles,
+    IndexOutOfRange {triangle : usize, index : usize}, //A triangle refers to a vertex past the end of the positions
+    AttributeCount {attribute : &'static str, expected : usize, found : usize}, //Per vertex data that isn't one per position
+    InvalidDisplacement {scale : f64, max_edge_length : f64} //A scale that isn't finite, or an edge length that isn't finite and positive
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::NoTriangles => write!(f, "mesh has no triangles"),
+            MeshError::IndexOutOfRange { triangle, index } => write!(f, "triangle {} refers to missing vertex {}", triangle, index),
+            MeshError::AttributeCount { attribute, expected, found } => write!(f, "mesh has {} {} for {} vertices", found, attribute, expected),
+            MeshError::InvalidDisplacement { scale, max_edge_length } => write!(f, "invalid displacement with scale {} and maximum edge length {}", scale, max_edge_length)
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+pub struct TriangleMeshBuilder {
+    positions : Vec<Point3>,
+    triangles : Vec<[usize; 3]>,
+    normals : Option<Vec<Vec3>>,
+    uvs : Option<Vec<(f64, f64)>>,
+    tangents : Option<Vec<(Vec3, f64)>>,
+    material : Material,
+    displacement : Option<(Texture, f64, f64)> //Height map, scale and longest edge to tessellate down to
+}
+
+impl TriangleMeshBuilder {
+    pub fn normals(mut self, normals : Vec<Vec3>) -> Self {
+        self.normals = Some(normals);
+        return self;
+    }
+
+    pub fn uvs(mut self, uvs : Vec<(f64, f64)>) -> Self {
+        self.uvs = Some(uvs);
+        return self;
+    }
+
+    //Tangents pointing towards increasing u, each with a handedness of 1 or -1 the bitangent, the normal crossed with
+    //the tangent, is multiplied by. Used by normal maps in place of the tangents worked out from the texture coordinates.
+    pub fn tangents(mut self, tangents : Vec<(Vec3, f64)>) -> Self {
+        self.tangents = Some(tangents);
+        return self;
+    }
+
+    pub fn material(mut self, material : Material) -> Self {
+        self.material = material;
+        return self;
+    }
+
+    //Moves the surface along its normals by the height map's value times scale, in scene units. Triangles are split
+    //until no edge is longer than max_edge_length, so the detail is real geometry that silhouettes and shadows show.
+    //Vertices split along texture seams can pull apart where the height map doesn't match across the seam.
+    pub fn displacement(mut self, height_map : Texture, scale : f64, max_edge_length : f64) -> Self {
+        self.displacement = Some((height_map, scale, max_edge_length));
+        return self;
+    }
+
+    pub fn build(self) -> Result<TriangleMesh, MeshError> {
+        if self.triangles.is_empty() {
+            return Err(MeshError::NoTriangles);
+        }
+        let vertices : usize = self.positions.len();
+        for (triangle, indices) in self.triangles.iter().enumerate() {
+            if let Some(index) = indices.iter().find(|index| **index >= vertices) {
+                return Err(MeshError::IndexOutOfRange {triangle : triangle, index : *index});
+            }
+        }
+        check_count("normals", vertices, self.normals.as_ref().map(Vec::len))?;
+        check_count("uvs", vertices, self.uvs.as_ref().map(Vec::len))?;
+        check_count("tangents", vertices, self.tangents.as_ref().map(Vec::len))?;
+        if let Some((_, scale, max_edge_length)) = &self.displacement {
+            if !scale.is_finite() || !max_edge_length.is_finite() || *max_edge_length <= 0.0 {
+                return Err(MeshError::InvalidDisplacement {scale : *scale, max_edge_length : *max_edge_length});
+            }
+        }
+
+        let mut mesh : TriangleMesh = TriangleMesh {
+            positions : self.positions,
+            normals : self.normals,
+            uvs : self.uvs,
+            tangents : self.tangents,
+            triangles : self.triangles,
+            material : self.material,
+            nodes : Vec::new()
+        };
+        if let Some((height_map, scale, max_edge_length)) = self.displacement {
+            mesh.displace(&height_map, scale, max_edge_length);
+        }
+        mesh.build_hierarchy();
+        return Ok(mesh);
+    }
+}
+
+fn check_count(attribute : &'static str, expected : usize, found : Option<usize>) -> Result<(), MeshError> {
+    match found {
+        Some(found) if found != expected => Err(MeshError::AttributeCount {attribute : attribute, expected : expected, found : found}),
+        _ => Ok(())
+    }
+}
+
+impl TriangleMesh {
+    //Triangles are triples of indices into positions, wound counterclockwise seen from the outside.
+    pub fn builder(positions : Vec<Point3>, triangles : Vec<[usize; 3]>) -> TriangleMeshBuilder {
+        return TriangleMeshBuilder {positions : positions, triangles : triangles, normals : None, uvs : None, tangents : None, material : Material::default(), displacement : None};
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        return self.triangles.len();
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        return self.positions.len();
+    }
+
+    //Splits every triangle into four until the edges are short enough, then moves each vertex along its normal.
+    //Splitting all of them at once keeps neighbours sharing their new vertices, so the surface can't crack.
+    fn displace(&mut self, height_map : &Texture, scale : f64, max_edge_length : f64) {
+        if self.normals.is_none() {
+            self.normals = Some(smooth_normals(&self.positions, &self.triangles));
+        }
+        while self.longest_edge() > max_edge_length && self.triangles.len() * 4 <= MAX_DISPLACED_TRIANGLES {
+            self.subdivide();
+        }
+
+        let normals : &Vec<Vec3> = self.normals.as_ref().unwrap();
+        for (index, position) in self.positions.iter_mut().enumerate() {
+            let (u, v) = self.uvs.as_ref().map_or((0.0, 0.0), |uvs| uvs[index]);
+            *position += (scale * height_map.scalar(u, v, position)) * normals[index];
+        }
+
+        //The given tangents followed the surface before it was moved, the texture coordinates still do.
+        self.normals = Some(smooth_normals(&self.positions, &self.triangles));
+        self.tangents = None;
+    }
+
+    fn longest_edge(&self) -> f64 {
+        let mut longest : f64 = 0.0;
+        for [a, b, c] in &self.triangles {
+            let (a, b, c) = (self.positions[*a], self.positions[*b], self.positions[*c]);
+            longest = longest.max((b - a).length()).max((c - b).length()).max((a - c).length());
+        }
+        return longest;
+    }
+
+    //Splits each triangle into four through the midpoints of its edges, shared with the neighbour across each edge.
+    fn subdivide(&mut self) {
+        let mut midpoints : HashMap<(usize, usize), usize> = HashMap::new();
+        let mut triangles : Vec<[usize; 3]> = Vec::with_capacity(self.triangles.len() * 4);
+        for [a, b, c] in self.triangles.clone() {
+            let ab : usize = self.midpoint(a, b, &mut midpoints);
+            let bc : usize = self.midpoint(b, c, &mut midpoints);
+            let ca : usize = self.midpoint(c, a, &mut midpoints);
+            triangles.extend_from_slice(&[[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+        }
+        self.triangles = triangles;
+    }
+
+    fn midpoint(&mut self, a : usize, b : usize, midpoints : &mut HashMap<(usize, usize), usize>) -> usize {
+        let key : (usize, usize) = (a.min(b), a.max(b));
+        if let Some(index) = midpoints.get(&key) {
+            return *index;
+        }
+        self.positions.push(0.5 * (self.positions[a] + self.positions[b]));
+        if let Some(normals) = &mut self.normals {
+            let normal : Vec3 = normals[a] + normals[b];
+            normals.push(if normal.near_zero() {normals[a]} else {normal.unit_vector()});
+        }
+        if let Some(uvs) = &mut self.uvs {
+            uvs.push((0.5 * (uvs[a].0 + uvs[b].0), 0.5 * (uvs[a].1 + uvs[b].1)));
+        }
+        if let Some(tangents) = &mut self.tangents {
+            tangents.push((tangents[a].0 + tangents[b].0, tangents[a].1));
+        }
+        let index : usize = self.positions.len() - 1;
+        midpoints.insert(key, index);
+        return index;
+    }
+
+    fn build_hierarchy(&mut self) {
+        let mut nodes : Vec<BvhNode> = Vec::with_capacity(2 * self.triangles.len() / LEAF_TRIANGLES + 1);
+        let mut triangles : Vec<[usize; 3]> = std::mem::take(&mut self.triangles);
+        let count : usize = triangles.len();
+        self.build_node(&mut triangles, 0, count, &mut nodes);
+        self.triangles = triangles;
+        self.nodes = nodes;
+    }
+
+    //Adds the node over triangles[start..end], splitting them at the median of their centroids along the axis the
+    //centroids spread furthest over.
+    fn build_node(&self, triangles : &mut [[usize; 3]], start : usize, end : usize, nodes : &mut Vec<BvhNode>) {
+        let mut bounds : Aabb = Aabb::empty();
+        let mut centroids : Aabb = Aabb::empty();
+        for triangle in &triangles[start..end] {
+            for index in triangle {
+                bounds.grow(&self.positions[*index]);
+            }
+            centroids.grow(&self.centroid(triangle));
+        }
+
+        let node : usize = nodes.len();
+        nodes.push(BvhNode {bounds : bounds, start : start, count : end - start, second_child : 0});
+        if end - start <= LEAF_TRIANGLES {
+            return;
+        }
+
+        let axis : usize = centroids.longest_axis();
+        let middle : usize = (start + end) / 2;
+        triangles[start..end].select_nth_unstable_by(middle - start, |a, b| self.centroid(a)[axis].total_cmp(&self.centroid(b)[axis]));
+        self.build_node(triangles, start, middle, nodes);
+        nodes[node].second_child = nodes.len();
+        nodes[node].count = 0;
+        self.build_node(triangles, middle, end, nodes);
+    }
+
+    fn centroid(&self, triangle : &[usize; 3]) -> Point3 {
+        return (1.0 / 3.0) * (self.positions[triangle[0]] + self.positions[triangle[1]] + self.positions[triangle[2]]);
+    }
+
+    //Distance along the ray and the weights of the second and third vertex where it crosses a triangle (Moller-Trumbore).
+    fn intersect(&self, ray : &Ray, triangle : &[usize; 3], interval : &Interval) -> Option<(f64, f64, f64)> {
+        stats::count_intersection_test();
+        let p0 : Point3 = self.positions[triangle[0]];
+        let edge1 : Vec3 = self.positions[triangle[1]] - p0;
+        let edge2 : Vec3 = self.positions[triangle[2]] - p0;
+        let p : Vec3 = Vec3::cross(&ray.dir, &edge2);
+        let determinant : f64 = Vec3::dot(&edge1, &p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+
+        let inverse : f64 = 1.0 / determinant;
+        let s : Vec3 = ray.origin - p0;
+        let b1 : f64 = inverse * Vec3::dot(&s, &p);
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let q : Vec3 = Vec3::cross(&s, &edge1);
+        let b2 : f64 = inverse * Vec3::dot(&ray.dir, &q);
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t : f64 = inverse * Vec3::dot(&edge2, &q);
+        return if interval.surrounds(t) {Some((t, b1, b2))} else {None};
+    }
+
+    //Texture coordinates at a point on a triangle, the barycentrics themselves for meshes without any.
+    fn texture_coordinates(&self, triangle : &[usize; 3], b1 : f64, b2 : f64) -> (f64, f64) {
+        let uvs : &Vec<(f64, f64)> = match &self.uvs {
+            Some(uvs) => uvs,
+            None => return (b1, b2)
+        };
+        let [i0, i1, i2] = *triangle;
+        let b0 : f64 = 1.0 - b1 - b2;
+        return (b0 * uvs[i0].0 + b1 * uvs[i1].0 + b2 * uvs[i2].0, b0 * uvs[i0].1 + b1 * uvs[i1].1 + b2 * uvs[i2].1);
+    }
+
+    fn fill_record(&self, ray : &Ray, triangle : &[usize; 3], t : f64, b1 : f64, b2 : f64, hit_record : &mut HitRecord) {
+        let [i0, i1, i2] = *triangle;
+        let b0 : f64 = 1.0 - b1 - b2;
+        let edge1 : Vec3 = self.positions[i1] - self.positions[i0];
+        let edge2 : Vec3 = self.positions[i2] - self.positions[i0];
+
+        hit_record.t = t;
+        hit_record.p = ray.at(t);
+        hit_record.material = self.material.clone();
+        hit_record.barycentrics = Some((b1, b2));
+        hit_record.set_face_normal(ray, &Vec3::cross(&edge1, &edge2).unit_vector());
+
+        let shading : Option<Vec3> = self.normals.as_ref().map(|normals| b0 * normals[i0] + b1 * normals[i1] + b2 * normals[i2]).filter(|normal| !normal.near_zero());
+        if let Some(shading) = shading {
+            let shading : Vec3 = shading.unit_vector();
+            hit_record.normal = if hit_record.front_face {shading} else {shading.negate()};
+        }
+
+        //How the point moves with the texture coordinates across the triangle, which its corners' uvs pin down.
+        let (mut tangent, mut bitangent) = (Vec3::default(), Vec3::default());
+        (hit_record.u, hit_record.v) = self.texture_coordinates(triangle, b1, b2);
+        if let Some(uvs) = &self.uvs {
+            let (du1, dv1) = (uvs[i1].0 - uvs[i0].0, uvs[i1].1 - uvs[i0].1);
+            let (du2, dv2) = (uvs[i2].0 - uvs[i0].0, uvs[i2].1 - uvs[i0].1);
+            let determinant : f64 = du1 * dv2 - dv1 * du2;
+            if determinant.abs() > 1e-12 {
+                tangent = (1.0 / determinant) * (dv2 * edge1 - dv1 * edge2);
+                bitangent = (1.0 / determinant) * (du1 * edge2 - du2 * edge1);
+            }
+        }
+
+        //Given tangents are smooth across triangles, so they take over the directions, keeping the lengths.
+        if let Some(tangents) = &self.tangents {
+            let direction : Vec3 = b0 * tangents[i0].0 + b1 * tangents[i1].0 + b2 * tangents[i2].0;
+            if !direction.near_zero() {
+                let outward : Vec3 = if hit_record.front_face {hit_record.normal} else {hit_record.normal.negate()};
+                let direction : Vec3 = direction.unit_vector();
+                tangent = tangent.length().max(1.0) * direction;
+                bitangent = (bitangent.length().max(1.0) * tangents[i0].1) * Vec3::cross(&outward, &direction);
+            }
+        }
+        hit_record.tangent = tangent;
+        hit_record.bitangent = bitangent;
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray : &Ray, interval : Interval, hit_record : &mut HitRecord) -> bool {
+        let inverse_dir : Vec3 = Vec3::new(1.0 / ray.dir.x(), 1.0 / ray.dir.y(), 1.0 / ray.dir.z());
+        let mut closest : Interval = interval;
+        let mut found : Option<(usize, f64, f64)> = None;
+        let mut stack : Vec<usize> = vec![0];
+
+        while let Some(index) = stack.pop() {
+            stats::count_bvh_node_visit();
+            let node : &BvhNode = &self.nodes[index];
+            if !node.bounds.hit(ray, &inverse_dir, &closest) {
+                continue;
+            }
+            if node.count > 0 {
+                for triangle in node.start..node.start + node.count {
+                    if let Some((t, b1, b2)) = self.intersect(ray, &self.triangles[triangle], &closest) {
+                        let (u, v) = self.texture_coordinates(&self.triangles[triangle], b1, b2);
+                        if self.material.is_cut_out(u, v, &ray.at(t)) {
+                            continue;
+                        }
+                        closest.max = t;
+                        found = Some((triangle, b1, b2));
+                    }
+                }
+            }
+            else {
+                stack.push(node.second_child);
+                stack.push(index + 1);
+            }
+        }
+
+        return match found {
+            Some((triangle, b1, b2)) => {
+                self.fill_record(ray, &self.triangles[triangle], closest.max, b1, b2, hit_record);
+                true
+            }
+            None => false
+        };
+    }
+}
+
+//Area weighted vertex normals, for meshes that come without their own.
+fn smooth_normals(positions : &[Point3], triangles : &[[usize; 3]]) -> Vec<Vec3> {
+    let mut normals : Vec<Vec3> = vec![Vec3::default(); positions.len()];
+    for [a, b, c] in triangles {
+        let face : Vec3 = Vec3::cross(&(positions[*b] - positions[*a]), &(positions[*c] - positions[*a]));
+        for index in [a, b, c] {
+            normals[*index] += face;
+        }
+    }
+    return normals.into_iter().map(|normal| if normal.near_zero() {normal} else {normal.unit_vector()}).collect();
+}
+
+//Interior nodes have count 0, their first child right after them and the second at second_child. Leaves hold count
+//triangles from start.
+struct BvhNode {
+    bounds : Aabb,
+    start : usize,
+    count : usize,
+    second_child : usize
+}
+
+#[derive(Copy, Clone)]
+struct Aabb {
+    min : Point3,
+    max : Point3
+}
+
+impl Aabb {
+    fn empty() -> Self {
+        return Self {min : Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), max : Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY)};
+    }
+
+    fn grow(&mut self, p : &Point3) {
+        for axis in 0..3 {
+            self.min[axis] = self.min[axis].min(p[axis]);
+            self.max[axis] = self.max[axis].max(p[axis]);
+        }
+    }
+
+    fn longest_axis(&self) -> usize {
+        let extent : Vec3 = self.max - self.min;
+        if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            return 0;
+        }
+        return if extent.y() >= extent.z() {1} else {2};
+    }
+
+    //Slab test, whether the ray passes through the box within the interval.
+    fn hit(&self, ray : &Ray, inverse_dir : &Vec3, interval : &Interval) -> bool {
+        let (mut near, mut far) = (interval.min, interval.max);
+        for axis in 0..3 {
+            let t0 : f64 = (self.min[axis] - ray.origin[axis]) * inverse_dir[axis];
+            let t1 : f64 = (self.max[axis] - ray.origin[axis]) * inverse_dir[axis];
+            let (t0, t1) = if t0 <= t1 {(t0, t1)} else {(t1, t0)};
+            near = if t0 > near {t0} else {near};
+            far = if t1 < far {t1} else {far};
+            if far < near {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    fn quad() -> TriangleMeshBuilder {
+        let positions : Vec<Point3> = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        return TriangleMesh::builder(positions, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn builder_rejects_invalid_meshes() {
+        assert_eq!(TriangleMesh::builder(vec![Point3::default()], Vec::new()).build().err(), Some(MeshError::NoTriangles));
+
+        let positions : Vec<Point3> = vec![Point3::default(); 3];
+        assert_eq!(TriangleMesh::builder(positions, vec![[0, 1, 2], [1, 2, 3]]).build().err(), Some(MeshError::IndexOutOfRange {triangle : 1, index : 3}));
+
+        assert_eq!(quad().normals(vec![Vec3::new(0.0, 0.0, 1.0); 3]).build().err(), Some(MeshError::AttributeCount {attribute : "normals", expected : 4, found : 3}));
+        assert_eq!(quad().uvs(vec![(0.0, 0.0); 5]).build().err(), Some(MeshError::AttributeCount {attribute : "uvs", expected : 4, found : 5}));
+        assert_eq!(quad().tangents(Vec::new()).build().err(), Some(MeshError::AttributeCount {attribute : "tangents", expected : 4, found : 0}));
+    }
+
+    #[test]
+    fn builder_rejects_invalid_displacement() {
+        for (scale, max_edge_length) in [(1.0, 0.0), (1.0, -0.5), (1.0, f64::INFINITY), (1.0, f64::NAN), (f64::NAN, 0.1), (f64::INFINITY, 0.1)] {
+            match quad().displacement(Texture::constant(1.0), scale, max_edge_length).build() {
+                Err(MeshError::InvalidDisplacement { .. }) => {}
+                other => panic!("scale {} and edge length {} gave {:?}", scale, max_edge_length, other.map(|mesh| mesh.triangle_count()))
+            }
+        }
+    }
+
+    #[test]
+    fn displacement_splits_edges_below_the_limit() {
+        let mesh : TriangleMesh = quad().displacement(Texture::constant(0.0), 1.0, 0.2).build().unwrap();
+        assert!(mesh.longest_edge() <= 0.2);
+        assert_eq!(mesh.triangle_count(), 2 * 4usize.pow(3));
+    }
+
+    #[test]
+    fn hierarchy_finds_the_same_hits_as_brute_force() {
+        let mut rng : StdRng = StdRng::seed_from_u64(3);
+        let random_point = |rng : &mut StdRng| Point3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        let mut positions : Vec<Point3> = Vec::new();
+        let mut triangles : Vec<[usize; 3]> = Vec::new();
+        for i in 0..300 {
+            let corner : Point3 = random_point(&mut rng);
+            positions.extend_from_slice(&[corner, corner + 0.2 * random_point(&mut rng), corner + 0.2 * random_point(&mut rng)]);
+            triangles.push([3 * i, 3 * i + 1, 3 * i + 2]);
+        }
+        let mesh : TriangleMesh = TriangleMesh::builder(positions, triangles).build().unwrap();
+
+        let mut hits : usize = 0;
+        for _ in 0..500 {
+            let origin : Point3 = 2.0 * random_point(&mut rng);
+            let ray : Ray = Ray::new(origin, random_point(&mut rng) - origin);
+            let interval : Interval = Interval::new(0.001, f64::INFINITY);
+
+            let closest : Option<f64> = mesh.triangles.iter()
+                .filter_map(|triangle| mesh.intersect(&ray, triangle, &interval).map(|(t, _, _)| t))
+                .min_by(f64::total_cmp);
+            let mut rec : HitRecord = HitRecord::default();
+            let hit : bool = mesh.hit(&ray, interval, &mut rec);
+
+            assert_eq!(hit, closest.is_some());
+            if let Some(t) = closest {
+                assert!((rec.t - t).abs() < 1e-9, "hierarchy hit at {} instead of {}", rec.t, t);
+                hits += 1;
+            }
+        }
+        assert!(hits > 50, "only {} rays hit anything", hits);
+    }
+}
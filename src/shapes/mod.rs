@@ -1,3 +1,8 @@
 pub mod hittable;
 pub mod sphere;
-pub mod material;
\ No newline at end of file
+pub mod material;
+pub mod medium;
+pub mod texture;
+pub mod coating;
+pub mod surface;
+pub mod mesh;
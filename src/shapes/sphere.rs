@@ -4,8 +4,10 @@ use super::material::{Material};
 use crate::math::vec3::{Vec3, Color3};
 use crate::math::ray::Ray;
 use crate::math::interval::Interval;
+use crate::render::stats;
+use std::f64::consts::PI;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Sphere {
     pub center : Vec3,
     pub radius : f64,
@@ -26,35 +28,59 @@ impl Default for Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, ray : &Ray, interval : Interval, hit_record : &mut HitRecord) -> bool {
+        stats::count_intersection_test();
         let oc : Vec3 = ray.origin - self.center;
         let a : f64 = ray.dir.length_squared();
         let half_b : f64 = Vec3::dot(&oc, &ray.dir);
         let c : f64 = oc.length_squared() - self.radius*self.radius;
         let discriminant : f64 = half_b*half_b - a*c;
 
-        if(discriminant < 0.0){
+        if discriminant < 0.0 {
             return false;
         }
         
-        //Find the nearest root that lies in a close enough range of the position
+        //Find the nearest root that lies in a close enough range of the position, and isn't in a cut out hole
         let sqrtd : f64 = discriminant.sqrt();
-        let mut root : f64 = (-half_b - sqrtd) / a;
-
-        if(!interval.surrounds(root)){
-            root = (-half_b + sqrtd) / a;
-            if(!interval.surrounds(root)){
-                return false;
+        for root in [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a] {
+            if !interval.surrounds(root) {
+                continue;
+            }
+            let p : Vec3 = ray.at(root);
+            let outward_normal : Vec3 = (p - self.center) / self.radius;
+            let (u, v) = sphere_uv(&outward_normal);
+            if self.material.is_cut_out(u, v, &p) {
+                continue;
             }
-        }
 
-        hit_record.t = root;
-        hit_record.p = ray.at(hit_record.t);
-        hit_record.material = self.material;
-        let outward_normal : Vec3 = (hit_record.p - self.center) / self.radius;
-        hit_record.set_face_normal(ray, &outward_normal);
+            hit_record.t = root;
+            hit_record.p = p;
+            hit_record.material = self.material.clone();
+            hit_record.set_face_normal(ray, &outward_normal);
+            (hit_record.u, hit_record.v) = (u, v);
+            (hit_record.tangent, hit_record.bitangent) = sphere_derivatives(&outward_normal, self.radius);
+            hit_record.barycentrics = None;
+            return true;
+        }
 
-        return true;
+        return false;
+    }
+}
 
+//Longitude and latitude of a point on the unit sphere, both in [0,1]. u goes around the y axis starting from -x,
+//v from the bottom pole to the top.
+fn sphere_uv(p : &Vec3) -> (f64, f64) {
+    let theta : f64 = (-p.y()).clamp(-1.0, 1.0).acos();
+    let phi : f64 = (-p.z()).atan2(p.x()) + PI;
+    return (phi / (2.0 * PI), theta / PI);
+}
 
+//How a point on the sphere moves with u and v, from its outward normal. Both vanish at the poles.
+fn sphere_derivatives(n : &Vec3, radius : f64) -> (Vec3, Vec3) {
+    let ring : f64 = (n.x() * n.x() + n.z() * n.z()).sqrt();
+    if ring < 1e-9 {
+        return (Vec3::default(), Vec3::default());
     }
-} 
\ No newline at end of file
+    let tangent : Vec3 = (2.0 * PI * radius) * Vec3::new(n.z(), 0.0, -n.x());
+    let bitangent : Vec3 = (PI * radius) * Vec3::new(-n.x() * n.y() / ring, ring, -n.y() * n.z() / ring);
+    return (tangent, bitangent);
+}
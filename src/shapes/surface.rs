@@ -0,0 +1,114 @@
+use rand::Rng;
+use crate::math::vec3::{Vec3, Point3, Color3};
+use crate::shapes::hittable::HitRecord;
+use crate::shapes::material::Material;
+use crate::shapes::texture::Texture;
+
+//Step in texture coordinates the slope of a bump map is measured over.
+const BUMP_DELTA : f64 = 0.0005;
+
+//Textures painted onto a surface, varying its material's parameters and tilting its shading normal without changing
+//its shape.
+#[derive(Clone, Debug, Default)]
+pub struct SurfaceMaps {
+    pub(crate) albedo_map : Option<Texture>, //Multiplies the albedo
+    pub(crate) roughness_map : Option<Texture>, //Multiplies a metal's fuzz
+    pub(crate) normal_map : Option<(Texture, f64)>, //Tangent space normals stored as colors, and how strongly they tilt the surface
+    pub(crate) bump_map : Option<(Texture, f64)>, //Heights, and the distance in scene units a height of one moves the surface by
+    pub(crate) opacity : Option<(Texture, AlphaMode)> //Cut outs, 0 where the surface isn't there and 1 where it is
+}
+
+//How an opacity map decides where a surface is cut out.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AlphaMode {
+    Mask {cutoff : f64}, //Solid where the opacity is at least cutoff, and not there at all elsewhere
+    Stochastic //Rays go through with a probability of one minus the opacity, so partly opaque parts blend on average
+}
+
+impl SurfaceMaps {
+    //Replaces rec's shading normal with the mapped one. Maps are applied to the outward facing side, and the result is
+    //turned back to face the ray like the normal it replaces. The geometric normal is left alone.
+    pub(crate) fn perturb(&self, rec : &mut HitRecord) {
+        let mut normal : Vec3 = if rec.front_face {rec.normal} else {rec.normal.negate()};
+        if let Some((height_map, scale)) = &self.bump_map {
+            normal = bump(rec, &normal, height_map, *scale);
+        }
+        if let Some((normal_map, strength)) = &self.normal_map {
+            let color : Vec3 = normal_map.value(rec.u, rec.v, &rec.p);
+            let (tangent, bitangent) = tangent_frame(rec, &normal);
+            let mapped : Vec3 = ((2.0 * color.x() - 1.0) * strength) * tangent + ((2.0 * color.y() - 1.0) * strength) * bitangent + (2.0 * color.z() - 1.0).max(1e-3) * normal;
+            if !mapped.near_zero() {
+                normal = mapped.unit_vector();
+            }
+        }
+        rec.normal = if rec.front_face {normal} else {normal.negate()};
+    }
+
+    //The base material with its parameters scaled by the textures at rec.
+    pub(crate) fn textured(&self, base : &Material, rec : &HitRecord) -> Material {
+        let mut material : Material = base.clone();
+        if let Some(albedo_map) = &self.albedo_map {
+            let color : Color3 = albedo_map.value(rec.u, rec.v, &rec.p);
+            if let Material::Lambertian { albedo } | Material::Metal { albedo, .. } | Material::Subsurface { albedo, .. } = &mut material {
+                *albedo = *albedo * color;
+            }
+        }
+        if let (Some(roughness_map), Material::Metal { fuzz, .. }) = (&self.roughness_map, &mut material) {
+            *fuzz *= roughness_map.scalar(rec.u, rec.v, &rec.p);
+        }
+        return material;
+    }
+
+    pub(crate) fn is_cut_out(&self, u : f64, v : f64, p : &Point3) -> bool {
+        let (opacity_map, mode) = match &self.opacity {
+            Some(opacity) => opacity,
+            None => return false
+        };
+        let opacity : f64 = opacity_map.scalar(u, v, p);
+        match mode {
+            AlphaMode::Mask { cutoff } => return opacity < *cutoff,
+            AlphaMode::Stochastic => return opacity < 1.0 && rand::thread_rng().gen::<f64>() >= opacity
+        }
+    }
+}
+
+//Normal of the surface moved along normal by the height map, from the slopes of the height in u and v.
+fn bump(rec : &HitRecord, normal : &Vec3, height_map : &Texture, scale : f64) -> Vec3 {
+    let (tangent, bitangent) = surface_derivatives(rec, normal);
+    let height = |du : f64, dv : f64| height_map.scalar(rec.u + du, rec.v + dv, &(rec.p + du * tangent + dv * bitangent));
+    let base : f64 = height(0.0, 0.0);
+    let slope_u : f64 = scale * (height(BUMP_DELTA, 0.0) - base) / BUMP_DELTA;
+    let slope_v : f64 = scale * (height(0.0, BUMP_DELTA) - base) / BUMP_DELTA;
+
+    let bumped : Vec3 = Vec3::cross(&(tangent + slope_u * *normal), &(bitangent + slope_v * *normal));
+    if bumped.near_zero() {
+        return *normal;
+    }
+    //Mirrored texture coordinates give a left handed frame, whose cross product points into the surface.
+    let bumped : Vec3 = bumped.unit_vector();
+    return if Vec3::dot(&bumped, normal) < 0.0 {bumped.negate()} else {bumped};
+}
+
+//How the point moves with u and v, laid flat onto the plane of normal. Shapes without texture coordinate derivatives
+//get an arbitrary frame of unit length.
+fn surface_derivatives(rec : &HitRecord, normal : &Vec3) -> (Vec3, Vec3) {
+    let flatten = |v : &Vec3| *v - Vec3::dot(v, normal) * *normal;
+    let (tangent, bitangent) = (flatten(&rec.tangent), flatten(&rec.bitangent));
+    if tangent.near_zero() || bitangent.near_zero() {
+        return tangent_frame(rec, normal);
+    }
+    return (tangent, bitangent);
+}
+
+//Orthonormal tangent and bitangent around normal, following the directions of increasing u and v where the shape
+//provides them.
+fn tangent_frame(rec : &HitRecord, normal : &Vec3) -> (Vec3, Vec3) {
+    let mut tangent : Vec3 = rec.tangent - Vec3::dot(&rec.tangent, normal) * *normal;
+    if tangent.near_zero() {
+        let helper : Vec3 = if normal.x().abs() > 0.9 {Vec3::new(0.0, 1.0, 0.0)} else {Vec3::new(1.0, 0.0, 0.0)};
+        tangent = Vec3::cross(&helper, normal);
+    }
+    let tangent : Vec3 = tangent.unit_vector();
+    let bitangent : Vec3 = Vec3::cross(normal, &tangent);
+    return if Vec3::dot(&bitangent, &rec.bitangent) < 0.0 {(tangent, bitangent.negate())} else {(tangent, bitangent)};
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+use std::sync::Arc;
+use crate::math::vec3::{Point3, Color3};
+
+//Value that varies over a surface, looked up with the texture coordinates and position of a hit. Scalar parameters
+//read the red channel.
+#[derive(Clone, Debug)]
+pub enum Texture {
+    Constant(Color3),
+    Checker {even : Color3, odd : Color3, scale : f64}, //Solid 3D checkerboard in world space, scale is the size of a cell
+    Image(Arc<ImageTexture>) //Shared, so materials using it stay cheap to clone
+}
+
+impl Texture {
+    pub fn constant(value : f64) -> Self {
+        return Texture::Constant(Color3::new(value, value, value));
+    }
+
+    pub fn image(image : ImageTexture) -> Self {
+        return Texture::Image(Arc::new(image));
+    }
+
+    pub fn value(&self, u : f64, v : f64, p : &Point3) -> Color3 {
+        match self {
+            Texture::Constant(value) => *value,
+            Texture::Checker { even, odd, scale } => {
+                let cell = |x : f64| (x / scale.max(1e-9)).floor() as i64;
+                if (cell(p.x()) + cell(p.y()) + cell(p.z())).rem_euclid(2) == 0 {*even} else {*odd}
+            }
+            Texture::Image(image) => image.sample(u, v)
+        }
+    }
+
+    pub fn scalar(&self, u : f64, v : f64, p : &Point3) -> f64 {
+        return self.value(u, v, p).x();
+    }
+}
+
+//Image looked up with bilinear filtering, repeating outside [0, 1]. v = 0 is the bottom row.
+#[derive(Clone, Debug)]
+pub struct ImageTexture {
+    width : usize,
+    height : usize,
+    texels : Vec<Color3>
+}
+
+impl ImageTexture {
+    //Texels are linear values, given row by row from the top left. Returns None if the image is empty or there isn't one
+    //texel per pixel.
+    pub fn new(width : usize, height : usize, texels : Vec<Color3>) -> Option<Self> {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            return None;
+        }
+        return Some(Self {width : width, height : height, texels : texels});
+    }
+
+    //Loads any format the image crate can decode. Color images are stored in sRGB and converted to linear, data such
+    //as normals, roughness or thickness is already linear and should be loaded with srgb false.
+    pub fn load<P : AsRef<Path>>(path : P, srgb : bool) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.to_rgb32f();
+        let decode = |c : f32| if srgb {srgb_to_linear(c as f64)} else {c as f64};
+        let texels : Vec<Color3> = image.pixels().map(|p| Color3::new(decode(p.0[0]), decode(p.0[1]), decode(p.0[2]))).collect();
+        return ImageTexture::new(image.width() as usize, image.height() as usize, texels).ok_or_else(|| {
+            image::ImageError::Parameter(image::error::ParameterError::from_kind(image::error::ParameterErrorKind::DimensionMismatch))
+        });
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    pub fn sample(&self, u : f64, v : f64) -> Color3 {
+        let x : f64 = u.rem_euclid(1.0) * self.width as f64 - 0.5;
+        let y : f64 = (1.0 - v.rem_euclid(1.0)) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |column : f64, row : f64| {
+            let column : usize = (column as i64).rem_euclid(self.width as i64) as usize;
+            let row : usize = (row as i64).rem_euclid(self.height as i64) as usize;
+            self.texels[row * self.width + column]
+        };
+        let top : Color3 = (1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1.0, y0);
+        let bottom : Color3 = (1.0 - fx) * texel(x0, y0 + 1.0) + fx * texel(x0 + 1.0, y0 + 1.0);
+        return (1.0 - fy) * top + fy * bottom;
+    }
+}
+
+pub(crate) fn srgb_to_linear(c : f64) -> f64 {
+    return if c <= 0.04045 {c / 12.92} else {((c + 0.055) / 1.055).powf(2.4)};
+}